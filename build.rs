@@ -0,0 +1,34 @@
+// 启用 grpc feature 时生成 gRPC 服务代码
+// 消息类型在 src/grpc.rs 中手写 (与 proto/img_server.proto 对应)，构建时不需要 protoc
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        use tonic_build::manual::{Builder, Method, Service};
+
+        let method = |name: &str, route: &str, input: &str, output: &str| {
+            Method::builder()
+                .name(name)
+                .route_name(route)
+                .input_type(format!("crate::grpc::{}", input))
+                .output_type(format!("crate::grpc::{}", output))
+                .codec_path("tonic_prost::ProstCodec")
+        };
+        let service = Service::builder()
+            .name("ImageStore")
+            .package("img_server")
+            .method(
+                method("upload", "Upload", "UploadRequest", "ImageInfo")
+                    .client_streaming()
+                    .build(),
+            )
+            .method(
+                method("download", "Download", "DownloadRequest", "DownloadChunk")
+                    .server_streaming()
+                    .build(),
+            )
+            .method(method("list", "List", "ListRequest", "ListResponse").build())
+            .method(method("delete", "Delete", "DeleteRequest", "DeleteResponse").build())
+            .build();
+        Builder::new().build_client(false).compile(&[service]);
+    }
+}
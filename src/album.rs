@@ -0,0 +1,259 @@
+// 相册：album 字段相同的图片
+//
+// 管理员可以为相册签发只读 token (POST /albums/{album}/tokens)，持有者可以通过 ?token= 读取相册中的私有图片，
+// 但不能读取相册外的图片，也不能进行任何修改
+// GET /albums/{album}/embed 返回可以放进 iframe 的缩略图条带，每张缩略图链接到原图
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use log::{error, info};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Deserialize;
+
+use crate::{
+    config::{AlbumToken, AppState},
+    handler::{can_read, check_ip, check_token},
+    id::random_string,
+};
+
+// 放入 URL 路径段或查询参数时需要编码的字符
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+pub(crate) fn encode(text: &str) -> String {
+    utf8_percent_encode(text, COMPONENT).to_string()
+}
+
+pub(crate) fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 签发相册的只读 token
+#[derive(Deserialize)]
+pub struct AlbumTokenParams {
+    label: Option<String>,
+    // 有效期 (秒)，缺省永久有效
+    expires_in: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/albums/{album}/tokens",
+    summary = "Create a read-only token for an album",
+    params(
+        ("album" = String, Path),
+        ("label" = Option<String>, Query),
+        (
+            "expires_in" = Option<i64>,
+            Query,
+            description = "Lifetime in seconds (never expires if omitted)",
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Token",
+            body = Object,
+            example = json!({"token": "ALBUM_TOKEN", "album": "trip", "expires_at": null, "embed_url": "/albums/trip/embed?token=ALBUM_TOKEN"}),
+        ),
+        (status = 400, description = "Empty album"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn create_album_token(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(album): Path<String>,
+    Query(params): Query<AlbumTokenParams>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("create_album_token").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    // 与 PATCH 设置相册时一样去除首尾空白
+    let album = album.trim().to_string();
+    if album.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Empty album".to_string()));
+    }
+    let mut config = state.write_config("create_album_token").await;
+
+    let now = chrono::Utc::now();
+    let expires_at = params
+        .expires_in
+        .map(|secs| now + chrono::Duration::seconds(secs.max(1)));
+    // 顺便清理已过期的 token
+    config
+        .album_tokens
+        .retain(|_, t| t.expires_at.is_none_or(|e| e > now));
+
+    let album_token = random_string(32);
+    config.album_tokens.insert(
+        album_token.clone(),
+        AlbumToken {
+            album: album.clone(),
+            label: params.label,
+            created_at: now,
+            expires_at,
+        },
+    );
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!(
+        "addr: {:?}, action: album_token_create, album: {:?}",
+        addr, album
+    );
+
+    Ok(Json(serde_json::json!({
+        "token": album_token,
+        "album": album,
+        "expires_at": expires_at,
+        "embed_url": format!("/albums/{}/embed?token={}", encode(&album), album_token),
+    })))
+}
+
+// 撤销相册的只读 token
+#[utoipa::path(
+    delete,
+    path = "/albums/{album}/tokens/{token}",
+    summary = "Revoke an album token",
+    params(
+        ("album" = String, Path),
+        ("token" = String, Path),
+    ),
+    responses(
+        (status = 204, description = "Revoked"),
+        (status = 404, description = "Token not found"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn revoke_album_token(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path((album, album_token)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("revoke_album_token").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    let mut config = state.write_config("revoke_album_token").await;
+
+    let album = album.trim();
+    if config
+        .album_tokens
+        .get(&album_token)
+        .is_none_or(|t| t.album != album)
+    {
+        return Err((StatusCode::NOT_FOUND, "Token not found".to_string()));
+    }
+    config.album_tokens.remove(&album_token);
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!(
+        "addr: {:?}, action: album_token_revoke, album: {:?}",
+        addr, album
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize)]
+pub struct EmbedParams {
+    // 相册 token，提供时包含相册中的私有图片
+    token: Option<String>,
+}
+
+// 相册的缩略图条带，最新上传的在前；用于 iframe 嵌入，只包含请求可以读取的图片
+#[utoipa::path(
+    get,
+    path = "/albums/{album}/embed",
+    summary = "Embeddable thumbnail strip of an album",
+    description = "HTML page for an iframe, newest first; each thumbnail links to the full image. Private images are included only with a valid album token.",
+    params(
+        ("album" = String, Path),
+        (
+            "token" = Option<String>,
+            Query,
+            description = "Album token granting read access to private images in that album",
+        ),
+    ),
+    responses(
+        (status = 200, description = "HTML page", body = String, content_type = "text/html"),
+        (status = 403, description = "IP blocked"),
+    ),
+)]
+pub async fn album_embed(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(album): Path<String>,
+    Query(params): Query<EmbedParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let config = state.read_config("album_embed").await;
+    check_ip(&config, &addr)?;
+
+    let album = album.trim();
+    let token = params.token.as_deref();
+    let items: String = config
+        .images()
+        .rev()
+        .filter(|i| i.album.as_deref() == Some(album))
+        .filter(|i| can_read(&config, &headers, token, i))
+        .map(|i| {
+            let url = format!("/images/{}", encode(&i.name));
+            // 私有图片的链接需要带上 token，公开图片的链接可以直接分享
+            let token = match (token, i.is_public()) {
+                (Some(t), false) => format!("token={}", encode(t)),
+                _ => String::new(),
+            };
+            let (link, thumb) = match token.is_empty() {
+                true => (url.clone(), format!("{}?thumb=true", url)),
+                false => (format!("{}?{}", url, token), format!("{}?thumb=true&{}", url, token)),
+            };
+            format!(
+                "<a href=\"{}\" target=\"_blank\" rel=\"noopener\"><img src=\"{}\" alt=\"{}\" title=\"{}\" loading=\"lazy\"></a>\n",
+                escape_html(&link),
+                escape_html(&thumb),
+                escape_html(&i.desc),
+                escape_html(&i.name),
+            )
+        })
+        .collect();
+
+    info!("addr: {:?}, action: album_embed, album: {:?}", addr, album);
+
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title>\n\
+         <style>body{{margin:0}}.strip{{display:flex;gap:4px;overflow-x:auto;padding:4px}}\
+         .strip img{{height:150px;display:block}}</style></head>\n\
+         <body>\n<div class=\"strip\">\n{}</div>\n</body>\n</html>\n",
+        escape_html(album),
+        items
+    ))
+    .into_response())
+}
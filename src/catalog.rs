@@ -0,0 +1,339 @@
+// 图片记录的持久化：与 config.toml 分开，存放在 <data_dir>/images.jsonl
+//
+// 文件是只追加的日志，每行一条 JSON：
+//   {"put": {"id": 3, ...}}  新增或替换该 id 的记录
+//   {"delete": 3}            删除记录
+// id 在记录加入时分配且不再改变 (改名不影响)，记录按 id 排列，即加入的顺序
+// 保存时只追加修改过的记录 (见 Changes)；日志中失效的行过多时整体重写为每条记录一行 (先写临时文件再重命名)
+// 旧版本的日志按名称标识记录 (put 中没有 id，delete 的值为名称)，加载时按出现顺序分配 id 并重写
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::ImageMeta;
+
+pub const FILE_NAME: &str = "images.jsonl";
+
+// 失效的行超过该数量且超过有效记录数时重写日志
+const COMPACT_MIN_STALE: usize = 1000;
+
+// 按 id 排列的图片记录
+pub type Records = BTreeMap<u64, ImageMeta>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Put(Box<Record>),
+    Delete(Key),
+}
+
+#[derive(Deserialize)]
+struct Record {
+    // 旧版本的日志没有 id
+    #[serde(default)]
+    id: Option<u64>,
+    #[serde(flatten)]
+    meta: ImageMeta,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Key {
+    Id(u64),
+    Name(String),
+}
+
+// 写入时使用的借用版本，避免复制记录
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EntryRef<'a> {
+    Put(RecordRef<'a>),
+    Delete(u64),
+}
+
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    id: u64,
+    #[serde(flatten)]
+    meta: &'a ImageMeta,
+}
+
+// 自上次保存后修改过 (新增、修改或删除) 的记录 id
+// 保存在配置读锁下进行 (见 AppState::flush)，因此取出时需要内部可变
+#[derive(Debug, Default)]
+pub struct Changes(Mutex<BTreeSet<u64>>);
+
+impl Changes {
+    pub fn mark(&mut self, id: u64) {
+        self.0.get_mut().unwrap().insert(id);
+    }
+
+    pub fn take(&self) -> BTreeSet<u64> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+
+    // 保存失败时放回，下次重试
+    pub fn restore(&self, ids: BTreeSet<u64>) {
+        self.0.lock().unwrap().extend(ids);
+    }
+}
+
+// 日志的总行数，用于判断何时重写
+static LINES: LazyLock<Mutex<HashMap<PathBuf, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// 读取并回放日志，不影响之后的增量写入；文件不存在时返回 None
+pub fn read(path: &Path) -> anyhow::Result<Option<Records>> {
+    Ok(replay(path)?.map(|replayed| replayed.records))
+}
+
+// 加载图片记录，并记录日志的行数作为之后追加的基准
+pub fn load(path: &Path) -> anyhow::Result<Option<Records>> {
+    let Some(replayed) = replay(path)? else {
+        return Ok(None);
+    };
+    // 不完整的行之后不能继续追加，旧格式的日志不能与新格式混合，都立即重写
+    match replayed.truncated || replayed.legacy {
+        true => write(path, &replayed.records)?,
+        false => {
+            LINES
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), replayed.lines);
+        }
+    }
+    Ok(Some(replayed.records))
+}
+
+struct Replayed {
+    records: Records,
+    lines: usize,
+    // 最后一行不完整 (写入中断，忽略该行)
+    truncated: bool,
+    // 包含旧格式 (按名称标识) 的行
+    legacy: bool,
+}
+
+fn replay(path: &Path) -> anyhow::Result<Option<Replayed>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut records = Records::new();
+    // 旧格式的行按名称对应到 id
+    let mut names: HashMap<String, u64> = HashMap::new();
+    let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut truncated = false;
+    let mut legacy = false;
+    for (i, line) in lines.iter().enumerate() {
+        let entry = match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => entry,
+            Err(_) if i + 1 == lines.len() && !content.ends_with('\n') => {
+                log::warn!("Ignoring incomplete last line of {:?}", path);
+                truncated = true;
+                break;
+            }
+            Err(e) => anyhow::bail!("{:?} line {}: {}", path, i + 1, e),
+        };
+        match entry {
+            Entry::Put(record) => {
+                let id = match record.id {
+                    Some(id) => id,
+                    None => {
+                        legacy = true;
+                        let next = records.last_key_value().map_or(1, |(id, _)| id + 1);
+                        *names.entry(record.meta.name.clone()).or_insert(next)
+                    }
+                };
+                records.insert(id, record.meta);
+            }
+            Entry::Delete(Key::Id(id)) => {
+                records.remove(&id);
+            }
+            Entry::Delete(Key::Name(name)) => {
+                legacy = true;
+                if let Some(id) = names.remove(&name) {
+                    records.remove(&id);
+                }
+            }
+        }
+    }
+    Ok(Some(Replayed {
+        records,
+        lines: lines.len(),
+        truncated,
+        legacy,
+    }))
+}
+
+// 保存图片记录：追加 changed 中记录的当前状态 (已不存在的记为删除)，失效的行过多时重写整个日志
+pub fn save(path: &Path, records: &Records, changed: &BTreeSet<u64>) -> anyhow::Result<()> {
+    let mut all_lines = LINES.lock().unwrap();
+    let Some(&lines) = all_lines.get(path) else {
+        drop(all_lines);
+        return write(path, records);
+    };
+    let mut buf = String::new();
+    for &id in changed {
+        let entry = match records.get(&id) {
+            Some(meta) => EntryRef::Put(RecordRef { id, meta }),
+            None => EntryRef::Delete(id),
+        };
+        buf.push_str(&serde_json::to_string(&entry)?);
+        buf.push('\n');
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(buf.as_bytes())?;
+    let lines = lines + changed.len();
+    all_lines.insert(path.to_path_buf(), lines);
+
+    let stale = lines.saturating_sub(records.len());
+    if stale >= COMPACT_MIN_STALE.max(records.len()) {
+        drop(all_lines);
+        write(path, records)?;
+    }
+    Ok(())
+}
+
+// 重写为每条记录一行
+pub fn write(path: &Path, records: &Records) -> anyhow::Result<()> {
+    let temp = path.with_extension("jsonl.tmp");
+    let mut file = std::io::BufWriter::new(fs::File::create(&temp)?);
+    for (&id, meta) in records {
+        serde_json::to_writer(&mut file, &EntryRef::Put(RecordRef { id, meta }))?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(&temp, path)?;
+    LINES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), records.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("img-server-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn meta(name: &str, desc: &str) -> ImageMeta {
+        serde_json::from_value(serde_json::json!({"name": name, "desc": desc, "hash": name}))
+            .unwrap()
+    }
+
+    fn line_count(path: &Path) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    fn summary(records: &Records) -> Vec<(u64, &str, &str)> {
+        records
+            .iter()
+            .map(|(&id, m)| (id, m.name.as_str(), m.desc.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn put_and_delete_by_id() {
+        let path = temp_path();
+        assert!(load(&path).unwrap().is_none());
+
+        let mut records = Records::from([(1, meta("a", "")), (2, meta("b", ""))]);
+        save(&path, &records, &BTreeSet::new()).unwrap();
+        assert_eq!(line_count(&path), 2);
+
+        // 改名不改变 id；删除按 id 记录
+        records.get_mut(&1).unwrap().name = "renamed".into();
+        records.remove(&2);
+        records.insert(3, meta("c", ""));
+        save(&path, &records, &BTreeSet::from([1, 2, 3])).unwrap();
+        assert_eq!(line_count(&path), 5);
+        assert!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .contains("{\"delete\":2}\n")
+        );
+
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(summary(&loaded), [(1, "renamed", ""), (3, "c", "")]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn legacy_lines_are_rewritten_on_load() {
+        let path = temp_path();
+        fs::write(
+            &path,
+            concat!(
+                "{\"put\": {\"name\": \"a\", \"desc\": \"\", \"hash\": \"a\"}}\n",
+                "{\"put\": {\"name\": \"b\", \"desc\": \"\", \"hash\": \"b\"}}\n",
+                "{\"put\": {\"name\": \"a\", \"desc\": \"new\", \"hash\": \"a\"}}\n",
+                "{\"delete\": \"b\"}\n",
+                "{\"put\": {\"name\": \"c\", \"desc\": \"\", \"hash\": \"c\"}}\n",
+            ),
+        )
+        .unwrap();
+
+        let expected = [(1, "a", "new"), (2, "c", "")];
+        assert_eq!(summary(&load(&path).unwrap().unwrap()), expected);
+        // 重写为每条记录一行，且都带有 id
+        let content = fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.lines().all(|l| l.starts_with("{\"put\":{\"id\":")));
+        assert_eq!(summary(&read(&path).unwrap().unwrap()), expected);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn incomplete_last_line_is_dropped_on_load() {
+        let path = temp_path();
+        let records = Records::from([(1, meta("a", ""))]);
+        write(&path, &records).unwrap();
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"put\": {\"id\": 2, \"na").unwrap();
+
+        // read 不修改文件
+        assert_eq!(summary(&read(&path).unwrap().unwrap()), [(1, "a", "")]);
+        assert_eq!(line_count(&path), 2);
+        assert_eq!(summary(&load(&path).unwrap().unwrap()), [(1, "a", "")]);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("}\n"));
+        assert_eq!(line_count(&path), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn compacts_after_enough_stale_lines() {
+        let path = temp_path();
+        let mut records = Records::from([(1, meta("a", "")), (2, meta("b", ""))]);
+        write(&path, &records).unwrap();
+
+        // 每次保存都让 id 1 之前的一行失效
+        for i in 1..COMPACT_MIN_STALE {
+            records.get_mut(&1).unwrap().desc = i.to_string();
+            save(&path, &records, &BTreeSet::from([1])).unwrap();
+        }
+        assert_eq!(line_count(&path), COMPACT_MIN_STALE + 1);
+
+        records.get_mut(&1).unwrap().desc = "last".into();
+        save(&path, &records, &BTreeSet::from([1])).unwrap();
+        assert_eq!(line_count(&path), 2);
+        assert_eq!(
+            summary(&load(&path).unwrap().unwrap()),
+            [(1, "a", "last"), (2, "b", "")]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
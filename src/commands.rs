@@ -0,0 +1,1441 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use config_file2::LoadConfigFile as _;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    catalog,
+    config::{
+        AppConfig, ImageMeta, load_config, load_config_readonly, migrate_config, save_config,
+        token_fingerprint,
+    },
+    imaging::{capture_time, generate_thumbnail, perceptual_hash, sniff_content_type},
+    migrate,
+    storage::{BlobKey, copy_to_blob, move_file, open_blob},
+    usage::Usage,
+};
+
+// 校验结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlobStatus {
+    Ok,
+    Missing,
+    Corrupted,
+}
+
+// 重新计算文件 (明文) 的 SHA256
+fn hash_file(path: &Path, key: Option<&BlobKey>) -> io::Result<String> {
+    let mut reader = open_blob(path, key)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// 完整性校验：重新计算每个 blob 的哈希，与其存储名（即 ImageMeta.hash）比对
+pub fn verify(config_path: &PathBuf, prune: bool) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    let images_dir = config.images_dir().clone();
+
+    // 多个元数据可能指向同一个 blob，每个 hash 只校验一次
+    let mut checked: HashMap<String, BlobStatus> = HashMap::new();
+    for meta in config.images() {
+        if checked.contains_key(&meta.hash) {
+            continue;
+        }
+        let path = images_dir.join(&meta.hash);
+        let status = match hash_file(&path, config.blob_key.as_ref()) {
+            Ok(actual) if actual == meta.hash => BlobStatus::Ok,
+            Ok(actual) => {
+                println!("CORRUPTED {} (actual: {})", meta.hash, actual);
+                BlobStatus::Corrupted
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                println!("MISSING   {}", meta.hash);
+                BlobStatus::Missing
+            }
+            // 加密 blob 认证失败
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                println!("CORRUPTED {} ({})", meta.hash, e);
+                BlobStatus::Corrupted
+            }
+            Err(e) => return Err(e.into()),
+        };
+        checked.insert(meta.hash.clone(), status);
+    }
+
+    for meta in config.images() {
+        match checked[&meta.hash] {
+            BlobStatus::Ok => {}
+            status => println!("  referenced by {:?} ({:?})", meta.name, status),
+        }
+    }
+
+    let missing = checked
+        .values()
+        .filter(|s| **s == BlobStatus::Missing)
+        .count();
+    let corrupted = checked
+        .values()
+        .filter(|s| **s == BlobStatus::Corrupted)
+        .count();
+    println!(
+        "Checked {} blobs: {} ok, {} missing, {} corrupted",
+        checked.len(),
+        checked.len() - missing - corrupted,
+        missing,
+        corrupted
+    );
+
+    // 仅删除指向缺失文件的元数据；损坏的文件保留，交给人工处理
+    if prune && missing > 0 {
+        let before = config.images().len();
+        config.retain_images(|meta| checked[&meta.hash] != BlobStatus::Missing);
+        save_config(config_path, &config)?;
+        println!(
+            "Removed {} dead metadata entries",
+            before - config.images().len()
+        );
+    }
+
+    let remaining = if prune {
+        corrupted
+    } else {
+        missing + corrupted
+    };
+    if remaining > 0 {
+        anyhow::bail!("integrity check failed");
+    }
+    Ok(())
+}
+
+// 导入时图片名称的来源
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum NameFrom {
+    // 使用文件名 (含扩展名)
+    Filename,
+    // 按配置的 id_strategy 生成
+    Generated,
+}
+
+fn collect_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                collect_files(&path, recursive, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// 导入单个文件：计算哈希、复制进内容存储、生成缩略图，返回对应的元数据
+fn import_file(config: &AppConfig, path: &Path, name: String) -> anyhow::Result<ImageMeta> {
+    let hash = hash_file(path, None)?;
+    let size = fs::metadata(path)?.len();
+    let target_path = config.images_dir().join(&hash);
+
+    // 相同内容已存在时直接复用
+    let mut blurhash = config.blurhash_of(&hash);
+    if !target_path.exists() {
+        // 先复制到临时文件再 rename，避免中断时留下不完整的 blob
+        let temp_path = config.temp_dir().join(uuid::Uuid::new_v4().to_string());
+        copy_to_blob(File::open(path)?, &temp_path, config.blob_key.as_ref())?;
+        if let Err(e) = move_file(&temp_path, &target_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+
+        if let Some(thumbnail_pixels) = config.thumbnail_pixels {
+            match generate_thumbnail(
+                &target_path,
+                &config.thumbs_dir().join(&hash),
+                thumbnail_pixels,
+                config.progressive_thumbnails,
+                config.decode_limits(),
+                config.blob_key.as_ref(),
+            ) {
+                Ok(hash) => blurhash = Some(hash),
+                Err(e) => println!("WARN   thumbnail failed for {:?}: {}", path, e),
+            }
+        }
+    }
+
+    Ok(ImageMeta {
+        name,
+        desc: String::new(),
+        hash,
+        size,
+        created_at: chrono::Utc::now(),
+        captured_at: capture_time(path, None),
+        content_type: sniff_content_type(path, None),
+        aliases: Vec::new(),
+        tags: Vec::new(),
+        pinned: false,
+        uploaded_by: None,
+        broken_sources: Vec::new(),
+        album: None,
+        private: false,
+        versions: Vec::new(),
+        blurhash,
+        phash: perceptual_hash(
+            &target_path,
+            config.decode_limits(),
+            config.blob_key.as_ref(),
+        )
+        .ok()
+        .map(|p| format!("{:016x}", p)),
+        quarantined: None,
+        thumbnail_pending: false,
+    })
+}
+
+// 批量导入已有目录中的图片
+pub fn import(
+    config_path: &PathBuf,
+    dir: &Path,
+    recursive: bool,
+    name_from: NameFrom,
+) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    // 导入的图片没有上传者，计入 unknown
+    let usage = Usage::open(&config)?;
+
+    let mut files = Vec::new();
+    collect_files(dir, recursive, &mut files)?;
+    files.sort();
+
+    let (mut imported, mut skipped) = (0, 0);
+    for path in files {
+        // 只导入能识别出格式的图片文件
+        if sniff_content_type(&path, None).is_none() {
+            println!("SKIP   {:?}: not an image", path);
+            skipped += 1;
+            continue;
+        }
+
+        let name = match name_from {
+            NameFrom::Filename => path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            NameFrom::Generated => config.next_image_name(),
+        };
+        if config.image_id(&name).is_some() {
+            println!("SKIP   {:?}: name {:?} already exists", path, name);
+            skipped += 1;
+            continue;
+        }
+
+        match import_file(&config, &path, name) {
+            Ok(meta) => {
+                println!("IMPORT {:?} -> {:?}", path, meta.name);
+                usage.record_upload("unknown", meta.size);
+                config.push_image(meta);
+                imported += 1;
+            }
+            Err(e) => {
+                println!("FAIL   {:?}: {}", path, e);
+                skipped += 1;
+            }
+        }
+    }
+
+    save_config(config_path, &config)?;
+    usage.flush()?;
+    println!("Imported {} images, skipped {}", imported, skipped);
+    Ok(())
+}
+
+// 元数据导出格式
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+}
+
+// 轮换 label 对应的 token，旧 token 在宽限期内仍然有效
+pub fn rotate_token(
+    config_path: &PathBuf,
+    label: &str,
+    grace_hours: Option<u64>,
+) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    let grace_hours = grace_hours.unwrap_or(config.token_grace_hours);
+    let (token, expires_at) =
+        config.rotate_token(label, chrono::Duration::hours(grace_hours as i64));
+    save_config(config_path, &config)?;
+
+    println!("New token for {:?}: {}", label, token);
+    match expires_at {
+        Some(expires_at) => println!("Previous token stays valid until {}", expires_at),
+        None => println!("No previous token for {:?}", label),
+    }
+    Ok(())
+}
+
+// 列出配置中的 token：只显示指纹，以及标签、创建时间和状态 (轮换后的失效时间)
+pub fn list_tokens(config_path: &PathBuf) -> anyhow::Result<()> {
+    let config = load_config(config_path)?;
+    let now = chrono::Utc::now();
+    let mut tokens: Vec<_> = config
+        .tokens
+        .iter()
+        .map(|token| (token_fingerprint(token), config.token_info.get(token)))
+        .collect();
+    // 没有附加信息的 token (gen-token 生成或手动添加) 排在前面
+    tokens.sort_by_key(|(fingerprint, info)| (info.map(|i| i.created_at), fingerprint.clone()));
+
+    println!(
+        "{:<12}  {:<16}  {:<20}  STATUS",
+        "FINGERPRINT", "LABEL", "CREATED"
+    );
+    for (fingerprint, info) in &tokens {
+        let label = info.and_then(|i| i.label.as_deref()).unwrap_or("-");
+        let created = info.map_or_else(
+            || "-".to_string(),
+            |i| i.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        );
+        let status = match info.and_then(|i| i.expires_at) {
+            None => "active".to_string(),
+            Some(t) if t <= now => "expired".to_string(),
+            Some(t) => format!("expires {}", t.format("%Y-%m-%d %H:%M")),
+        };
+        println!(
+            "{:<12}  {:<16}  {:<20}  {}",
+            fingerprint, label, created, status
+        );
+    }
+    println!("{} tokens", tokens.len());
+    Ok(())
+}
+
+// 吊销 token；token 可以是完整 token、token 的前缀或 token list 显示的指纹 (前缀)，必须只匹配一个 token
+pub fn revoke_token(config_path: &PathBuf, token: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        token.len() >= 4,
+        "give at least 4 characters of the token or its fingerprint"
+    );
+    let mut config = load_config(config_path)?;
+    let mut matches: Vec<String> = match config.tokens.contains(token) {
+        true => vec![token.to_string()],
+        false => config
+            .tokens
+            .iter()
+            .filter(|t| t.starts_with(token) || token_fingerprint(t).starts_with(token))
+            .cloned()
+            .collect(),
+    };
+    match matches.len() {
+        0 => anyhow::bail!("no token matches {:?}", token),
+        1 => {}
+        n => anyhow::bail!("{:?} matches {} tokens, give more characters", token, n),
+    }
+
+    let revoked = matches.remove(0);
+    config.tokens.remove(&revoked);
+    let info = config.token_info.remove(&revoked);
+    save_config(config_path, &config)?;
+
+    println!(
+        "Revoked token {} (label: {})",
+        token_fingerprint(&revoked),
+        info.as_ref()
+            .and_then(|i| i.label.as_deref())
+            .unwrap_or("-")
+    );
+    println!("A running server accepts it until it reloads the config (SIGHUP) or restarts");
+    Ok(())
+}
+
+// 升级配置文件与数据格式；dry_run 时只列出需要的步骤
+pub fn migrate(config_path: &PathBuf, dry_run: bool) -> anyhow::Result<()> {
+    let plan = migrate::Plan::load(config_path)?
+        .ok_or_else(|| anyhow::anyhow!("{:?} does not exist", config_path))?;
+    println!(
+        "Schema version: {} (current: {})",
+        plan.version,
+        migrate::CURRENT_VERSION
+    );
+    // 不认识的设置项不会被迁移，提醒用户手动处理
+    for key in plan.unknown_settings()? {
+        println!(
+            "UNKNOWN {} (not used by this version, dropped when the config is rewritten)",
+            key
+        );
+    }
+    let steps = plan.steps();
+    if steps.is_empty() {
+        println!("Already up to date");
+        return Ok(());
+    }
+    for (from, description) in &steps {
+        println!("MIGRATE v{} -> v{}: {}", from, from + 1, description);
+    }
+    if dry_run {
+        println!("Dry run, nothing changed");
+        return Ok(());
+    }
+    drop(plan);
+    if let Some((_, backups)) = migrate_config(config_path)? {
+        for backup in backups {
+            println!("BACKUP  {:?}", backup);
+        }
+    }
+    println!("Migrated to schema version {}", migrate::CURRENT_VERSION);
+    Ok(())
+}
+
+// 目录可写：写入并删除一个探测文件；目录不存在时检查能否在最近的已存在上级目录中创建
+fn check_writable(dir: &Path) -> Result<(), String> {
+    let mut existing = dir;
+    while !existing.exists() {
+        match existing.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => existing = parent,
+            _ => {
+                existing = Path::new(".");
+                break;
+            }
+        }
+    }
+    if !existing.is_dir() {
+        return Err(format!("{:?} is not a directory", existing));
+    }
+    let probe = existing.join(format!(".validate-{}", uuid::Uuid::new_v4().simple()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .map_err(|e| match existing == dir {
+            true => format!("{:?} is not writable: {}", dir, e),
+            false => format!("{:?} cannot be created in {:?}: {}", dir, existing, e),
+        })?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+// 检查配置文件 (不做任何修改)：能否解析、目录权限、图片名称重复、指向缺失 blob 的记录、无效的黑名单项
+// 有错误时以非零状态退出
+pub fn validate_config(config_path: &PathBuf) -> anyhow::Result<()> {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let plan = migrate::Plan::load(config_path)?
+        .ok_or_else(|| anyhow::anyhow!("{:?} does not exist", config_path))?;
+    if plan.version < migrate::CURRENT_VERSION {
+        warnings.push(format!(
+            "schema version {} is outdated (current: {}), run `img-server migrate`",
+            plan.version,
+            migrate::CURRENT_VERSION
+        ));
+    }
+    for key in plan.unknown_settings()? {
+        warnings.push(format!("unknown setting {:?} is ignored", key));
+    }
+    let config = AppConfig::load(config_path)?
+        .ok_or_else(|| anyhow::anyhow!("{:?} does not exist", config_path))?;
+    if let Err(e) = config.resolve_blob_key() {
+        errors.push(format!("invalid encryption key: {}", e));
+    }
+
+    // 配置文件以临时文件 + rename 的方式写入，所在目录也需要可写
+    let config_dir = config_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    for dir in [
+        config_dir,
+        &config.data_dir,
+        config.images_dir(),
+        config.thumbs_dir(),
+        config.temp_dir(),
+        config.variants_dir(),
+        config.logs_dir(),
+    ] {
+        if let Err(e) = check_writable(dir) {
+            errors.push(e);
+        }
+    }
+
+    // 与 check_ip 相同，按客户端地址的字符串形式比较，非规范写法永远不会匹配
+    let mut blacklist: Vec<&String> = config.blacklist.iter().collect();
+    blacklist.sort();
+    for entry in blacklist {
+        match entry.parse::<std::net::IpAddr>() {
+            Ok(ip) if ip.to_string() == *entry => {}
+            Ok(ip) => errors.push(format!(
+                "blacklist entry {:?} never matches, write it as {:?}",
+                entry,
+                ip.to_string()
+            )),
+            Err(_) => errors.push(format!(
+                "blacklist entry {:?} is not an IP address (ranges are not supported)",
+                entry
+            )),
+        }
+    }
+
+    let images = match catalog::read(&config.images_file()) {
+        Ok(images) => images.unwrap_or_default(),
+        Err(e) => {
+            errors.push(format!("cannot read image records: {}", e));
+            Default::default()
+        }
+    };
+
+    // 名称和别名在所有记录中必须唯一
+    let mut owners: HashMap<&str, Vec<&str>> = HashMap::new();
+    for img in images.values() {
+        for name in std::iter::once(&img.name).chain(&img.aliases) {
+            let names = owners.entry(name).or_default();
+            if !names.contains(&img.name.as_str()) {
+                names.push(&img.name);
+            }
+        }
+    }
+    let mut duplicates: Vec<_> = owners.into_iter().filter(|(_, n)| n.len() > 1).collect();
+    duplicates.sort();
+    for (name, records) in duplicates {
+        errors.push(format!("name {:?} is used by records {:?}", name, records));
+    }
+
+    // 设置了 upstream 时缺失的 blob 会从上游拉取，只作为警告
+    let images_dir = config.images_dir();
+    let mut blobs = HashMap::new();
+    for img in images.values() {
+        for hash in std::iter::once(&img.hash).chain(img.versions.iter().map(|v| &v.hash)) {
+            let exists = *blobs
+                .entry(hash)
+                .or_insert_with(|| images_dir.join(hash).exists());
+            if !exists {
+                let message = format!("image {:?} points to missing blob {}", img.name, hash);
+                match config.upstream {
+                    Some(_) => warnings.push(message),
+                    None => errors.push(message),
+                }
+            }
+        }
+    }
+    let mut links: Vec<_> = config
+        .one_time_links
+        .values()
+        .filter(|link| !blobs.contains_key(&link.hash))
+        .map(|link| &link.hash)
+        .collect();
+    links.sort();
+    links.dedup();
+    for hash in links {
+        warnings.push(format!(
+            "one-time link points to {}, which no image uses",
+            hash
+        ));
+    }
+
+    for warning in &warnings {
+        println!("WARN   {}", warning);
+    }
+    for error in &errors {
+        println!("ERROR  {}", error);
+    }
+    if !errors.is_empty() {
+        anyhow::bail!("{} problems found in {:?}", errors.len(), config_path);
+    }
+    println!(
+        "{:?} is valid ({} images, {} warnings)",
+        config_path,
+        images.len(),
+        warnings.len()
+    );
+    Ok(())
+}
+
+// 导出全部图片元数据到 stdout 或文件
+pub fn export(
+    config_path: &PathBuf,
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let mut config = load_config_readonly(config_path)?;
+
+    // 旧记录没有 size 字段，从文件补全 (只在内存中，不写回)
+    let images_dir = config.images_dir().clone();
+    config.update_images(|meta| {
+        if meta.size != 0 {
+            return false;
+        }
+        match fs::metadata(images_dir.join(&meta.hash)) {
+            Ok(metadata) => {
+                meta.size = metadata.len();
+                true
+            }
+            Err(_) => false,
+        }
+    });
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+
+    match format {
+        ExportFormat::Json => {
+            let images: Vec<_> = config.images().collect();
+            serde_json::to_writer_pretty(&mut writer, &images)?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer.write_record(["name", "desc", "hash", "created_at", "size"])?;
+            for meta in config.images() {
+                writer.write_record([
+                    meta.name.as_str(),
+                    meta.desc.as_str(),
+                    meta.hash.as_str(),
+                    meta.created_at.to_rfc3339().as_str(),
+                    meta.size.to_string().as_str(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// 请求运行中服务的 /readyz，非 2xx 或无法连接时返回错误 (进程退出码为 1)
+pub async fn healthcheck(addr: &str) -> anyhow::Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    let resp = client.get(format!("http://{}/readyz", addr)).send().await?;
+    anyhow::ensure!(
+        resp.status().is_success(),
+        "server not ready: {}",
+        resp.status()
+    );
+    println!("OK");
+    Ok(())
+}
+
+// 管理接口使用的 token：未指定时使用配置文件中未在轮换中的 token
+fn admin_token(config_path: &PathBuf, token: Option<String>) -> anyhow::Result<Option<String>> {
+    if token.is_some() {
+        return Ok(token);
+    }
+    Ok(AppConfig::load(config_path)?.and_then(|config| {
+        config
+            .tokens
+            .iter()
+            .find(|t| {
+                config
+                    .token_info
+                    .get(*t)
+                    .is_none_or(|info| info.expires_at.is_none())
+            })
+            .cloned()
+    }))
+}
+
+// 向运行中服务的管理接口发送请求，返回 JSON 响应；非 2xx 时返回错误
+async fn admin_request(
+    addr: &str,
+    token: Option<&str>,
+    method: reqwest::Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> anyhow::Result<serde_json::Value> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+    let mut request = client.request(method, format!("http://{}{}", addr, path));
+    if let Some(token) = token {
+        request = request.header("x-admin-token", token);
+    }
+    if let Some(body) = body {
+        request = request
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(&body)?);
+    }
+    let resp = request.send().await?;
+    let status = resp.status();
+    let body = resp.bytes().await?;
+    anyhow::ensure!(
+        status.is_success(),
+        "server returned {}: {}",
+        status,
+        String::from_utf8_lossy(&body)
+    );
+    Ok(serde_json::from_slice(&body)?)
+}
+
+// 查询运行中服务的 /admin/stats，打印版本、运行时间、图片数量和存储用量；json 时原样输出统计数据
+pub async fn status(
+    config_path: &PathBuf,
+    addr: &str,
+    token: Option<String>,
+    json: bool,
+) -> anyhow::Result<()> {
+    let token = admin_token(config_path, token)?;
+    let stats = admin_request(
+        addr,
+        token.as_deref(),
+        reqwest::Method::GET,
+        "/admin/stats",
+        None,
+    )
+    .await?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    let number = |key: &str| stats[key].as_u64().unwrap_or(0);
+    println!(
+        "Version:  {}",
+        stats["version"].as_str().unwrap_or("unknown")
+    );
+    println!("Uptime:   {}", format_uptime(number("uptime_secs")));
+    println!("Images:   {}", number("images"));
+    println!(
+        "Storage:  {} in {} blobs",
+        format_bytes(number("blob_bytes")),
+        number("blobs")
+    );
+    println!("Queued:   {} thumbnails", number("thumbnail_queue"));
+    Ok(())
+}
+
+// 查看或修改运行中服务的日志级别；duration_secs 后服务自动恢复原来的级别
+pub async fn log_level(
+    config_path: &PathBuf,
+    addr: &str,
+    token: Option<String>,
+    level: Option<String>,
+    duration_secs: Option<u64>,
+) -> anyhow::Result<()> {
+    let token = admin_token(config_path, token)?;
+    let resp = match level {
+        Some(level) => {
+            let body = serde_json::json!({ "level": level, "duration_secs": duration_secs });
+            admin_request(
+                addr,
+                token.as_deref(),
+                reqwest::Method::PUT,
+                "/admin/log-level",
+                Some(body),
+            )
+            .await?
+        }
+        None => {
+            admin_request(
+                addr,
+                token.as_deref(),
+                reqwest::Method::GET,
+                "/admin/log-level",
+                None,
+            )
+            .await?
+        }
+    };
+    println!("{}", resp["level"].as_str().unwrap_or("unknown"));
+    if let Some(secs) = duration_secs {
+        println!("Reverts in {}", format_uptime(secs));
+    }
+    Ok(())
+}
+
+// 1d 2h 3m 4s 形式的时长，省略前面为 0 的单位
+fn format_uptime(secs: u64) -> String {
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+    ];
+    let mut out: Vec<String> = units
+        .iter()
+        .skip_while(|(n, _)| *n == 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+    out.push(format!("{}s", secs % 60));
+    out.join(" ")
+}
+
+// 以 1024 为进制的可读大小
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} B", bytes),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+// 缩略图重新生成的进度记录，位于缩略图目录
+// 第一行为缩略图设置，之后每行为一个已完成的 "<hash> <blurhash>"；全部成功后删除
+const REGEN_MARKER: &str = ".regen-progress";
+
+// 读取上次中断时的进度；缩略图设置不同时作废
+fn read_regen_marker(path: &Path, settings: &str) -> HashMap<String, String> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    let mut lines = content.lines();
+    if lines.next() != Some(settings) {
+        return HashMap::new();
+    }
+    lines
+        .filter_map(|line| line.split_once(' '))
+        .map(|(hash, blurhash)| (hash.to_string(), blurhash.to_string()))
+        .collect()
+}
+
+// 在终端中显示进度条
+fn print_progress(done: usize, total: usize) {
+    const WIDTH: usize = 30;
+    let filled = WIDTH * done / total.max(1);
+    eprint!(
+        "\r[{}{}] {}/{} ({}%)",
+        "#".repeat(filled),
+        "-".repeat(WIDTH - filled),
+        done,
+        total,
+        100 * done / total.max(1)
+    );
+}
+
+// 为所有已存储的原图 (含历史版本) 重新生成缩略图，用于修改 thumbnail_pixels 或缩略图丢失后
+// 同时更新记录中的 BlurHash；中断后再次运行时从进度记录继续，fresh 时重新开始
+pub fn regen_thumbs(
+    config_path: &PathBuf,
+    missing_only: bool,
+    jobs: Option<usize>,
+    fresh: bool,
+) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    let Some(thumbnail_pixels) = config.thumbnail_pixels else {
+        anyhow::bail!("thumbnails are disabled (thumbnail_pixels is not set)");
+    };
+    let marker_path = config.thumbs_dir().join(REGEN_MARKER);
+    let settings = format!(
+        "pixels={} progressive={}",
+        thumbnail_pixels, config.progressive_thumbnails
+    );
+    let resumed = match fresh {
+        true => HashMap::new(),
+        false => read_regen_marker(&marker_path, &settings),
+    };
+
+    let mut hashes: Vec<String> = config
+        .images()
+        .flat_map(|meta| std::iter::once(&meta.hash).chain(meta.versions.iter().map(|v| &v.hash)))
+        .cloned()
+        .collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+    if missing_only {
+        hashes.retain(|hash| !config.thumbs_dir().join(hash).exists());
+    }
+    if !resumed.is_empty() {
+        hashes.retain(|hash| !resumed.contains_key(hash));
+        println!(
+            "Resuming: {} thumbnails already regenerated, {} remaining",
+            resumed.len(),
+            hashes.len()
+        );
+    }
+    let marker = match resumed.is_empty() {
+        true => {
+            let mut file = File::create(&marker_path)?;
+            writeln!(file, "{}", settings)?;
+            file
+        }
+        false => fs::OpenOptions::new().append(true).open(&marker_path)?,
+    };
+    // 进度条与完成记录共用一把锁，避免输出交错
+    let marker = Mutex::new(marker);
+    let tty = io::stderr().is_terminal();
+
+    let jobs = jobs
+        .or_else(|| std::thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, hashes.len().max(1));
+    let next = AtomicUsize::new(0);
+    let finished = AtomicUsize::new(0);
+    let failed = AtomicUsize::new(0);
+    let blurhashes = Mutex::new(resumed);
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Some(hash) = hashes.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let src = config.images_dir().join(hash);
+                    let dst = config.thumbs_dir().join(hash);
+                    let result = generate_thumbnail(
+                        &src,
+                        &dst,
+                        thumbnail_pixels,
+                        config.progressive_thumbnails,
+                        config.decode_limits(),
+                        config.blob_key.as_ref(),
+                    );
+                    if let Ok(blurhash) = &result {
+                        // 旧缩略图转换出的格式副本已过期
+                        for path in config.variants_of(hash) {
+                            if path.to_string_lossy().contains(".thumb.") {
+                                let _ = fs::remove_file(path);
+                            }
+                        }
+                        blurhashes
+                            .lock()
+                            .unwrap()
+                            .insert(hash.clone(), blurhash.clone());
+                    }
+
+                    let mut marker = marker.lock().unwrap();
+                    let done = finished.fetch_add(1, Ordering::Relaxed) + 1;
+                    if tty {
+                        eprint!("\r\x1b[K");
+                    }
+                    match result {
+                        Ok(blurhash) => {
+                            if let Err(e) = writeln!(marker, "{} {}", hash, blurhash) {
+                                println!("WARN   failed to record progress: {}", e);
+                            }
+                            if !tty {
+                                println!("REGEN  {}", hash);
+                            }
+                        }
+                        Err(e) => {
+                            println!("FAIL   {}: {}", hash, e);
+                            failed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                    if tty {
+                        print_progress(done, hashes.len());
+                    }
+                }
+            });
+        }
+    });
+
+    let blurhashes = blurhashes.into_inner().unwrap();
+    config.update_images(|img| {
+        let mut changed = false;
+        if let Some(blurhash) = blurhashes.get(&img.hash) {
+            img.blurhash = Some(blurhash.clone());
+            changed = true;
+        }
+        for version in &mut img.versions {
+            if let Some(blurhash) = blurhashes.get(&version.hash) {
+                version.blurhash = Some(blurhash.clone());
+                changed = true;
+            }
+        }
+        changed
+    });
+    save_config(config_path, &config)?;
+    if tty && !hashes.is_empty() {
+        eprintln!();
+    }
+
+    // 全部成功后删除进度记录；有失败时保留，再次运行只重试失败的部分
+    let failed = failed.into_inner();
+    if failed == 0 {
+        let _ = fs::remove_file(&marker_path);
+    }
+    println!(
+        "Regenerated {} thumbnails, {} failed",
+        hashes.len() - failed,
+        failed
+    );
+    if failed > 0 {
+        anyhow::bail!("thumbnail regeneration failed for {} images", failed);
+    }
+    Ok(())
+}
+
+// 删除不再被任何记录 (含历史版本) 引用的 blob，以及它的缩略图和格式副本；返回释放的字节数
+// dry_run 时只计算不删除
+fn remove_unused_blobs(config: &AppConfig, hashes: &[String], dry_run: bool) -> u64 {
+    let mut freed = 0;
+    let mut seen = HashSet::new();
+    for hash in hashes {
+        if !seen.insert(hash) || config.hash_in_use(hash) {
+            continue;
+        }
+        let path = config.images_dir().join(hash);
+        if let Ok(metadata) = fs::metadata(&path) {
+            freed += metadata.len();
+        }
+        if dry_run {
+            continue;
+        }
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(config.thumbs_dir().join(hash));
+        for variant in config.variants_of(hash) {
+            let _ = fs::remove_file(variant);
+        }
+    }
+    freed
+}
+
+// 并查集的根
+fn find_root(parent: &mut [usize], mut i: usize) -> usize {
+    while parent[i] != i {
+        parent[i] = parent[parent[i]];
+        i = parent[i];
+    }
+    i
+}
+
+// 按感知哈希把视觉上相同的图片 (当前版本的 blob) 聚成组并列出；interactive 时逐组选择保留的 blob，
+// 组内其他记录改为指向它，不再被引用的 blob 被删除
+pub fn dedupe(
+    config_path: &PathBuf,
+    threshold: Option<u32>,
+    interactive: bool,
+) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    let threshold = threshold.unwrap_or(config.similar_distance);
+    let images_dir = config.images_dir().clone();
+
+    // 旧记录没有感知哈希，从 blob 计算并补全；视频不参与比较
+    let mut phashes: HashMap<String, u64> = HashMap::new();
+    for img in config.images() {
+        if let Some(phash) = img
+            .phash
+            .as_deref()
+            .and_then(|p| u64::from_str_radix(p, 16).ok())
+        {
+            phashes.insert(img.hash.clone(), phash);
+        }
+    }
+    let limits = config.decode_limits();
+    let key = config.blob_key.clone();
+    let computed = config.update_images(|img| {
+        if img.phash.is_some()
+            || img
+                .content_type
+                .as_deref()
+                .is_some_and(|t| t.starts_with("video/"))
+        {
+            return false;
+        }
+        let phash = match phashes.get(&img.hash) {
+            Some(phash) => *phash,
+            None => match perceptual_hash(&images_dir.join(&img.hash), limits, key.as_ref()) {
+                Ok(phash) => {
+                    phashes.insert(img.hash.clone(), phash);
+                    phash
+                }
+                Err(e) => {
+                    println!("SKIP   {} ({})", img.name, e);
+                    return false;
+                }
+            },
+        };
+        img.phash = Some(format!("{:016x}", phash));
+        true
+    });
+    if computed > 0 {
+        println!("Computed perceptual hashes for {} images", computed);
+    }
+
+    // 汉明距离不超过 threshold 的 blob 连成一组；纯色图片的指纹为 0，不参与比较
+    let mut nodes: Vec<(&String, u64)> = phashes
+        .iter()
+        .filter(|(_, phash)| **phash != 0)
+        .map(|(hash, phash)| (hash, *phash))
+        .collect();
+    nodes.sort();
+    let mut parent: Vec<usize> = (0..nodes.len()).collect();
+    for i in 0..nodes.len() {
+        for j in i + 1..nodes.len() {
+            if (nodes[i].1 ^ nodes[j].1).count_ones() <= threshold {
+                let (a, b) = (find_root(&mut parent, i), find_root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+    let mut clusters: HashMap<usize, Vec<String>> = HashMap::new();
+    for (i, (hash, _)) in nodes.iter().enumerate() {
+        let root = find_root(&mut parent, i);
+        clusters.entry(root).or_default().push(hash.to_string());
+    }
+
+    // 每个 blob 的大小、类型和引用它的记录；组内按大小降序排列，第一个 (质量通常最好) 为默认保留的 blob
+    let size_of = |hash: &str| -> u64 {
+        config
+            .images()
+            .find(|i| i.hash == hash && i.size > 0)
+            .map(|i| i.size)
+            .or_else(|| fs::metadata(images_dir.join(hash)).ok().map(|m| m.len()))
+            .unwrap_or(0)
+    };
+    let mut groups: Vec<Vec<(String, u64)>> = clusters
+        .into_values()
+        .filter(|hashes| hashes.len() > 1)
+        .map(|hashes| {
+            let mut members: Vec<_> = hashes
+                .into_iter()
+                .map(|hash| {
+                    let size = size_of(&hash);
+                    (hash, size)
+                })
+                .collect();
+            members.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            members
+        })
+        .collect();
+    groups.sort_by(|a, b| a[0].0.cmp(&b[0].0));
+
+    let mut merges: Vec<(String, Vec<String>)> = Vec::new();
+    let mut reclaimable = 0;
+    for (n, members) in groups.iter().enumerate() {
+        let saving: u64 = members[1..].iter().map(|(_, size)| size).sum();
+        reclaimable += saving;
+        println!(
+            "GROUP  {}: {} blobs, {} reclaimable",
+            n + 1,
+            members.len(),
+            format_bytes(saving)
+        );
+        for (i, (hash, size)) in members.iter().enumerate() {
+            let records: Vec<&ImageMeta> = config.images_with_hash(hash).collect();
+            let names: Vec<&str> = records.iter().map(|img| img.name.as_str()).collect();
+            println!(
+                "  [{}] {}  {:>10}  {:<12} {}",
+                i + 1,
+                &hash[..hash.len().min(12)],
+                format_bytes(*size),
+                records
+                    .first()
+                    .and_then(|img| img.content_type.as_deref())
+                    .unwrap_or("-"),
+                names.join(", ")
+            );
+        }
+        if !interactive {
+            continue;
+        }
+        let keep = loop {
+            print!(
+                "Keep which blob? [1-{}, Enter = 1, s = skip, q = quit]: ",
+                members.len()
+            );
+            io::stdout().flush()?;
+            let mut line = String::new();
+            if io::stdin().read_line(&mut line)? == 0 {
+                break None;
+            }
+            match line.trim() {
+                "" => break Some(0),
+                "s" => break Some(usize::MAX),
+                "q" => break None,
+                choice => match choice.parse::<usize>() {
+                    Ok(i) if (1..=members.len()).contains(&i) => break Some(i - 1),
+                    _ => println!("Invalid choice: {}", choice),
+                },
+            }
+        };
+        match keep {
+            None => break,
+            Some(usize::MAX) => continue,
+            Some(keep) => merges.push((
+                members[keep].0.clone(),
+                members
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != keep)
+                    .map(|(_, (hash, _))| hash.clone())
+                    .collect(),
+            )),
+        }
+    }
+    println!(
+        "Found {} groups of similar images (threshold {}), {} reclaimable",
+        groups.len(),
+        threshold,
+        format_bytes(reclaimable)
+    );
+
+    // 被合并的记录保留名称、描述和标签，内容相关的字段改用保留的 blob 的值
+    let mut removed = Vec::new();
+    for (keep, others) in &merges {
+        let Some(kept) = config.images().find(|img| img.hash == *keep).cloned() else {
+            continue;
+        };
+        let mut merged = 0;
+        for hash in others {
+            merged += config.update_blob(hash, |img| {
+                if img.hash != *hash {
+                    return false;
+                }
+                img.hash = kept.hash.clone();
+                img.size = kept.size;
+                img.content_type = kept.content_type.clone();
+                img.captured_at = kept.captured_at.or(img.captured_at);
+                img.blurhash = kept.blurhash.clone();
+                img.phash = kept.phash.clone();
+                img.thumbnail_pending = kept.thumbnail_pending;
+                true
+            });
+        }
+        println!(
+            "MERGED {} records into {}",
+            merged,
+            &keep[..keep.len().min(12)]
+        );
+        removed.extend(others.iter().cloned());
+    }
+    if computed > 0 || !merges.is_empty() {
+        save_config(config_path, &config)?;
+    }
+    if !merges.is_empty() {
+        let freed = remove_unused_blobs(&config, &removed, false);
+        println!("Freed {}", format_bytes(freed));
+    } else if !interactive && !groups.is_empty() {
+        println!("Run with --interactive to merge groups");
+    }
+    Ok(())
+}
+
+// 90d、12h 形式的时长 (单位 s / m / h / d / w)，用于命令行参数
+pub fn parse_age(s: &str) -> Result<chrono::Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: i64 = n
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}, expected e.g. 90d", s))?;
+    match unit {
+        "s" => Ok(chrono::Duration::seconds(n)),
+        "m" => Ok(chrono::Duration::minutes(n)),
+        "h" => Ok(chrono::Duration::hours(n)),
+        "d" => Ok(chrono::Duration::days(n)),
+        "w" => Ok(chrono::Duration::weeks(n)),
+        _ => Err(format!(
+            "invalid duration unit in {:?}, expected s, m, h, d or w",
+            s
+        )),
+    }
+}
+
+// 删除上传时间早于 older_than 的图片 (可限定标签) 及其历史版本；blob 仍被其他记录引用时保留
+// 置顶的图片不会被删除
+pub fn prune(
+    config_path: &PathBuf,
+    older_than: chrono::Duration,
+    tag: Option<&str>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    let mut config = load_config(config_path)?;
+    let cutoff = chrono::Utc::now() - older_than;
+
+    let mut hashes = Vec::new();
+    let mut deleted = 0;
+    config.retain_images(|img| {
+        if img.created_at >= cutoff || tag.is_some_and(|tag| !img.tags.iter().any(|t| t == tag)) {
+            return true;
+        }
+        if img.pinned {
+            println!("KEEP   {} (pinned)", img.name);
+            return true;
+        }
+        println!(
+            "DELETE {} (uploaded {}, {})",
+            img.name,
+            img.created_at.format("%Y-%m-%d"),
+            format_bytes(img.size)
+        );
+        hashes.push(img.hash.clone());
+        hashes.extend(img.versions.iter().map(|v| v.hash.clone()));
+        deleted += 1;
+        false
+    });
+
+    if dry_run {
+        let freed = remove_unused_blobs(&config, &hashes, true);
+        println!(
+            "Would delete {} images, freeing {} (dry run)",
+            deleted,
+            format_bytes(freed)
+        );
+        return Ok(());
+    }
+    // 先写入元数据再删除文件，中断时不会留下指向缺失 blob 的记录
+    if deleted > 0 {
+        save_config(config_path, &config)?;
+    }
+    let freed = remove_unused_blobs(&config, &hashes, false);
+    println!("Deleted {} images, freed {}", deleted, format_bytes(freed));
+    Ok(())
+}
+
+// 目录中文件的数量和总大小 (不递归)
+fn dir_usage(dir: &Path) -> (usize, u64) {
+    fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .fold((0, 0), |(n, size), m| (n + 1, size + m.len()))
+}
+
+// 不经过运行中的服务，直接读取元数据和数据目录，输出图片数量、存储占用、最大的文件和按月的上传数
+pub fn stats(config_path: &PathBuf, top: usize, json: bool) -> anyhow::Result<()> {
+    let mut config = load_config_readonly(config_path)?;
+
+    // 旧记录没有 size 字段，从文件补全 (只在内存中，不写回)
+    let images_dir = config.images_dir().clone();
+    config.update_images(|meta| {
+        if meta.size != 0 {
+            return false;
+        }
+        match fs::metadata(images_dir.join(&meta.hash)) {
+            Ok(metadata) => {
+                meta.size = metadata.len();
+                true
+            }
+            Err(_) => false,
+        }
+    });
+
+    let images: Vec<&ImageMeta> = config.images().collect();
+    let aliases: usize = images.iter().map(|i| i.aliases.len()).sum();
+    let versions: usize = images.iter().map(|i| i.versions.len()).sum();
+    let total: u64 = images
+        .iter()
+        .map(|i| i.size + i.versions.iter().map(|v| v.size).sum::<u64>())
+        .sum();
+    let (blobs, unique) = config.blob_usage();
+
+    // 不被任何记录 (含历史版本) 引用的 blob，例如上传中断后的残留
+    let (orphans, orphaned) = fs::read_dir(&images_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|e| !config.hash_in_use(&e.file_name().to_string_lossy()))
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .fold((0, 0), |(n, size), m| (n + 1, size + m.len()));
+    let (_, images_disk) = dir_usage(&images_dir);
+    let (_, thumbs_disk) = dir_usage(config.thumbs_dir());
+    let (_, variants_disk) = dir_usage(config.variants_dir());
+
+    // 最大的文件：同一 blob 只列出一次
+    let mut biggest: Vec<&ImageMeta> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut by_size = images.clone();
+    by_size.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    for img in by_size {
+        if biggest.len() == top {
+            break;
+        }
+        if seen.insert(&img.hash) {
+            biggest.push(img);
+        }
+    }
+
+    let mut months: std::collections::BTreeMap<String, usize> = Default::default();
+    for img in &images {
+        *months
+            .entry(img.created_at.format("%Y-%m").to_string())
+            .or_default() += 1;
+    }
+
+    if json {
+        let stats = serde_json::json!({
+            "images": images.len(),
+            "aliases": aliases,
+            "versions": versions,
+            "total_bytes": total,
+            "blobs": blobs,
+            "unique_bytes": unique,
+            "orphaned_blobs": orphans,
+            "orphaned_bytes": orphaned,
+            "disk_bytes": {
+                "images": images_disk,
+                "thumbs": thumbs_disk,
+                "variants": variants_disk,
+            },
+            "biggest": biggest
+                .iter()
+                .map(|i| serde_json::json!({ "name": i.name, "hash": i.hash, "size": i.size }))
+                .collect::<Vec<_>>(),
+            "per_month": months,
+        });
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    println!(
+        "Images:   {} ({} aliases, {} older versions)",
+        images.len(),
+        aliases,
+        versions
+    );
+    println!(
+        "Size:     {} total, {} in {} unique blobs",
+        format_bytes(total),
+        format_bytes(unique),
+        blobs
+    );
+    println!(
+        "On disk:  {} blobs, {} thumbnails, {} converted copies",
+        format_bytes(images_disk),
+        format_bytes(thumbs_disk),
+        format_bytes(variants_disk)
+    );
+    if orphans > 0 {
+        println!(
+            "Orphaned: {} blobs ({}) not used by any image",
+            orphans,
+            format_bytes(orphaned)
+        );
+    }
+
+    if !biggest.is_empty() {
+        println!();
+        println!("Biggest files:");
+        for img in &biggest {
+            println!(
+                "  {:>10}  {}  {}",
+                format_bytes(img.size),
+                &img.hash[..img.hash.len().min(12)],
+                img.name
+            );
+        }
+    }
+
+    if !months.is_empty() {
+        const WIDTH: usize = 40;
+        let max = months.values().copied().max().unwrap_or(1);
+        println!();
+        println!("Uploads per month:");
+        for (month, count) in &months {
+            println!(
+                "  {}  {:>6}  {}",
+                month,
+                count,
+                "#".repeat((WIDTH * count).div_ceil(max))
+            );
+        }
+    }
+    Ok(())
+}
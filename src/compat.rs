@@ -0,0 +1,361 @@
+// 第三方上传客户端的兼容接口：按客户端默认的请求格式接收上传，返回它们能直接解析的响应
+//
+// ShareX (自定义上传器)：POST /sharex，返回 url / thumbnail_url / deletion_url
+// PicGo / uPic：POST /picgo，返回 SM.MS 风格的 JSON，包含可直接粘贴的 Markdown
+// 两者返回的删除链接都是 /sharex/delete/{key}
+use std::{net::SocketAddr, sync::Arc, sync::atomic::Ordering};
+
+use axum::{
+    Json,
+    extract::{ConnectInfo, Multipart, Path, State},
+    http::{StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use log::{error, info};
+
+use crate::{
+    album::escape_html,
+    config::{AppConfig, AppState, DeletionKey},
+    handler::{
+        ReceivedFile, StoredImage, UploadFields, check_disk_space, check_ip, check_token,
+        receive_file, remove_unused_blobs, store_files,
+    },
+    id::random_string,
+};
+
+// 对外的访问地址：优先使用 public_url，否则按请求的 Host 和 X-Forwarded-Proto (反向代理) 推断
+fn base_url(config: &AppConfig, headers: &header::HeaderMap) -> String {
+    if let Some(url) = &config.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http");
+    format!("{}://{}", scheme, host)
+}
+
+// Markdown 图片的替代文本中需要转义的字符
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// 接收 multipart 中的单个文件和 name / desc / tags 字段
+// 客户端的文件字段名各不相同 (ShareX 由 FileFormName 决定，常见 file / sharex / image)，带文件名的字段都作为文件
+async fn receive_single(
+    state: &Arc<AppState>,
+    addr: &SocketAddr,
+    token: Option<&str>,
+    site: &'static str,
+    mut multipart: Multipart,
+) -> Result<(ReceivedFile, UploadFields), (StatusCode, String)> {
+    let (temp_dir, blob_key, max_file) = {
+        let config = state.read_config(site).await;
+        check_ip(&config, addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+        (
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.max_file_bytes(),
+        )
+    };
+
+    let mut fields = UploadFields::default();
+    let mut file = None;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.file_name().is_some() {
+            if file.is_some() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Only one file per request".to_string(),
+                ));
+            }
+            file = Some(receive_file(field, &temp_dir, blob_key.as_ref(), max_file).await?);
+            continue;
+        }
+        let name = field.name().unwrap_or("").to_string();
+        let text = field
+            .text()
+            .await
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+        match name.as_str() {
+            "name" => fields.names.push(text),
+            "desc" => fields.descs.push(text),
+            "tags" => fields.tags.extend(text.split(',').map(str::to_string)),
+            _ => {}
+        }
+    }
+    let file = file.ok_or((StatusCode::BAD_REQUEST, "Missing file".to_string()))?;
+    Ok((file, fields))
+}
+
+// 上传后返回给客户端的链接
+struct Links {
+    name: String,
+    url: String,
+    thumbnail_url: String,
+    deletion_url: String,
+}
+
+// 接收并保存单个文件，生成删除链接
+async fn upload_single(
+    state: &Arc<AppState>,
+    addr: &SocketAddr,
+    headers: &header::HeaderMap,
+    site: &'static str,
+    multipart: Multipart,
+) -> Result<Links, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let (file, fields) = receive_single(state, addr, token, site, multipart).await?;
+    let StoredImage { meta, .. } = store_files(state, addr, token, vec![file], fields)
+        .await?
+        .remove(0);
+
+    let key = random_string(32);
+    let mut config = state.write_config(site).await;
+    // 顺便清理已失效 (图片已删除、改名或被替换) 的删除链接
+    let stale: Vec<String> = config
+        .deletion_keys
+        .iter()
+        .filter(|(_, k)| {
+            config
+                .image_id(&k.name)
+                .is_none_or(|i| config.image(i).name != k.name || config.image(i).hash != k.hash)
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in stale {
+        config.deletion_keys.remove(&key);
+    }
+    config.deletion_keys.insert(
+        key.clone(),
+        DeletionKey {
+            name: meta.name.clone(),
+            hash: meta.hash.clone(),
+        },
+    );
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    let base = base_url(&config, headers);
+    Ok(Links {
+        url: format!("{}{}", base, meta.url(false, config.versioned_urls)),
+        thumbnail_url: format!("{}{}", base, meta.url(true, config.versioned_urls)),
+        deletion_url: format!("{}/sharex/delete/{}", base, key),
+        name: meta.name,
+    })
+}
+
+// ShareX 自定义上传器：
+//   RequestURL: https://<host>/sharex, Body: multipart/form-data, Headers: x-admin-token
+//   URL: {json:url}, ThumbnailURL: {json:thumbnail_url}, DeletionURL: {json:deletion_url}
+#[utoipa::path(
+    post,
+    path = "/sharex",
+    summary = "Upload one image (ShareX custom uploader)",
+    description = "Multipart fields `name`, `desc`, `tags` (comma-separated) and the file, which may use any field name (e.g. `sharex`). The returned URLs are absolute (public_url, or the request's Host and X-Forwarded-Proto).",
+    request_body(content_type = "multipart/form-data"),
+    responses(
+        (
+            status = 200,
+            description = "Links to the stored image; `deletion_url` is a single-use link that deletes it",
+            body = Object,
+            example = json!({"name": "cat.png", "url": "https://img.example.com/images/cat.png", "thumbnail_url": "https://img.example.com/images/cat.png?thumb=true", "deletion_url": "https://img.example.com/sharex/delete/KEY"}),
+        ),
+        (status = 400, description = "Missing file or more than one file"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 413, description = "File exceeds max_file_mb"),
+        (status = 415, description = "Not an accepted image format"),
+        (status = 503, description = "Image processing pool is busy"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn sharex_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    multipart: Multipart,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let links = upload_single(&state, &addr, &headers, "sharex_upload", multipart).await?;
+    Ok(Json(serde_json::json!({
+        "name": links.name,
+        "url": links.url,
+        "thumbnail_url": links.thumbnail_url,
+        "deletion_url": links.deletion_url,
+    })))
+}
+
+// PicGo (web-uploader 插件) / uPic 等图床客户端：
+//   POST /picgo，文件字段名任意，Headers: x-admin-token
+//   图片地址取 data.url (PicGo 的 JSON 路径 data.url，uPic 的 ["data", "url"])
+// 响应沿用 SM.MS 风格的 success / code / message / data，失败时同样返回 JSON，客户端可以显示 message
+#[utoipa::path(
+    post,
+    path = "/picgo",
+    summary = "Upload one image (PicGo / uPic)",
+    description = "Same upload as /sharex with an SM.MS-style response; errors keep their status code and are returned as JSON with success = false",
+    request_body(content_type = "multipart/form-data"),
+    responses(
+        (
+            status = 200,
+            description = "Links to the stored image; `delete` is a single-use deletion link, see /sharex/delete/{key}",
+            body = Object,
+            example = json!({"success": true, "code": "success", "message": "Upload success", "data": {"name": "cat.png", "url": "https://img.example.com/images/cat.png", "thumbnail_url": "https://img.example.com/images/cat.png?thumb=true", "markdown": "![cat.png](https://img.example.com/images/cat.png)", "html": "<img src=\"https://img.example.com/images/cat.png\" alt=\"cat.png\">", "delete": "https://img.example.com/sharex/delete/KEY"}}),
+        ),
+        (status = 400, description = "Missing file or more than one file"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 413, description = "File exceeds max_file_mb"),
+        (status = 415, description = "Not an accepted image format"),
+        (status = 503, description = "Image processing pool is busy"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn picgo_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    multipart: Multipart,
+) -> Response {
+    match upload_single(&state, &addr, &headers, "picgo_upload", multipart).await {
+        Ok(links) => {
+            let alt = escape_markdown(&links.name);
+            Json(serde_json::json!({
+                "success": true,
+                "code": "success",
+                "message": "Upload success",
+                "data": {
+                    "name": links.name,
+                    "url": links.url,
+                    "thumbnail_url": links.thumbnail_url,
+                    "markdown": format!("![{}]({})", alt, links.url),
+                    "html": format!(
+                        "<img src=\"{}\" alt=\"{}\">",
+                        escape_html(&links.url),
+                        escape_html(&links.name)
+                    ),
+                    "delete": links.deletion_url,
+                },
+            }))
+            .into_response()
+        }
+        Err((status, message)) => (
+            status,
+            Json(serde_json::json!({
+                "success": false,
+                "code": status.as_u16().to_string(),
+                "message": message,
+            })),
+        )
+            .into_response(),
+    }
+}
+
+// 删除链接在浏览器中打开时先显示确认页面，避免被聊天软件等的链接预览误删
+#[utoipa::path(
+    get,
+    path = "/sharex/delete/{key}",
+    summary = "Confirmation page for a deletion link",
+    params(
+        ("key" = String, Path),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "HTML page with a button that submits the deletion",
+            body = String,
+            content_type = "text/html",
+        ),
+        (status = 404, description = "Link not found"),
+    ),
+)]
+pub async fn sharex_delete_page(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(key): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    let config = state.read_config("sharex_delete_page").await;
+    check_ip(&config, &addr)?;
+    let deletion = config
+        .deletion_keys
+        .get(&key)
+        .ok_or((StatusCode::NOT_FOUND, "Link not found".to_string()))?;
+    let name = escape_html(&deletion.name);
+    Ok(Html(format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Delete {0}</title></head>\n\
+         <body>\n<p>Delete <b>{0}</b>?</p>\n<form method=\"post\"><button type=\"submit\">Delete</button></form>\n</body>\n</html>\n",
+        name
+    ))
+    .into_response())
+}
+
+// 通过删除链接删除图片；链接只能使用一次
+#[utoipa::path(
+    method(post, delete),
+    path = "/sharex/delete/{key}",
+    summary = "Delete the image through a deletion link",
+    params(
+        ("key" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "Deleted"),
+        (status = 404, description = "Link not found"),
+        (status = 410, description = "Image was already deleted or replaced; the link is consumed"),
+    ),
+)]
+pub async fn sharex_delete(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(key): Path<String>,
+) -> Result<&'static str, (StatusCode, String)> {
+    let mut config = state.write_config("sharex_delete").await;
+    check_ip(&config, &addr)?;
+    let deletion = config
+        .deletion_keys
+        .remove(&key)
+        .ok_or((StatusCode::NOT_FOUND, "Link not found".to_string()))?;
+
+    let current = config
+        .image_id(&deletion.name)
+        .map(|i| config.image(i))
+        .is_some_and(|img| img.name == deletion.name && img.hash == deletion.hash);
+    let hashes = match current {
+        true => config.remove_image(&deletion.name),
+        false => None,
+    };
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+    let Some(hashes) = hashes else {
+        return Err((
+            StatusCode::GONE,
+            "Image was already deleted or replaced".to_string(),
+        ));
+    };
+    remove_unused_blobs(&config, &hashes).await;
+    state.stats.deletes.fetch_add(1, Ordering::Relaxed);
+
+    info!(
+        "addr: {:?}, action: delete, name: {:?} (deletion link)",
+        addr, deletion.name
+    );
+    Ok("Deleted")
+}
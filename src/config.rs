@@ -1,8 +1,29 @@
-use std::{collections::HashSet, fs, path::PathBuf, sync::LazyLock as Lazy, sync::OnceLock};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::PathBuf,
+    sync::LazyLock as Lazy,
+    sync::OnceLock,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use config_file2::{LoadConfigFile, StoreConfigFile};
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use sha2::{Digest, Sha256};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, mpsc};
+
+use crate::{
+    album, catalog,
+    id::IdStrategy,
+    imaging::DecodeLimits,
+    logging::LogFormat,
+    migrate,
+    pool::ProcessingPool,
+    report::ReportInterval,
+    stats::Stats,
+    storage::{BlobKey, free_space},
+    usage::{self, Usage},
+};
 
 pub static CONFIG_DIR: Lazy<PathBuf> = Lazy::new(|| {
     let dir = home::home_dir()
@@ -15,40 +36,812 @@ pub static CONFIG_DIR: Lazy<PathBuf> = Lazy::new(|| {
 
 // --- 1. 配置与数据结构 ---
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, utoipa::ToSchema)]
 pub struct ImageMeta {
     pub name: String,
     pub desc: String,
+    /// SHA256 of the content
     pub hash: String,
+    // 文件大小 (字节)，旧记录为 0
+    #[serde(default)]
+    pub size: u64,
     #[serde(default = "chrono::Utc::now")]
     pub created_at: chrono::DateTime<chrono::Utc>,
+    // EXIF 中的拍摄时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captured_at: Option<chrono::DateTime<chrono::Utc>>,
+    // 根据文件头识别的 MIME 类型，下载时作为 Content-Type
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    // 重复内容以其他名称上传时记录的别名 (alias_duplicates 开启时)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    // 标签，已去除首尾空白并去重
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    // 置顶：定期读取以保持在系统页缓存中
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    // 上传者 token 的指纹，用于按 token 统计用量
+    /// Uploader token fingerprint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploaded_by: Option<String>,
+    // 描述中已失效的来源链接，由定期的链接检查维护
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub broken_sources: Vec<String>,
+    // 所属相册，相册 token 只能读取其中的图片
+    /// Album the image belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub album: Option<String>,
+    // 私有图片不出现在公开列表中，只有管理员或持有其所在相册 token 的请求可以下载
+    /// Hidden from listings; readable only with an admin token or a token for its album
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+    // 以相同名称重新上传前的历史版本，按上传顺序排列；当前版本即记录本身
+    /// Earlier versions, oldest first; the record itself is the current version
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub versions: Vec<ImageVersion>,
+    // 缩略图生成时计算的 BlurHash 占位图
+    /// BlurHash placeholder computed from the thumbnail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    // 上传时计算的感知哈希 (dHash，16 位 hex)，用于发现重新编码过的重复图片
+    /// Perceptual hash (64-bit dHash, hex) used to find visually identical images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+    // 被内容审核标记时的隔离原因；隔离中的图片只对管理员可见，等待审核
+    /// Quarantine reason; the image awaits moderation review and is only visible to admins
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quarantined: Option<String>,
+    // 缩略图仍在后台队列中等待生成
+    /// The thumbnail is still queued for background generation
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub thumbnail_pending: bool,
 }
 
+// 图片的一个历史版本
+#[derive(Debug, Clone, Serialize, Deserialize, utoipa::ToSchema)]
+pub struct ImageVersion {
+    pub hash: String,
+    pub size: u64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub captured_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uploaded_by: Option<String>,
+    /// BlurHash placeholder computed from the thumbnail
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// Perceptual hash (64-bit dHash, hex) used to find visually identical images
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+}
+
+// 感知哈希相近的图片 (视觉上相同) 已存在时的处理方式
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarImages {
+    // 不检查
+    Off,
+    // 照常保存，在上传结果的 similar 中列出相近的图片
+    #[default]
+    Warn,
+    // 拒绝上传 (409)
+    Reject,
+}
+
+// 生成 32 位的随机字母数字 token
+pub fn generate_token() -> String {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    (0..32)
+        .map(|_| CHARS[rand::random_range(0..CHARS.len())] as char)
+        .collect()
+}
+
+// token 指纹：SHA256 的前 12 位 hex，可以安全地出现在元数据和日志中
+pub fn token_fingerprint(token: &str) -> String {
+    let digest = Sha256::digest(token.as_bytes());
+    hex::encode(&digest[..6])
+}
+
+impl ImageMeta {
+    // 不是私有图片且不在隔离中，任何人都可以读取
+    pub fn is_public(&self) -> bool {
+        !self.private && self.quarantined.is_none()
+    }
+
+    // 当前版本号，从 1 开始
+    pub fn version(&self) -> usize {
+        self.versions.len() + 1
+    }
+
+    // 将当前内容归档为历史版本，并替换为新上传的内容
+    pub fn push_version(&mut self, current: ImageVersion) {
+        let previous = ImageVersion {
+            hash: std::mem::replace(&mut self.hash, current.hash),
+            size: std::mem::replace(&mut self.size, current.size),
+            created_at: std::mem::replace(&mut self.created_at, current.created_at),
+            captured_at: std::mem::replace(&mut self.captured_at, current.captured_at),
+            content_type: std::mem::replace(&mut self.content_type, current.content_type),
+            uploaded_by: std::mem::replace(&mut self.uploaded_by, current.uploaded_by),
+            blurhash: std::mem::replace(&mut self.blurhash, current.blurhash),
+            phash: std::mem::replace(&mut self.phash, current.phash),
+        };
+        self.versions.push(previous);
+    }
+
+    // 指定版本的 Hash 和 MIME 类型；版本号超出范围时返回 None
+    pub fn version_blob(&self, version: usize) -> Option<(&str, Option<&str>)> {
+        if version == self.version() {
+            return Some((&self.hash, self.content_type.as_deref()));
+        }
+        let v = self.versions.get(version.checked_sub(1)?)?;
+        Some((&v.hash, v.content_type.as_deref()))
+    }
+
+    // 名称或别名是否匹配
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|a| a == name)
+    }
+
+    // 下载地址，名称经过百分号编码；versioned 时附加内容版本 (Hash 前 8 位)，同名图片被替换后浏览器不会沿用旧缓存
+    pub fn url(&self, thumb: bool, versioned: bool) -> String {
+        let mut params = Vec::new();
+        if thumb {
+            params.push("thumb=true".to_string());
+        }
+        if versioned {
+            params.push(format!("v={}", &self.hash[..self.hash.len().min(8)]));
+        }
+        let path = format!("/images/{}", album::encode(&self.name));
+        match params.is_empty() {
+            true => path,
+            false => format!("{}?{}", path, params.join("&")),
+        }
+    }
+
+    // 名称、别名或描述是否包含 query (query 需为小写)
+    pub fn matches(&self, query: &str) -> bool {
+        std::iter::once(&self.name)
+            .chain(&self.aliases)
+            .chain(std::iter::once(&self.desc))
+            .any(|s| s.to_lowercase().contains(query))
+    }
+
+    // 合并标签，忽略空标签和已有的标签
+    pub fn add_tags<I: IntoIterator<Item = String>>(&mut self, tags: I) {
+        for tag in tags {
+            let tag = tag.trim();
+            if !tag.is_empty() && !self.tags.iter().any(|t| t == tag) {
+                self.tags.push(tag.to_string());
+            }
+        }
+    }
+}
+
+// 一次性下载链接
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OneTimeLink {
+    pub hash: String,
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+    // 已被使用的链接保留到过期，以便返回 410 而不是 404
+    #[serde(default)]
+    pub used: bool,
+    // 正在下载，完整发送后才标记为 used；中断时恢复，可以重新下载
+    #[serde(skip)]
+    pub downloading: bool,
+}
+
+// 上传时签发的删除链接，持有者无需 admin token 即可删除该图片；图片被替换或改名后失效
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DeletionKey {
+    pub name: String,
+    pub hash: String,
+}
+
+// token 的附加信息，没有记录的 token 视为永久有效
 #[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // 被轮换替代后的失效时间，在此之前旧 token 仍然有效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// 相册的只读 token，持有者可以读取相册中的私有图片
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AlbumToken {
+    pub album: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    // 未设置时永久有效，直到被撤销
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(default)]
 pub struct AppConfig {
+    // 配置文件与数据格式的版本，旧版本在加载时 (或通过 migrate 命令) 升级
+    pub schema_version: u32,
     pub data_dir: PathBuf,
     pub max_size_mb: usize,
+    // 单个文件的大小上限 (MB)，上传时边接收边检查；未设置时与 max_size_mb 相同
+    pub max_file_mb: Option<usize>,
+    // data_dir 所在文件系统的剩余空间低于该值 (MB) 时拒绝上传 (507)，为 0 时不检查
+    pub min_free_mb: u64,
     pub tokens: HashSet<String>,
+    // token 的标签与轮换信息，key 为 token
+    pub token_info: HashMap<String, TokenInfo>,
+    // 轮换 token 时旧 token 继续有效的时间 (小时)
+    pub token_grace_hours: u64,
     pub blacklist: HashSet<String>,
-    pub images: Vec<ImageMeta>,
+    // 图片记录单独保存在 <data_dir>/images.jsonl (见 catalog)，不再写入配置文件
+    // 旧版本配置文件中的记录在加载时迁移 (见 migrate)
+    // 只能通过 push_image、update_image 等方法修改，以便更新索引并记录需要保存的修改
+    #[serde(skip)]
+    images: catalog::Records,
+    // 修改过但尚未保存的记录
+    #[serde(skip)]
+    changes: catalog::Changes,
     pub thumbnail_pixels: Option<u32>,
+    // 解码 (生成缩略图、转换、裁剪等) 前检查的像素数和边长上限，超出时跳过处理
+    pub max_pixels: u64,
+    pub max_dimension: u32,
+    // 缩略图使用渐进式 JPEG / 隔行 PNG，慢速网络下可逐步显示
+    pub progressive_thumbnails: bool,
+    // 客户端 Accept 支持时缩略图转换成的格式 (按优先级)，为空时不转换
+    pub thumbnail_formats: Vec<String>,
+    // 客户端 Accept 支持时 JPEG/PNG 原图转换成的格式 (按优先级)，为空时不转换
+    // 新上传的图片会在后台预先生成这些格式的副本
+    pub original_formats: Vec<String>,
+    // 允许上传的图片格式 (扩展名)，按文件头识别，其他内容返回 415
+    pub allowed_formats: Vec<String>,
+    // 上传时去除 JPEG 的 EXIF/XMP 元数据 (含 GPS 位置)，保留方向信息；可被单次上传覆盖
+    pub strip_metadata: bool,
+    // 上传后在后台对 PNG/JPEG 做无损压缩优化，记录改为指向更小的 blob
+    pub optimize_uploads: bool,
+    // 格式副本缓存的总大小上限 (MB)，超出时按最近访问时间淘汰；未设置时不限制
+    pub max_variants_mb: Option<u64>,
+    // 列表接口的默认每页数量和上限
+    pub page_size: usize,
+    pub max_page_size: usize,
+    // 上传未提供 name 时自动生成标识符的策略
+    pub id_strategy: IdStrategy,
+    // sequential 策略使用的自增计数
+    pub id_sequence: u64,
+    // 重复内容以新名称上传时，记录为已有记录的别名而不是新建记录
+    pub alias_duplicates: bool,
+    // 与已有图片的感知哈希相近 (汉明距离不超过 similar_distance) 时的处理方式
+    pub similar_images: SimilarImages,
+    pub similar_distance: u32,
+    // 接口返回的下载地址附加 ?v=<内容版本>，用于缓存失效
+    pub versioned_urls: bool,
+    // 提供 /openapi.json 和 /docs (Swagger UI)
+    pub openapi_docs: bool,
+    // 在 /metrics 提供 Prometheus 格式的指标
+    pub metrics: bool,
+    // 静态加密密钥 (64 位 hex)，或存放密钥的文件路径；两者都未设置时不加密
+    pub encryption_key: Option<String>,
+    pub encryption_key_file: Option<PathBuf>,
+    // 由上面两项解析得到的密钥
+    #[serde(skip)]
+    pub blob_key: Option<BlobKey>,
+    // 检查描述中来源链接的间隔 (小时)，未设置时不检查
+    pub link_check_interval_hours: Option<u64>,
+    // 置顶图片的预热间隔 (秒) 和置顶集合的总大小上限 (MB)
+    pub pin_interval_secs: u64,
+    pub max_pinned_mb: u64,
+    // 上游 (主节点) 地址，本地缺失的 blob 从上游拉取并缓存
+    pub upstream: Option<String>,
+    // 图片处理池的并发数 (未设置时为 CPU 核数) 和请求排队数上限，超出时返回 503；修改后需重启
+    pub processing_workers: Option<usize>,
+    pub processing_queue: usize,
+    // 下载时每次从磁盘读取的大小和写入 blob (上传、拉取上游) 的缓冲区大小 (KB)；修改后需重启
+    pub read_buffer_kb: usize,
+    pub write_buffer_kb: usize,
+    // 内容审核服务地址，新上传的内容公开前先发送审核；未设置时不审核
+    pub moderation_url: Option<String>,
+    // gRPC 接口监听地址，需要以 grpc feature 编译；未设置时不启动
+    pub grpc_addr: Option<String>,
+    // OTLP/HTTP collector 地址，请求和上传各阶段的 span 导出到这里；需要以 otel feature 编译
+    pub otlp_endpoint: Option<String>,
+    // Sentry 兼容服务的 DSN，panic 和返回 5xx 的请求上报到这里；未设置时不上报
+    pub sentry_dsn: Option<String>,
+    // 日志格式 (text / json)，可被环境变量 IMG_SERVER_LOG_FORMAT 覆盖
+    pub log_format: LogFormat,
+    // 访问日志 (每个请求一行，combined 格式) 单独写入 <data_dir>/logs/access/
+    pub access_log: bool,
+    // 元数据写入方式：开启 write_through 时每次修改都在请求中同步写入磁盘；
+    // 否则只标记为待写入，由后台每 persist_interval_ms 合并写入一次，关闭服务时写入剩余的修改
+    pub write_through: bool,
+    pub persist_interval_ms: u64,
+    // 关闭服务时等待进行中请求 (含元数据写入和 blob 移动) 完成的最长时间 (秒)
+    pub shutdown_timeout_secs: u64,
+    // 处理时间超过该值 (毫秒) 的请求记录一条警告，为 0 时不记录
+    pub slow_request_ms: u64,
+    // 定期摘要报告的周期 (daily / weekly)，未设置时不生成
+    // 报告写入日志，设置了 report_webhook 时同时以 JSON POST 到该地址
+    pub report_interval: Option<ReportInterval>,
+    pub report_webhook: Option<String>,
+    // 分块上传会话超过该时间 (小时) 未完成时，在创建新会话时清理
+    pub upload_session_ttl_hours: u64,
+    // 对外的访问地址 (例如 https://img.example.com)，兼容接口据此返回完整的 URL；未设置时按请求的 Host 头推断
+    pub public_url: Option<String>,
+    // 一次性下载链接，key 为链接 token
+    pub one_time_links: HashMap<String, OneTimeLink>,
+    // 相册的只读 token，key 为 token
+    pub album_tokens: HashMap<String, AlbumToken>,
+    // 兼容接口 (ShareX) 上传时返回的删除链接，key 为链接中的密钥
+    pub deletion_keys: HashMap<String, DeletionKey>,
+    // images 的名称和 Hash 索引
+    #[serde(skip)]
+    index: ImageIndex,
+}
+
+// 图片记录的索引，避免按名称或 Hash 查找时遍历所有记录
+// 由 push_image、update_image 等修改记录的方法同步更新
+#[derive(Debug, Default)]
+struct ImageIndex {
+    // 名称和别名 → 记录 id
+    names: HashMap<String, u64>,
+    // 当前版本的 Hash → 记录 id
+    current: HashMap<String, BTreeSet<u64>>,
+    // Hash → 引用它的记录 id (含历史版本)
+    refs: HashMap<String, BTreeSet<u64>>,
+}
+
+impl ImageIndex {
+    fn insert(&mut self, id: u64, meta: &ImageMeta) {
+        for name in std::iter::once(&meta.name).chain(&meta.aliases) {
+            self.names.insert(name.clone(), id);
+        }
+        self.current
+            .entry(meta.hash.clone())
+            .or_default()
+            .insert(id);
+        for hash in std::iter::once(&meta.hash).chain(meta.versions.iter().map(|v| &v.hash)) {
+            self.refs.entry(hash.clone()).or_default().insert(id);
+        }
+    }
+
+    fn remove(&mut self, id: u64, meta: &ImageMeta) {
+        for name in std::iter::once(&meta.name).chain(&meta.aliases) {
+            if self.names.get(name) == Some(&id) {
+                self.names.remove(name);
+            }
+        }
+        remove_id(&mut self.current, &meta.hash, id);
+        for hash in std::iter::once(&meta.hash).chain(meta.versions.iter().map(|v| &v.hash)) {
+            remove_id(&mut self.refs, hash, id);
+        }
+    }
+}
+
+fn remove_id(map: &mut HashMap<String, BTreeSet<u64>>, hash: &str, id: u64) {
+    if let Some(ids) = map.get_mut(hash) {
+        ids.remove(&id);
+        if ids.is_empty() {
+            map.remove(hash);
+        }
+    }
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
+            schema_version: migrate::CURRENT_VERSION,
             data_dir: PathBuf::from("data"),
             max_size_mb: 20,
+            max_file_mb: None,
+            min_free_mb: 512,
             tokens: HashSet::new(),
+            token_info: HashMap::new(),
+            token_grace_hours: 24,
             blacklist: HashSet::new(),
-            images: Vec::new(),
+            images: catalog::Records::new(),
+            changes: catalog::Changes::default(),
             thumbnail_pixels: Some(50000),
+            max_pixels: 100_000_000,
+            max_dimension: 32768,
+            progressive_thumbnails: true,
+            thumbnail_formats: vec!["webp".to_string()],
+            original_formats: Vec::new(),
+            allowed_formats: [
+                "jpeg", "png", "gif", "webp", "avif", "bmp", "tiff", "ico", "cr2", "nef", "arw",
+                "mp4", "webm",
+            ]
+            .into_iter()
+            .chain(cfg!(feature = "heic").then_some("heic"))
+            .map(String::from)
+            .collect(),
+            strip_metadata: true,
+            optimize_uploads: false,
+            max_variants_mb: None,
+            page_size: 20,
+            max_page_size: 100,
+            id_strategy: IdStrategy::default(),
+            id_sequence: 0,
+            alias_duplicates: false,
+            similar_images: SimilarImages::default(),
+            similar_distance: 4,
+            versioned_urls: false,
+            openapi_docs: false,
+            metrics: false,
+            encryption_key: None,
+            encryption_key_file: None,
+            blob_key: None,
+            link_check_interval_hours: None,
+            pin_interval_secs: 300,
+            max_pinned_mb: 256,
+            upstream: None,
+            processing_workers: None,
+            processing_queue: 64,
+            read_buffer_kb: 64,
+            write_buffer_kb: 256,
+            moderation_url: None,
+            grpc_addr: None,
+            otlp_endpoint: None,
+            sentry_dsn: None,
+            log_format: LogFormat::default(),
+            access_log: true,
+            write_through: false,
+            persist_interval_ms: 1000,
+            shutdown_timeout_secs: 30,
+            slow_request_ms: 5000,
+            report_interval: None,
+            report_webhook: None,
+            upload_session_ttl_hours: 24,
+            public_url: None,
+            one_time_links: HashMap::new(),
+            album_tokens: HashMap::new(),
+            deletion_keys: HashMap::new(),
+            index: ImageIndex::default(),
         }
     }
 }
 
 impl AppConfig {
+    // 按配置的策略生成一个未被占用的图片名称
+    pub fn next_image_name(&mut self) -> String {
+        let generator = self.id_strategy.generator();
+        loop {
+            let id = generator.generate(self.id_sequence);
+            self.id_sequence += 1;
+            if self.image_id(&id).is_none() {
+                return id;
+            }
+        }
+    }
+
+    // 为 label 签发新 token，该 label 原有的 token 在 grace 之后失效
+    // 返回新 token 以及旧 token 的失效时间 (label 之前没有 token 时为 None)
+    pub fn rotate_token(
+        &mut self,
+        label: &str,
+        grace: chrono::Duration,
+    ) -> (String, Option<chrono::DateTime<chrono::Utc>>) {
+        self.prune_expired_tokens();
+        let now = chrono::Utc::now();
+        let expires_at = now + grace;
+        let mut rotated = false;
+        for (token, info) in self.token_info.iter_mut() {
+            if info.label.as_deref() == Some(label)
+                && info.expires_at.is_none()
+                && self.tokens.contains(token)
+            {
+                info.expires_at = Some(expires_at);
+                rotated = true;
+            }
+        }
+
+        let token = generate_token();
+        self.tokens.insert(token.clone());
+        self.token_info.insert(
+            token.clone(),
+            TokenInfo {
+                label: Some(label.to_string()),
+                created_at: now,
+                expires_at: None,
+            },
+        );
+        (token, rotated.then_some(expires_at))
+    }
+
+    // 删除已过宽限期的旧 token
+    pub fn prune_expired_tokens(&mut self) {
+        let now = chrono::Utc::now();
+        let tokens = &mut self.tokens;
+        self.token_info.retain(|token, info| {
+            let expired = info.expires_at.is_some_and(|t| t <= now);
+            if expired {
+                tokens.remove(token);
+            }
+            !expired
+        });
+    }
+
+    // 解析静态加密密钥，密钥文件优先
+    pub(crate) fn resolve_blob_key(&self) -> anyhow::Result<Option<BlobKey>> {
+        if let Some(path) = &self.encryption_key_file {
+            return Ok(Some(BlobKey::from_hex(&fs::read_to_string(path)?)?));
+        }
+        self.encryption_key
+            .as_deref()
+            .map(BlobKey::from_hex)
+            .transpose()
+    }
+
+    // 在 base 后追加数字后缀，直到名称未被占用
+    pub fn unique_name(&self, base: &str) -> String {
+        (1..)
+            .map(|i| format!("{}-{}", base, i))
+            .find(|name| self.image_id(name).is_none())
+            .expect("unbounded range")
+    }
+
+    // 将记录 id 的名称或别名 old 改为 new，调用方需保证 new 未被占用
+    pub fn rename_image(&mut self, id: u64, old: &str, new: String) {
+        self.update_image(id, |img| {
+            if img.name == old {
+                img.name = new;
+            } else if let Some(alias) = img.aliases.iter_mut().find(|a| *a == old) {
+                *alias = new;
+            }
+        })
+    }
+
+    // 不重复的 blob 数量和总字节数；多条记录 (含历史版本) 共享同一 blob 时只计算一次
+    pub fn blob_usage(&self) -> (usize, u64) {
+        let mut blobs: HashMap<&str, u64> = HashMap::new();
+        for img in self.images.values() {
+            blobs.insert(&img.hash, img.size);
+            for v in &img.versions {
+                blobs.insert(&v.hash, v.size);
+            }
+        }
+        (blobs.len(), blobs.values().sum())
+    }
+
+    // 重建图片索引 (加载记录后)
+    fn reindex(&mut self) {
+        let mut index = ImageIndex::default();
+        for (&id, meta) in &self.images {
+            index.insert(id, meta);
+        }
+        self.index = index;
+    }
+
+    // 所有图片记录，按加入的顺序
+    pub fn images(&self) -> std::collections::btree_map::Values<'_, u64, ImageMeta> {
+        self.images.values()
+    }
+
+    // 按 id 取图片记录，id 需来自 image_id 等查找
+    pub fn image(&self, id: u64) -> &ImageMeta {
+        &self.images[&id]
+    }
+
+    // 追加一条记录并更新索引，返回分配的 id
+    pub fn push_image(&mut self, meta: ImageMeta) -> u64 {
+        let id = self.images.last_key_value().map_or(1, |(id, _)| id + 1);
+        self.index.insert(id, &meta);
+        self.images.insert(id, meta);
+        self.changes.mark(id);
+        id
+    }
+
+    // 修改记录 id，记录为需要保存
+    pub fn update_image<R>(&mut self, id: u64, f: impl FnOnce(&mut ImageMeta) -> R) -> R {
+        let meta = self.images.get_mut(&id).expect("image id from a lookup");
+        self.index.remove(id, meta);
+        let result = f(meta);
+        self.index.insert(id, meta);
+        self.changes.mark(id);
+        result
+    }
+
+    // 依次修改 ids 中的记录，f 返回是否有修改；返回修改的记录数
+    fn update_ids(
+        &mut self,
+        ids: impl IntoIterator<Item = u64>,
+        mut f: impl FnMut(&mut ImageMeta) -> bool,
+    ) -> usize {
+        let mut updated = 0;
+        for id in ids {
+            let Some(meta) = self.images.get_mut(&id) else {
+                continue;
+            };
+            self.index.remove(id, meta);
+            if f(meta) {
+                self.changes.mark(id);
+                updated += 1;
+            }
+            self.index.insert(id, meta);
+        }
+        updated
+    }
+
+    // 依次修改每条记录，f 返回是否有修改；返回修改的记录数
+    pub fn update_images(&mut self, f: impl FnMut(&mut ImageMeta) -> bool) -> usize {
+        let ids: Vec<u64> = self.images.keys().copied().collect();
+        self.update_ids(ids, f)
+    }
+
+    // 修改引用该 Hash (含历史版本) 的记录，同 update_images
+    pub fn update_blob(&mut self, hash: &str, f: impl FnMut(&mut ImageMeta) -> bool) -> usize {
+        let ids: Vec<u64> = self
+            .index
+            .refs
+            .get(hash)
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect();
+        self.update_ids(ids, f)
+    }
+
+    // 只保留 f 返回 true 的记录
+    pub fn retain_images(&mut self, mut f: impl FnMut(&ImageMeta) -> bool) {
+        let (changes, index) = (&mut self.changes, &mut self.index);
+        self.images.retain(|&id, meta| {
+            let keep = f(meta);
+            if !keep {
+                index.remove(id, meta);
+                changes.mark(id);
+            }
+            keep
+        });
+    }
+
+    // 按名称或别名查找图片记录的 id
+    pub fn image_id(&self, name: &str) -> Option<u64> {
+        self.index.names.get(name).copied()
+    }
+
+    // 当前版本为该 Hash 的第一条记录的 id
+    pub fn hash_id(&self, hash: &str) -> Option<u64> {
+        self.index.current.get(hash)?.first().copied()
+    }
+
+    // 当前版本为该 Hash 的记录
+    pub fn images_with_hash(&self, hash: &str) -> impl Iterator<Item = &ImageMeta> {
+        self.index
+            .current
+            .get(hash)
+            .into_iter()
+            .flatten()
+            .map(|id| &self.images[id])
+    }
+
+    // 按名称、别名或 Hash 查找图片记录
+    pub fn find_image(&self, id: &str) -> Option<&ImageMeta> {
+        let id = self.image_id(id).or_else(|| self.hash_id(id))?;
+        self.images.get(&id)
+    }
+
+    // 相册 token 是否允许读取该图片：token 未过期，图片在其相册中且不在隔离中
+    pub fn album_token_allows(&self, token: &str, img: &ImageMeta) -> bool {
+        self.album_tokens.get(token).is_some_and(|t| {
+            t.expires_at.is_none_or(|e| e > chrono::Utc::now())
+                && img.album.as_deref() == Some(t.album.as_str())
+                && img.quarantined.is_none()
+        })
+    }
+
+    // 置顶集合的总大小 (字节)，相同内容只计一次
+    pub fn pinned_size(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.images
+            .values()
+            .filter(|i| i.pinned && seen.insert(i.hash.as_str()))
+            .map(|i| i.size)
+            .sum()
+    }
+
+    // 是否还有记录 (含历史版本) 引用该 Hash
+    pub fn hash_in_use(&self, hash: &str) -> bool {
+        self.index.refs.contains_key(hash)
+    }
+
+    // 单个文件的大小上限 (字节)
+    pub fn max_file_bytes(&self) -> u64 {
+        self.max_file_mb.unwrap_or(self.max_size_mb) as u64 * 1024 * 1024
+    }
+
+    // 剩余空间不足 min_free_mb 时返回当前剩余空间 (字节)；无法获取时不拒绝
+    pub fn low_disk_space(&self) -> Option<u64> {
+        if self.min_free_mb == 0 {
+            return None;
+        }
+        let free = free_space(&self.data_dir).ok().flatten()?;
+        (free < self.min_free_mb * 1024 * 1024).then_some(free)
+    }
+
+    pub fn decode_limits(&self) -> DecodeLimits {
+        DecodeLimits {
+            max_pixels: self.max_pixels,
+            max_dimension: self.max_dimension,
+        }
+    }
+
+    // 引用该 Hash 的记录 (含历史版本) 已计算的 BlurHash
+    pub fn blurhash_of(&self, hash: &str) -> Option<String> {
+        let ids = self.index.refs.get(hash)?;
+        ids.iter().map(|id| &self.images[id]).find_map(|i| {
+            std::iter::once((&i.hash, &i.blurhash))
+                .chain(i.versions.iter().map(|v| (&v.hash, &v.blurhash)))
+                .find_map(|(h, b)| b.clone().filter(|_| h == hash))
+        })
+    }
+
+    // 感知哈希与 phash 相近的记录名称 (只比较当前版本)，内容完全相同 (Hash 为 hash) 的记录除外
+    // 纯色等没有明暗变化的图片指纹为 0，彼此之间并不相同，不参与比较
+    pub fn similar_images(&self, phash: u64, hash: &str) -> Vec<String> {
+        if phash == 0 {
+            return Vec::new();
+        }
+        self.images
+            .values()
+            .filter(|i| i.hash != hash)
+            .filter(|i| {
+                i.phash
+                    .as_deref()
+                    .and_then(|p| u64::from_str_radix(p, 16).ok())
+                    .is_some_and(|p| (p ^ phash).count_ones() <= self.similar_distance)
+            })
+            .map(|i| i.name.clone())
+            .collect()
+    }
+
+    // 该 Hash 是否只属于不公开 (私有或隔离中) 的记录 (当前版本)，此时不对外提供匿名下载
+    pub fn hidden_hash(&self, hash: &str) -> bool {
+        let mut owners = self.images_with_hash(hash).peekable();
+        owners.peek().is_some() && owners.all(|i| !i.is_public())
+    }
+
+    // 按名称、别名或 Hash 删除记录，返回被移除记录的 Hash；找不到时返回 None
+    // 删除别名只移除别名本身；原记录仍有别名时，将第一个别名提升为记录名称
+    // 按 Hash 删除时移除所有引用该 Hash 的记录
+    pub fn remove_image(&mut self, id: &str) -> Option<Vec<String>> {
+        let Some(key) = self.image_id(id) else {
+            let ids = self.index.current.get(id)?.clone();
+            let mut hashes = Vec::new();
+            for key in ids {
+                hashes.extend(self.take_image(key));
+            }
+            return Some(hashes);
+        };
+
+        let img = &self.images[&key];
+        if img.name != id {
+            self.update_image(key, |img| img.aliases.retain(|a| a != id));
+            Some(Vec::new())
+        } else if !img.aliases.is_empty() {
+            self.update_image(key, |img| img.name = img.aliases.remove(0));
+            Some(Vec::new())
+        } else {
+            Some(self.take_image(key))
+        }
+    }
+
+    // 移除记录 id，返回它引用的 Hash
+    fn take_image(&mut self, id: u64) -> Vec<String> {
+        let img = self.images.remove(&id).expect("image id from a lookup");
+        self.index.remove(id, &img);
+        self.changes.mark(id);
+        std::iter::once(img.hash)
+            .chain(img.versions.into_iter().map(|v| v.hash))
+            .collect()
+    }
+
     pub fn images_dir(&self) -> &PathBuf {
         static IMAGES_DIR: OnceLock<PathBuf> = OnceLock::new();
         IMAGES_DIR.get_or_init(|| self.data_dir.join("images"))
@@ -64,6 +857,41 @@ impl AppConfig {
         TEMP_DIR.get_or_init(|| self.data_dir.join("temp"))
     }
 
+    // 转换格式后的副本 (例如 WebP 缩略图)
+    pub fn variants_dir(&self) -> &PathBuf {
+        static VARIANTS_DIR: OnceLock<PathBuf> = OnceLock::new();
+        VARIANTS_DIR.get_or_init(|| self.data_dir.join("variants"))
+    }
+
+    // 副本文件名：<hash>.<kind>.<ext>
+    pub fn variant_path(&self, hash: &str, kind: &str, format: image::ImageFormat) -> PathBuf {
+        let ext = format.extensions_str().first().copied().unwrap_or("bin");
+        self.variants_dir()
+            .join(format!("{}.{}.{}", hash, kind, ext))
+    }
+
+    // 某个 Hash 的所有副本
+    pub fn variants_of(&self, hash: &str) -> Vec<PathBuf> {
+        let prefix = format!("{}.", hash);
+        fs::read_dir(self.variants_dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|e| e.path())
+            .collect()
+    }
+
+    // 图片记录日志
+    pub fn images_file(&self) -> PathBuf {
+        self.data_dir.join(catalog::FILE_NAME)
+    }
+
+    // 用量统计日志
+    pub fn usage_file(&self) -> PathBuf {
+        self.data_dir.join(usage::FILE_NAME)
+    }
+
     pub fn logs_dir(&self) -> &PathBuf {
         static LOG_DIR: OnceLock<PathBuf> = OnceLock::new();
         LOG_DIR.get_or_init(|| self.data_dir.join("logs"))
@@ -73,20 +901,275 @@ impl AppConfig {
 pub struct AppState {
     pub config: RwLock<AppConfig>,
     pub config_path: PathBuf,
+    pub stats: Stats,
+    // 按 (月份, token) 持久化的上传和下载用量
+    pub usage: Usage,
+    // 后台缩略图队列，由 tasks::thumbnail_worker 处理；附带加入队列时的 span，用于关联到上传请求
+    pub thumbnails: mpsc::UnboundedSender<(String, tracing::Span)>,
+    // 图片处理 (解码、转换、缩略图等) 的工作线程池
+    pub pool: ProcessingPool,
+    // 有尚未写入磁盘的元数据修改 (未开启 write_through 时)
+    pub unsaved: AtomicBool,
+}
+
+impl AppState {
+    // 将 blob 加入后台缩略图队列；队列只会在服务退出时关闭，此时直接丢弃
+    pub fn queue_thumbnail(&self, hash: String) {
+        if self
+            .thumbnails
+            .send((hash, tracing::Span::current()))
+            .is_ok()
+        {
+            self.stats.thumbnail_queue.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    // 持久化修改后的配置：write_through 时立即写入，否则交给 tasks::persist_loop 合并写入
+    #[tracing::instrument(
+        level = "debug",
+        name = "config_save",
+        skip_all,
+        fields(write_through = config.write_through)
+    )]
+    pub fn persist(&self, config: &AppConfig) -> anyhow::Result<()> {
+        if config.write_through {
+            self.unsaved.store(false, Ordering::Relaxed);
+            save_config(&self.config_path, config)
+        } else {
+            self.unsaved.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    // 写入尚未保存的修改和用量；失败时保留标记，下次重试
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let usage = self.usage.flush();
+        if self.unsaved.swap(false, Ordering::Relaxed) {
+            let config = self.read_config("flush").await;
+            let _span = tracing::debug_span!("config_flush").entered();
+            save_config(&self.config_path, &config).inspect_err(|_| {
+                self.unsaved.store(true, Ordering::Relaxed);
+            })?;
+        }
+        usage
+    }
+
+    // 重新读取配置文件中的设置，图片记录等运行时数据保持不变；返回被修改的设置项
+    // 与上次写入相比，文件中被修改的项使用文件的值，其余保留内存中的值 (可能有尚未写入的修改)
+    // 文件无法解析或修改了 LOCKED_SETTINGS 时不做任何修改
+    pub async fn reload(&self) -> anyhow::Result<Vec<String>> {
+        let mut config = self.write_config("reload").await;
+        let file = AppConfig::load(&self.config_path)?
+            .ok_or_else(|| anyhow::anyhow!("{:?} does not exist", self.config_path))?;
+        let file_settings = settings_of(&file)?;
+        let base = match SAVED_SETTINGS.lock().unwrap().get(&self.config_path) {
+            Some(saved) => serde_json::from_str(saved)?,
+            None => serde_json::to_value(&*config)?,
+        };
+
+        let mut merged = serde_json::to_value(&*config)?;
+        let mut changed = Vec::new();
+        if let (Some(merged), serde_json::Value::Object(file)) = (
+            merged.as_object_mut(),
+            serde_json::from_str(&file_settings)?,
+        ) {
+            for (key, value) in file {
+                if base.get(&key) == Some(&value) || merged.get(&key) == Some(&value) {
+                    continue;
+                }
+                anyhow::ensure!(
+                    !LOCKED_SETTINGS.contains(&key.as_str()),
+                    "{} cannot be changed while running, restart the server",
+                    key
+                );
+                merged.insert(key.clone(), value);
+                changed.push(key);
+            }
+        }
+        if changed.is_empty() {
+            return Ok(changed);
+        }
+        let mut merged: AppConfig = serde_json::from_value(merged)?;
+        merged.images = std::mem::take(&mut config.images);
+        merged.changes = std::mem::take(&mut config.changes);
+        merged.index = std::mem::take(&mut config.index);
+        merged.blob_key = config.blob_key.clone();
+        // 不写入文件的下载占用标记，经过序列化后会丢失
+        for (token, link) in &mut merged.one_time_links {
+            link.downloading = config
+                .one_time_links
+                .get(token)
+                .is_some_and(|l| l.downloading);
+        }
+        *config = merged;
+
+        SAVED_SETTINGS
+            .lock()
+            .unwrap()
+            .insert(self.config_path.clone(), file_settings);
+        // 保留的修改与文件不同时写回 (write_through 时立即写入)
+        self.persist(&config)?;
+        Ok(changed)
+    }
+
+    // 获取配置读锁；site 标识调用方，开启 lock-metrics feature 时记录等待时间
+    pub async fn read_config(&self, site: &'static str) -> RwLockReadGuard<'_, AppConfig> {
+        #[cfg(feature = "lock-metrics")]
+        let start = std::time::Instant::now();
+        let guard = self.config.read().await;
+        #[cfg(feature = "lock-metrics")]
+        self.stats.record_lock_wait(site, start.elapsed());
+        #[cfg(not(feature = "lock-metrics"))]
+        let _ = site;
+        guard
+    }
+
+    // 获取配置写锁，同 read_config
+    pub async fn write_config(&self, site: &'static str) -> RwLockWriteGuard<'_, AppConfig> {
+        #[cfg(feature = "lock-metrics")]
+        let start = std::time::Instant::now();
+        let guard = self.config.write().await;
+        #[cfg(feature = "lock-metrics")]
+        self.stats.record_lock_wait(site, start.elapsed());
+        #[cfg(not(feature = "lock-metrics"))]
+        let _ = site;
+        guard
+    }
+}
+
+// 上次写入 (或读取) 的设置内容，设置没有变化时不重写配置文件，避免覆盖手动修改
+static SAVED_SETTINGS: Lazy<std::sync::Mutex<HashMap<PathBuf, String>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+// 运行中不能修改的设置：修改后已有的 blob 无法读取
+const LOCKED_SETTINGS: &[&str] = &["data_dir", "encryption_key", "encryption_key_file"];
+
+// 只在启动时读取的设置，重新加载后需要重启才能生效
+pub const RESTART_SETTINGS: &[&str] = &[
+    "processing_workers",
+    "processing_queue",
+    "read_buffer_kb",
+    "write_buffer_kb",
+    "grpc_addr",
+    "otlp_endpoint",
+    "sentry_dsn",
+    "log_format",
+    "access_log",
+    "persist_interval_ms",
+    "shutdown_timeout_secs",
+    "link_check_interval_hours",
+    "pin_interval_secs",
+    "max_variants_mb",
+    "report_interval",
+];
+
+fn settings_of(config: &AppConfig) -> anyhow::Result<String> {
+    Ok(serde_json::to_string(config)?)
+}
+
+// 写入配置文件：先写到同一目录下的临时文件再 rename，中断或磁盘写满时不会留下不完整的配置文件
+// 临时文件名保留原扩展名 (config_file2 按扩展名选择格式)，并沿用原文件的权限
+fn store_config(path: &PathBuf, config: &AppConfig) -> anyhow::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}-{}", uuid::Uuid::new_v4().simple(), name));
+    let stored = config
+        .store(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| {
+            if let Ok(metadata) = fs::metadata(path) {
+                fs::set_permissions(&temp, metadata.permissions())?;
+            }
+            fs::rename(&temp, path)?;
+            Ok(())
+        });
+    if stored.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    stored
+}
+
+// 升级旧版本的配置文件 (先备份)，返回原来的版本和备份的路径；已是当前版本时返回 None
+pub fn migrate_config(path: &PathBuf) -> anyhow::Result<Option<(u32, Vec<PathBuf>)>> {
+    let Some(plan) = migrate::Plan::load(path)? else {
+        return Ok(None);
+    };
+    if plan.version == migrate::CURRENT_VERSION {
+        return Ok(None);
+    }
+    let version = plan.version;
+    let backups = plan.backup(path)?;
+    store_config(path, &plan.upgrade()?)?;
+    Ok(Some((version, backups)))
 }
 
 // 加载配置
 pub fn load_config(path: &PathBuf) -> anyhow::Result<AppConfig> {
-    let config = AppConfig::load_or_default(path)?;
+    // 日志尚未初始化，提示输出到 stderr
+    if let Some((version, backups)) = migrate_config(path)? {
+        eprintln!(
+            "Migrated {:?} from schema version {} to {}, backup: {:?}",
+            path,
+            version,
+            migrate::CURRENT_VERSION,
+            backups
+        );
+    }
+    let mut config = AppConfig::load_or_default(path)?;
+    config.blob_key = config.resolve_blob_key()?;
     // 确保存储目录存在
     fs::create_dir_all(config.images_dir())?;
     fs::create_dir_all(config.thumbs_dir())?;
     fs::create_dir_all(config.temp_dir())?;
+    fs::create_dir_all(config.variants_dir())?;
     fs::create_dir_all(config.logs_dir())?;
+
+    if let Some(images) = catalog::load(&config.images_file())? {
+        config.images = images;
+    }
+    SAVED_SETTINGS
+        .lock()
+        .unwrap()
+        .insert(path.clone(), settings_of(&config)?);
+    config.reindex();
+    Ok(config)
+}
+
+// 只读地加载配置和图片记录，用于 stats、export 等只查看数据的命令
+// 不迁移、不创建目录、不重写图片记录日志；配置版本过旧时报错
+pub fn load_config_readonly(path: &PathBuf) -> anyhow::Result<AppConfig> {
+    let plan =
+        migrate::Plan::load(path)?.ok_or_else(|| anyhow::anyhow!("{:?} does not exist", path))?;
+    if plan.version < migrate::CURRENT_VERSION {
+        anyhow::bail!(
+            "schema version {} of {:?} is outdated (current: {}), run `img-server migrate` first",
+            plan.version,
+            path,
+            migrate::CURRENT_VERSION
+        );
+    }
+    let mut config =
+        AppConfig::load(path)?.ok_or_else(|| anyhow::anyhow!("{:?} does not exist", path))?;
+    if let Some(images) = catalog::read(&config.images_file())? {
+        config.images = images;
+    }
+    config.reindex();
     Ok(config)
 }
 
-// 保存配置 (持久化)
+// 保存配置 (持久化)：修改过的图片记录追加到日志，设置有变化时才重写配置文件
 pub fn save_config(path: &PathBuf, config: &AppConfig) -> anyhow::Result<()> {
-    Ok(config.store(path)?)
+    let changed = config.changes.take();
+    if !changed.is_empty()
+        && let Err(e) = catalog::save(&config.images_file(), &config.images, &changed)
+    {
+        config.changes.restore(changed);
+        return Err(e);
+    }
+    let settings = settings_of(config)?;
+    let mut saved = SAVED_SETTINGS.lock().unwrap();
+    if saved.get(path) != Some(&settings) {
+        store_config(path, config)?;
+        saved.insert(path.clone(), settings);
+    }
+    Ok(())
 }
@@ -0,0 +1,173 @@
+use std::sync::{Arc, LazyLock};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, Object, Schema, SimpleObject,
+};
+use chrono::{DateTime, Utc};
+
+use crate::config::{AppState, ImageMeta};
+
+// 图片元数据的只读 GraphQL 查询，前端可以一次请求所需的字段和过滤条件
+
+pub type ImageSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+// AppState 随每个请求通过 Request::data 传入
+pub static SCHEMA: LazyLock<ImageSchema> = LazyLock::new(|| {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .limit_depth(8)
+        .finish()
+});
+
+#[derive(SimpleObject)]
+pub struct Image {
+    name: String,
+    desc: String,
+    hash: String,
+    size: u64,
+    created_at: DateTime<Utc>,
+    captured_at: Option<DateTime<Utc>>,
+    content_type: Option<String>,
+    aliases: Vec<String>,
+    tags: Vec<String>,
+    album: Option<String>,
+    pinned: bool,
+    blurhash: Option<String>,
+    url: String,
+    thumb_url: String,
+}
+
+impl Image {
+    fn new(meta: &ImageMeta, versioned: bool) -> Self {
+        Self {
+            name: meta.name.clone(),
+            desc: meta.desc.clone(),
+            hash: meta.hash.clone(),
+            size: meta.size,
+            created_at: meta.created_at,
+            captured_at: meta.captured_at,
+            content_type: meta.content_type.clone(),
+            aliases: meta.aliases.clone(),
+            tags: meta.tags.clone(),
+            album: meta.album.clone(),
+            pinned: meta.pinned,
+            blurhash: meta.blurhash.clone(),
+            url: meta.url(false, versioned),
+            thumb_url: meta.url(true, versioned),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct ImagePage {
+    total: usize,
+    items: Vec<Image>,
+}
+
+#[derive(SimpleObject)]
+pub struct TagCount {
+    tag: String,
+    count: usize,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortField {
+    #[default]
+    CreatedAt,
+    CapturedAt,
+    Name,
+    Size,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Look up an image by name, alias or hash
+    async fn image(&self, ctx: &Context<'_>, id: String) -> Option<Image> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let config = state.read_config("graphql").await;
+        config
+            .find_image(&id)
+            .filter(|i| i.is_public())
+            .map(|i| Image::new(i, config.versioned_urls))
+    }
+
+    /// List images, filtered and paginated; `limit` is capped by `max_page_size`
+    #[allow(clippy::too_many_arguments)]
+    async fn images(
+        &self,
+        ctx: &Context<'_>,
+        tag: Option<String>,
+        q: Option<String>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        #[graphql(default)] sort: SortField,
+        #[graphql(default)] order: SortOrder,
+        #[graphql(default)] offset: usize,
+        limit: Option<usize>,
+    ) -> ImagePage {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let config = state.read_config("graphql").await;
+        let limit = limit
+            .unwrap_or(config.page_size)
+            .clamp(1, config.max_page_size.max(1));
+
+        let query = q.as_deref().map(str::to_lowercase);
+        let mut images: Vec<_> = config
+            .images()
+            .filter(|i| i.is_public())
+            .filter(|i| tag.as_ref().is_none_or(|t| i.tags.contains(t)))
+            .filter(|i| query.as_ref().is_none_or(|q| i.matches(q)))
+            .filter(|i| created_after.is_none_or(|t| i.created_at >= t))
+            .filter(|i| created_before.is_none_or(|t| i.created_at < t))
+            .collect();
+        match sort {
+            // 记录按上传顺序追加，无需排序
+            SortField::CreatedAt => {}
+            SortField::CapturedAt => images.sort_by_key(|i| i.captured_at.unwrap_or(i.created_at)),
+            SortField::Name => images.sort_by(|a, b| a.name.cmp(&b.name)),
+            SortField::Size => images.sort_by_key(|i| i.size),
+        }
+        if order == SortOrder::Desc {
+            images.reverse();
+        }
+
+        ImagePage {
+            total: images.len(),
+            items: images
+                .into_iter()
+                .skip(offset)
+                .take(limit)
+                .map(|i| Image::new(i, config.versioned_urls))
+                .collect(),
+        }
+    }
+
+    /// All tags in use with their image counts
+    async fn tags(&self, ctx: &Context<'_>) -> Vec<TagCount> {
+        let state = ctx.data_unchecked::<Arc<AppState>>();
+        let config = state.read_config("graphql").await;
+        let mut counts: std::collections::BTreeMap<&str, usize> = Default::default();
+        for tag in config
+            .images()
+            .filter(|i| i.is_public())
+            .flat_map(|i| &i.tags)
+        {
+            *counts.entry(tag).or_default() += 1;
+        }
+        counts
+            .into_iter()
+            .map(|(tag, count)| TagCount {
+                tag: tag.to_string(),
+                count,
+            })
+            .collect()
+    }
+}
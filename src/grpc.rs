@@ -0,0 +1,313 @@
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, atomic::Ordering},
+};
+
+use axum::{body::Bytes, http::StatusCode};
+use futures::{Stream, StreamExt, TryStreamExt};
+use log::{error, info};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::{
+    config::{AppState, ImageMeta},
+    handler::{
+        UploadFields, check_disk_space, check_ip, check_token, receive_file, remove_unused_blobs,
+        store_files,
+    },
+    storage::blob_stream,
+};
+
+// gRPC 接口，与 HTTP 接口共用 AppState；消息定义与 proto/img_server.proto 对应
+
+include!(concat!(env!("OUT_DIR"), "/img_server.ImageStore.rs"));
+
+pub use image_store_server::ImageStoreServer;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct UploadRequest {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub desc: String,
+    #[prost(string, repeated, tag = "3")]
+    pub tags: Vec<String>,
+    #[prost(bytes = "vec", tag = "4")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ImageInfo {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub desc: String,
+    #[prost(string, tag = "3")]
+    pub hash: String,
+    #[prost(uint64, tag = "4")]
+    pub size: u64,
+    #[prost(string, tag = "5")]
+    pub created_at: String,
+    #[prost(string, repeated, tag = "6")]
+    pub tags: Vec<String>,
+    #[prost(string, tag = "7")]
+    pub content_type: String,
+}
+
+impl From<&ImageMeta> for ImageInfo {
+    fn from(meta: &ImageMeta) -> Self {
+        Self {
+            name: meta.name.clone(),
+            desc: meta.desc.clone(),
+            hash: meta.hash.clone(),
+            size: meta.size,
+            created_at: meta.created_at.to_rfc3339(),
+            tags: meta.tags.clone(),
+            content_type: meta.content_type.clone().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownloadRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+    #[prost(bool, tag = "2")]
+    pub thumb: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DownloadChunk {
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListRequest {
+    #[prost(uint32, tag = "1")]
+    pub page: u32,
+    #[prost(uint32, tag = "2")]
+    pub page_size: u32,
+    #[prost(string, tag = "3")]
+    pub tag: String,
+    #[prost(string, tag = "4")]
+    pub q: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ListResponse {
+    #[prost(uint64, tag = "1")]
+    pub total: u64,
+    #[prost(message, repeated, tag = "2")]
+    pub images: Vec<ImageInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteRequest {
+    #[prost(string, tag = "1")]
+    pub id: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DeleteResponse {}
+
+// 将 HTTP handler 的错误转换为对应的 gRPC 状态
+fn to_status((code, message): (StatusCode, String)) -> Status {
+    match code {
+        StatusCode::BAD_REQUEST | StatusCode::UNSUPPORTED_MEDIA_TYPE => {
+            Status::invalid_argument(message)
+        }
+        StatusCode::UNAUTHORIZED => Status::unauthenticated(message),
+        StatusCode::FORBIDDEN => Status::permission_denied(message),
+        StatusCode::NOT_FOUND => Status::not_found(message),
+        StatusCode::CONFLICT => Status::already_exists(message),
+        StatusCode::SERVICE_UNAVAILABLE => Status::unavailable(message),
+        StatusCode::PAYLOAD_TOO_LARGE | StatusCode::INSUFFICIENT_STORAGE => {
+            Status::resource_exhausted(message)
+        }
+        _ => Status::internal(message),
+    }
+}
+
+fn remote_addr<T>(request: &Request<T>) -> SocketAddr {
+    request
+        .remote_addr()
+        .unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)))
+}
+
+fn admin_token<T>(request: &Request<T>) -> Option<String> {
+    request
+        .metadata()
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+pub struct ImageStoreService {
+    state: Arc<AppState>,
+}
+
+impl ImageStoreService {
+    pub fn new(state: Arc<AppState>) -> Self {
+        Self { state }
+    }
+}
+
+type ChunkStream = Pin<Box<dyn Stream<Item = Result<DownloadChunk, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl image_store_server::ImageStore for ImageStoreService {
+    async fn upload(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<ImageInfo>, Status> {
+        let addr = remote_addr(&request);
+        let token = admin_token(&request);
+        let (temp_dir, blob_key, max_file) = {
+            let config = self.state.read_config("grpc_upload").await;
+            check_ip(&config, &addr).map_err(to_status)?;
+            check_token(&config, token.as_deref()).map_err(to_status)?;
+            check_disk_space(&config).map_err(to_status)?;
+            (
+                config.temp_dir().clone(),
+                config.blob_key.clone(),
+                config.max_file_bytes(),
+            )
+        };
+
+        // 首条消息携带元数据，之后的消息只读取 data
+        let mut stream = request.into_inner();
+        let first = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Empty upload"))?;
+        let data = futures::stream::once(async move { Ok(Bytes::from(first.data)) })
+            .chain(stream.map_ok(|m| Bytes::from(m.data)));
+        let file = receive_file(Box::pin(data), &temp_dir, blob_key.as_ref(), max_file)
+            .await
+            .map_err(to_status)?;
+
+        let names = vec![first.name].into_iter().filter(|n| !n.is_empty());
+        let fields = UploadFields {
+            names: names.collect(),
+            descs: vec![first.desc],
+            tags: first.tags,
+            strip_metadata: None,
+            captured_at: None,
+        };
+        let metas = store_files(&self.state, &addr, token.as_deref(), vec![file], fields)
+            .await
+            .map_err(to_status)?;
+        Ok(Response::new(ImageInfo::from(&metas[0].meta)))
+    }
+
+    type DownloadStream = ChunkStream;
+
+    async fn download(
+        &self,
+        request: Request<DownloadRequest>,
+    ) -> Result<Response<Self::DownloadStream>, Status> {
+        let addr = remote_addr(&request);
+        let DownloadRequest { id, thumb } = request.into_inner();
+        let (path, blob_key) = {
+            let config = self.state.read_config("grpc_download").await;
+            check_ip(&config, &addr).map_err(to_status)?;
+
+            // 与 HTTP 下载相同：先匹配名称，再按 Hash 匹配；私有或隔离中的图片视为不存在
+            let hash = match config.image_id(&id).map(|i| config.image(i)) {
+                Some(img) if !img.is_public() => return Err(Status::not_found("Image not found")),
+                Some(img) => img.hash.clone(),
+                None if config.hidden_hash(&id) => {
+                    return Err(Status::not_found("Image not found"));
+                }
+                None if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) => id.clone(),
+                None => return Err(Status::not_found("Image not found")),
+            };
+            let dir = if thumb {
+                config.thumbs_dir()
+            } else {
+                config.images_dir()
+            };
+            (dir.join(hash), config.blob_key.clone())
+        };
+        if !path.exists() {
+            return Err(Status::not_found("File not found"));
+        }
+
+        let stream = blob_stream(&path, blob_key.as_ref()).await.map_err(|e| {
+            error!("Failed to open blob {:?}: {}", path, e);
+            Status::internal("Read failed")
+        })?;
+        info!(
+            "addr: {:?}, action: grpc_download, id: {:?}, thumb: {:?}",
+            addr, id, thumb
+        );
+        let stream = stream
+            .map_ok(|chunk| DownloadChunk {
+                data: chunk.to_vec(),
+            })
+            .map_err(|e| Status::internal(e.to_string()));
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn list(&self, request: Request<ListRequest>) -> Result<Response<ListResponse>, Status> {
+        let addr = remote_addr(&request);
+        let params = request.into_inner();
+        let config = self.state.read_config("grpc_list").await;
+        check_ip(&config, &addr).map_err(to_status)?;
+
+        let page = (params.page as usize).max(1);
+        let page_size = match params.page_size {
+            0 => config.page_size,
+            n => n as usize,
+        }
+        .clamp(1, config.max_page_size.max(1));
+        let query = params.q.to_lowercase();
+        let images: Vec<_> = config
+            .images()
+            .filter(|i| i.is_public())
+            .filter(|i| params.tag.is_empty() || i.tags.contains(&params.tag))
+            .filter(|i| query.is_empty() || i.matches(&query))
+            .collect();
+
+        Ok(Response::new(ListResponse {
+            total: images.len() as u64,
+            // 与 HTTP 列表的默认顺序一致：最新上传的在前
+            images: images
+                .into_iter()
+                .rev()
+                .skip((page - 1) * page_size)
+                .take(page_size)
+                .map(ImageInfo::from)
+                .collect(),
+        }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<DeleteRequest>,
+    ) -> Result<Response<DeleteResponse>, Status> {
+        let addr = remote_addr(&request);
+        let token = admin_token(&request);
+        let id = request.into_inner().id;
+        {
+            let config = self.state.read_config("grpc_delete").await;
+            check_ip(&config, &addr).map_err(to_status)?;
+            check_token(&config, token.as_deref()).map_err(to_status)?;
+        }
+        let mut config = self.state.write_config("grpc_delete").await;
+
+        let Some(hashes) = config.remove_image(&id) else {
+            return Err(Status::not_found("Image not found"));
+        };
+        remove_unused_blobs(&config, &hashes).await;
+        self.state.persist(&config).map_err(|e| {
+            error!("Failed to save config: {}", e);
+            Status::internal("Save failed")
+        })?;
+        self.state.stats.deletes.fetch_add(1, Ordering::Relaxed);
+
+        info!("addr: {:?}, action: grpc_delete, id: {:?}", addr, id);
+        Ok(Response::new(DeleteResponse {}))
+    }
+}
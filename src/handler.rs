@@ -1,14 +1,25 @@
-use std::{io::BufWriter, net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, atomic::Ordering},
+};
 
 use axum::{
-    Json,
+    Json, RequestExt as _,
     body::Body,
-    extract::{ConnectInfo, Multipart, Path, Query, State},
+    extract::{
+        ConnectInfo, Multipart, Path, Query, Request, State, multipart::MultipartRejection,
+        rejection::JsonRejection,
+    },
     http::{StatusCode, header},
-    response::Response,
+    response::{IntoResponse, Response},
+};
+use base64::{
+    Engine as _,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
 };
-use futures::TryStreamExt;
-use image::{GenericImageView as _, ImageReader};
+use config_file2::LoadConfigFile as _;
+use futures::{StreamExt as _, TryStreamExt};
 use log::{error, info, warn};
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
@@ -16,12 +27,32 @@ use tokio::{
     fs::{self, File},
     io::AsyncWriteExt,
 };
-use tokio_util::io::ReaderStream;
+use tracing::Instrument as _;
 
-use crate::config::{AppConfig, AppState, ImageMeta, save_config};
+use crate::{
+    catalog,
+    config::{
+        AppConfig, AppState, ImageMeta, ImageVersion, OneTimeLink, SimilarImages, token_fingerprint,
+    },
+    id::random_string,
+    imaging::{
+        Transform, apply_transforms, capture_time, capture_time_from, convert_image, crop_image,
+        extension_mime, image_dimensions, perceptual_hash, sniff_content_type, strip_jpeg_metadata,
+        thumbnail_content_type,
+    },
+    logging, moderation,
+    pool::PoolError,
+    sentry, stats,
+    storage::{
+        BlobEncoder, BlobKey, blob_len, blob_stream, blob_stream_range, move_file_async, read_blob,
+        write_blob, write_buffer,
+    },
+    tasks::{self, extract_urls},
+    upstream,
+};
 
 // 检查 IP 黑名单
-fn check_ip(config: &AppConfig, addr: &SocketAddr) -> Result<(), (StatusCode, String)> {
+pub(crate) fn check_ip(config: &AppConfig, addr: &SocketAddr) -> Result<(), (StatusCode, String)> {
     let ip = addr.ip().to_string();
     if config.blacklist.contains(&ip) {
         warn!("Blocked request from blacklisted IP: {}", ip);
@@ -31,14 +62,87 @@ fn check_ip(config: &AppConfig, addr: &SocketAddr) -> Result<(), (StatusCode, St
 }
 
 // 检查 Admin Token
-fn check_token(config: &AppConfig, token: Option<&str>) -> Result<(), (StatusCode, String)> {
-    match token {
-        Some(t) if config.tokens.contains(t) => Ok(()),
-        _ => Err((
+pub(crate) fn check_token(
+    config: &AppConfig,
+    token: Option<&str>,
+) -> Result<(), (StatusCode, String)> {
+    let unauthorized = || {
+        Err((
             StatusCode::UNAUTHORIZED,
             "Invalid or missing token".to_string(),
-        )),
+        ))
+    };
+    let Some(t) = token.filter(|t| config.tokens.contains(*t)) else {
+        return unauthorized();
+    };
+    // 已被轮换的旧 token 在宽限期内仍可使用，记录日志以便找出尚未切换的客户端
+    if let Some(info) = config.token_info.get(t)
+        && let Some(expires_at) = info.expires_at
+    {
+        if expires_at <= chrono::Utc::now() {
+            return unauthorized();
+        }
+        warn!(
+            "Deprecated token used, fingerprint: {:?}, label: {:?}, expires_at: {}",
+            token_fingerprint(t),
+            info.label,
+            expires_at
+        );
+    }
+    Ok(())
+}
+
+// data_dir 剩余空间不足 min_free_mb 时拒绝上传：磁盘写满后，下一次元数据写入会留下不完整的文件
+pub(crate) fn check_disk_space(config: &AppConfig) -> Result<(), (StatusCode, String)> {
+    if let Some(free) = config.low_disk_space() {
+        error!(
+            "Low disk space: {} MB free in {:?}, below min_free_mb = {}, refusing upload",
+            free / 1024 / 1024,
+            config.data_dir,
+            config.min_free_mb
+        );
+        return Err((
+            StatusCode::INSUFFICIENT_STORAGE,
+            "Insufficient storage".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// 图片处理池排队已满
+fn server_busy() -> (StatusCode, String) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Server busy, retry later".to_string(),
+    )
+}
+
+// 请求能否读取该图片：公开图片所有人可读；私有图片需要 admin token 或其所在相册的 token
+// 隔离中 (等待内容审核) 的图片只对管理员可见
+pub(crate) fn can_read(
+    config: &AppConfig,
+    headers: &header::HeaderMap,
+    album_token: Option<&str>,
+    img: &ImageMeta,
+) -> bool {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    img.is_public()
+        || check_token(config, token).is_ok()
+        || album_token.is_some_and(|t| config.album_token_allows(t, img))
+}
+
+// 无权读取时视为不存在；images 为引用同一内容的记录，其中任意一条可读即可，没有记录时不限制
+fn check_readable<'a>(
+    config: &AppConfig,
+    headers: &header::HeaderMap,
+    album_token: Option<&str>,
+    images: impl IntoIterator<Item = &'a ImageMeta>,
+) -> Result<(), (StatusCode, String)> {
+    let mut images = images.into_iter().peekable();
+    if images.peek().is_some() && !images.any(|i| can_read(config, headers, album_token, i)) {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
     }
+    Ok(())
 }
 
 // 一个简单的 RAII 守卫，用于自动删除临时文件
@@ -68,321 +172,3964 @@ impl Drop for TempFileGuard {
     }
 }
 
+// 存储是否就绪：存储目录可访问，且临时目录可以写入 (写入并删除一个探测文件)；/readyz 和 /health 共用
+async fn storage_ready(state: &AppState) -> bool {
+    let (dirs, temp_dir) = {
+        let config = state.read_config("readyz").await;
+        (
+            [config.images_dir().clone(), config.temp_dir().clone()],
+            config.temp_dir().clone(),
+        )
+    };
+    for dir in dirs {
+        if !fs::metadata(&dir).await.is_ok_and(|m| m.is_dir()) {
+            warn!("Readiness check failed: {:?} is not accessible", dir);
+            return false;
+        }
+    }
+    let probe = temp_dir.join(format!("health-{}", uuid::Uuid::new_v4()));
+    match fs::write(&probe, b"ok").await {
+        Ok(()) => fs::remove_file(&probe).await.is_ok(),
+        Err(e) => {
+            warn!(
+                "Readiness check failed: {:?} is not writable: {}",
+                temp_dir, e
+            );
+            false
+        }
+    }
+}
+
+// 就绪检查：存储就绪时返回 200
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    summary = "Readiness probe",
+    description = "Storage directories are accessible and the data directory is writable.",
+    responses(
+        (status = 200, description = "Ready"),
+        (status = 503, description = "Not ready"),
+    ),
+)]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> (StatusCode, &'static str) {
+    match storage_ready(&state).await {
+        true => (StatusCode::OK, "ok"),
+        false => (StatusCode::SERVICE_UNAVAILABLE, "not ready"),
+    }
+}
+
+// 按当前配置的 max_size_mb 限制请求体大小，重新加载配置后立即生效
+pub async fn limit_body(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: axum::middleware::Next,
+) -> Response {
+    use tower::{Layer as _, ServiceExt as _};
+
+    let max_size = state.read_config("limit_body").await.max_size_mb * 1024 * 1024;
+    let Ok(response) = axum::extract::DefaultBodyLimit::max(max_size)
+        .layer(next)
+        .oneshot(request)
+        .await;
+    response
+}
+
+// 统计进行中的请求 (用于优雅关闭)，并记录每个请求的指标、span 和访问日志
+pub async fn track_in_flight(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let _guard = state.stats.enter_request();
+    let method = request.method().clone();
+    let uri = request.uri().to_string();
+    let version = request.version();
+    let route = request
+        .extensions()
+        .get::<axum::extract::MatchedPath>()
+        .map(|p| p.as_str().to_string());
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|c| c.0);
+    let (referer, user_agent, request_length, request_id) = {
+        let headers = request.headers();
+        let text = |name| headers.get(name)?.to_str().ok().map(str::to_string);
+        (
+            text(header::REFERER),
+            text(header::USER_AGENT),
+            text(header::CONTENT_LENGTH),
+            // 沿用客户端或反向代理提供的 X-Request-Id，否则生成一个
+            text(REQUEST_ID_HEADER)
+                .filter(|id| is_valid_request_id(id))
+                .unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string()),
+        )
+    };
+    let span = tracing::debug_span!(
+        "request",
+        http.method = %method,
+        http.route = route.as_deref().unwrap_or("unmatched"),
+        http.status_code = tracing::field::Empty,
+        request_id = %request_id,
+    );
+    let started = std::time::Instant::now();
+    let mut response = logging::REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+    if let Ok(value) = header::HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    span.record("http.status_code", response.status().as_u16());
+    state.stats.record_request(
+        method.as_str(),
+        route.as_deref(),
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    let length = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok());
+    if method == axum::http::Method::GET
+        && response.status().is_success()
+        && let Some(length) = length
+    {
+        state
+            .stats
+            .download_bytes
+            .fetch_add(length, Ordering::Relaxed);
+    }
+    // 配置了 sentry_dsn 时上报 5xx 响应，错误说明取自内存中的响应体 (读取后原样放回)
+    let mut error_message = None;
+    if response.status().is_server_error()
+        && sentry::enabled()
+        && axum::body::HttpBody::size_hint(response.body())
+            .exact()
+            .is_some()
+    {
+        let (parts, body) = response.into_parts();
+        let data = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .unwrap_or_default();
+        error_message = Some(String::from_utf8_lossy(&data).into_owned());
+        response = Response::from_parts(parts, Body::from(data));
+    }
+    // 内存中的响应体 (JSON 等) 没有 Content-Length 头，大小取自响应体
+    let bytes = length.or_else(|| axum::body::HttpBody::size_hint(response.body()).exact());
+    let entry = logging::AccessEntry {
+        addr: client,
+        method: method.as_str(),
+        uri: &uri,
+        version,
+        status: response.status().as_u16(),
+        bytes,
+        referer: referer.as_deref(),
+        user_agent: user_agent.as_deref(),
+        elapsed: started.elapsed(),
+        request_id: &request_id,
+    };
+    logging::log_access(&entry);
+    if let Some(message) = error_message {
+        sentry::capture_request(&entry, route.as_deref(), &message);
+    }
+
+    // 耗时为返回响应头之前的处理时间，不含流式响应体的传输
+    let elapsed = started.elapsed();
+    let slow_request_ms = state.read_config("slow_request").await.slow_request_ms;
+    if slow_request_ms > 0 && elapsed.as_millis() >= slow_request_ms as u128 {
+        logging::REQUEST_ID.sync_scope(request_id, || {
+            let or_dash = |v: Option<u64>| v.map_or_else(|| "-".to_string(), |v| v.to_string());
+            warn!(
+                "addr: {}, action: slow_request, method: {}, uri: {:?}, route: {:?}, status: {}, elapsed_ms: {}, request_bytes: {}, response_bytes: {}, user_agent: {:?}",
+                client.map_or_else(|| "-".to_string(), |a| a.to_string()),
+                method,
+                uri,
+                route.as_deref().unwrap_or("unmatched"),
+                response.status().as_u16(),
+                elapsed.as_millis(),
+                or_dash(request_length.and_then(|l| l.parse().ok())),
+                or_dash(bytes),
+                user_agent.as_deref().unwrap_or("-"),
+            );
+        });
+    }
+    response
+}
+
+pub const REQUEST_ID_HEADER: header::HeaderName = header::HeaderName::from_static("x-request-id");
+
+// 客户端提供的请求 ID 会写入日志和响应头：限制长度和字符，避免伪造日志行
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+// 健康检查：在就绪检查 (同 /readyz) 的基础上，确认磁盘上的元数据可以解析，都通过时返回 200
+#[utoipa::path(
+    get,
+    path = "/health",
+    summary = "Health check",
+    description = "Runs the readiness check of /readyz and also checks that the metadata is loadable.",
+    responses(
+        (
+            status = 200,
+            description = "Healthy; `ready` is the result of the /readyz check",
+            body = Object,
+            example = json!({"ok": true, "ready": true, "metadata_loadable": true}),
+        ),
+        (status = 503, description = "Unhealthy"),
+    ),
+)]
+pub async fn health(State(state): State<Arc<AppState>>) -> (StatusCode, Json<serde_json::Value>) {
+    let ready = storage_ready(&state).await;
+    // 重新读取配置文件和图片记录，确认元数据没有损坏
+    let config_path = state.config_path.clone();
+    let loadable = match tokio::task::spawn_blocking(move || {
+        let config = AppConfig::load_or_default(&config_path)?;
+        catalog::read(&config.images_file())
+    })
+    .await
+    {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            warn!("Health check failed: metadata is not loadable: {}", e);
+            false
+        }
+        Err(_) => false,
+    };
+
+    let status = match ready && loadable {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (
+        status,
+        Json(serde_json::json!({
+            "ok": ready && loadable,
+            "ready": ready,
+            "metadata_loadable": loadable,
+        })),
+    )
+}
+
+// 实例的功能和限制，供通用客户端自动适配
+#[utoipa::path(
+    get,
+    path = "/capabilities",
+    summary = "Instance capabilities and limits",
+    responses(
+        (status = 200, description = "Capabilities", body = Object),
+    ),
+)]
+pub async fn capabilities(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let config = state.read_config("capabilities").await;
+    check_ip(&config, &addr)?;
+
+    // 编译进来、可以解码的图片格式
+    let mut formats: Vec<_> = image::ImageFormat::all()
+        .filter(|f| f.reading_enabled())
+        .map(|f| f.to_mime_type())
+        .filter(|mime| *mime != "application/octet-stream")
+        .collect();
+    if cfg!(feature = "heic") {
+        formats.push("image/heic");
+    }
+    formats.extend(["image/x-canon-cr2", "image/x-nikon-nef", "image/x-sony-arw"]);
+    Ok(Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "max_upload_bytes": config.max_size_mb * 1024 * 1024,
+        "max_file_bytes": config.max_file_bytes(),
+        "formats": formats,
+        "upload_formats": config.allowed_formats,
+        "thumbnails": {
+            "enabled": config.thumbnail_pixels.is_some(),
+            "pixels": config.thumbnail_pixels,
+            "formats": config.thumbnail_formats,
+            "progressive": config.progressive_thumbnails,
+        },
+        "original_formats": config.original_formats,
+        "page_size": config.page_size,
+        "max_page_size": config.max_page_size,
+        "auth": ["x-admin-token"],
+        "features": {
+            "encryption": config.blob_key.is_some(),
+            "upstream": config.upstream.is_some(),
+            "alias_duplicates": config.alias_duplicates,
+            "versioned_urls": config.versioned_urls,
+            "link_check": config.link_check_interval_hours.is_some(),
+            "range_requests": true,
+            "one_time_links": true,
+            "albums": true,
+            "sharex": true,
+            "picgo": true,
+            "chunked_uploads": true,
+            "crop": true,
+            "transform": true,
+            "strip_metadata": config.strip_metadata,
+            "optimize_uploads": config.optimize_uploads,
+            "lock_metrics": cfg!(feature = "lock-metrics"),
+            "grpc": cfg!(feature = "grpc") && config.grpc_addr.is_some(),
+        },
+    })))
+}
+
+// Swagger UI 页面，静态资源从 CDN 加载
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>img-server API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>
+"##;
+
+// 未开启 openapi_docs 时返回 404
+async fn check_openapi_docs(
+    state: &AppState,
+    addr: &SocketAddr,
+) -> Result<(), (StatusCode, String)> {
+    let config = state.read_config("openapi").await;
+    check_ip(&config, addr)?;
+    if !config.openapi_docs {
+        return Err((StatusCode::NOT_FOUND, "Not found".to_string()));
+    }
+    Ok(())
+}
+
+pub async fn openapi_json(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response, (StatusCode, String)> {
+    check_openapi_docs(&state, &addr).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        crate::openapi::SPEC.as_str(),
+    )
+        .into_response())
+}
+
+pub async fn swagger_ui(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response, (StatusCode, String)> {
+    check_openapi_docs(&state, &addr).await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+        SWAGGER_UI,
+    )
+        .into_response())
+}
+
+// 上传请求中的一个文件，已写入临时文件
+pub(crate) struct ReceivedFile {
+    temp_path: PathBuf,
+    guard: TempFileGuard,
+    hash: String,
+    size: u64,
+}
+
+// POST /images：Content-Type 为 application/json 时按 JSON (base64) 上传，否则按 multipart 上传
+// 不单独占用 /images/json 这样的路径，以免遮住同名图片
+#[utoipa::path(
+    post,
+    path = "/images",
+    summary = "Upload images",
+    description = "A multipart body may carry several files (fields `name`, `desc`, `tags` (comma-separated, may be repeated), `file`, `strip_metadata`). A JSON body (`Content-Type: application/json`) carries a single file as base64, for clients that cannot build multipart bodies.",
+    params(
+        ("X-Image-Name" = Option<String>, Header, nullable = false),
+        ("X-Image-Desc" = Option<String>, Header, nullable = false),
+        (
+            "X-Image-Tags" = Option<String>,
+            Header,
+            nullable = false,
+            description = "Comma-separated tags",
+        ),
+        (
+            "X-Strip-Metadata" = Option<bool>,
+            Header,
+            nullable = false,
+            description = "Override the strip_metadata setting for this upload",
+        ),
+    ),
+    request_body(content(("multipart/form-data"), (JsonUpload = "application/json"))),
+    responses(
+        (
+            status = 200,
+            description = "The stored metadata; an array when several files were uploaded",
+            body = StoredImage,
+        ),
+        (status = 400, description = "Missing file"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (
+            status = 409,
+            description = "A visually identical image already exists (similar_images = \"reject\")",
+        ),
+        (status = 413, description = "File exceeds max_file_mb"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+        (status = 415, description = "Not an accepted image format"),
+        (status = 503, description = "Image processing pool is busy"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn post_images(
+    state: State<Arc<AppState>>,
+    addr: ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    request: Request,
+) -> Result<Response, (StatusCode, String)> {
+    let is_json = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if is_json {
+        let payload = request
+            .extract()
+            .await
+            .map_err(|e: JsonRejection| (e.status(), e.body_text()))?;
+        return upload_image_json(state, addr, headers, payload)
+            .await
+            .map(IntoResponse::into_response);
+    }
+    let multipart = request
+        .extract()
+        .await
+        .map_err(|e: MultipartRejection| (e.status(), e.body_text()))?;
+    upload_image(state, addr, headers, multipart).await
+}
+
 pub async fn upload_image(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     headers: header::HeaderMap,
     mut multipart: Multipart,
-) -> Result<Json<ImageMeta>, (StatusCode, String)> {
+) -> Result<Response, (StatusCode, String)> {
     let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
 
     // 1. 初始读取配置：检查权限和获取配置参数
-    let (temp_dir, images_dir, thumbs_dir, thumbnail_pixels) = {
-        let config = state.config.read().await;
+    let (temp_dir, blob_key, max_file) = {
+        let config = state.read_config("upload_image").await;
         check_ip(&config, &addr)?;
         check_token(&config, token)?;
+        check_disk_space(&config)?;
         (
             config.temp_dir().clone(),
-            config.images_dir().clone(),
-            config.thumbs_dir().clone(),
-            config.thumbnail_pixels,
+            config.blob_key.clone(),
+            config.max_file_bytes(),
         )
     };
 
-    let mut name = None;
-    let mut desc = String::new();
-    let mut file_hash = String::new();
-
-    // 生成临时文件路径 (使用 uuid 避免冲突)
-    let temp_file_path = temp_dir.join(uuid::Uuid::new_v4().to_string());
-    // **创建守卫**：如果本函数中途报错退出，这个守卫会自动删除临时文件
-    let mut temp_guard = TempFileGuard::new(temp_file_path.clone());
+    // 一次请求可以包含多个 file，name/desc 按出现顺序与 file 对应 (也可写作 name[]/desc[])
+    let mut names = Vec::new();
+    let mut descs = Vec::new();
+    let mut tags = Vec::new();
+    let mut strip_metadata = strip_metadata_header(&headers)?;
+    let mut files: Vec<ReceivedFile> = Vec::new();
 
     // 2. 处理 Multipart
-    let mut file_received = false;
-
     while let Ok(Some(field)) = multipart.next_field().await {
         let field_name = field.name().unwrap_or("").to_string();
 
-        if field_name == "name" {
-            name = Some(
+        if field_name == "name" || field_name == "name[]" {
+            names.push(
+                field
+                    .text()
+                    .await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
+            );
+        } else if field_name == "desc" || field_name == "desc[]" {
+            descs.push(
                 field
                     .text()
                     .await
                     .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
             );
-        } else if field_name == "desc" {
-            desc = field
+        } else if field_name == "tags" {
+            // 逗号分隔，字段可以重复出现
+            let text = field
                 .text()
                 .await
                 .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
-        } else if field_name == "file" {
-            // 打开临时文件准备写入
-            let mut file = File::create(&temp_file_path).await.map_err(|e| {
-                error!("Failed to create temp file: {}", e);
-                (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
-            })?;
-
-            let mut hasher = Sha256::new();
-            let mut stream = field;
-
-            while let Ok(Some(chunk)) = stream.try_next().await {
-                hasher.update(&chunk);
-                file.write_all(&chunk)
-                    .await
-                    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            }
-
-            // 刷入磁盘
-            file.flush()
+            tags.extend(text.split(',').map(str::to_string));
+        } else if field_name == "strip_metadata" {
+            let text = field
+                .text()
                 .await
-                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-            file_hash = hex::encode(hasher.finalize());
-            file_received = true;
+                .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+            strip_metadata = Some(parse_bool(&text, "strip_metadata")?);
+        } else if field_name == "file" {
+            files.push(receive_file(field, &temp_dir, blob_key.as_ref(), max_file).await?);
         }
     }
 
-    let name = name.ok_or((StatusCode::BAD_REQUEST, "Missing 'name'".to_string()))?;
-    if !file_received {
+    if files.is_empty() {
         return Err((StatusCode::BAD_REQUEST, "Missing 'file'".to_string()));
     }
 
-    // 3. 文件移动处理 (I/O 阶段，不持有锁)
-    // 逻辑：基于 Hash 去重。如果目标文件已存在，则直接复用，删除临时文件。
-    let target_path = images_dir.join(&file_hash);
-    let thumb_path = thumbs_dir.join(&file_hash);
-
-    if target_path.exists() {
-        // 文件已存在，不需要移动，不需要生成缩略图
-        // 这里的 temp_guard 在函数结束或 drop 时会自动删除临时文件，符合预期
-    } else {
-        // 文件不存在，移动临时文件到目标位置
-        fs::rename(&temp_file_path, &target_path)
-            .await
-            .map_err(|e| {
-                error!("Failed to move file: {}", e);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "File move failed".to_string(),
-                )
-            })?;
-
-        // 生成缩略图 (Blocking)
-        let t_p = target_path.clone();
-        if let Some(thumbnail_pixels) = thumbnail_pixels {
-            let th_p = thumb_path.clone();
-            tokio::task::spawn_blocking(move || {
-                let res = (|| -> image::ImageResult<()> {
-                    // 1. 打开文件并猜测格式
-                    let reader = ImageReader::open(&t_p)?.with_guessed_format()?;
-
-                    // 2. 在解码前获取格式，用于后续保存
-                    let format = reader.format().unwrap_or(image::ImageFormat::Png);
-
-                    // 3. 解码图片
-                    let img = reader.decode()?;
-
-                    // 4. 计算缩放后的尺寸
-                    let (width, height) = img.dimensions();
-                    let current_pixels = (width * height) as f64;
-
-                    // 计算缩放比例：sqrt(目标像素 / 当前像素)
-                    let scale_factor = (thumbnail_pixels as f64 / current_pixels).sqrt();
-
-                    // 如果当前像素已经小于目标值，可以选择不缩放，或者仍然强制缩放
-                    // 这里假设：如果图片太大，就缩小；如果本来就小，保持原样 (scale_factor > 1.0)
-                    let (new_w, new_h) = if scale_factor < 1.0 {
-                        (
-                            (width as f64 * scale_factor) as u32,
-                            (height as f64 * scale_factor) as u32,
-                        )
-                    } else {
-                        (width, height)
-                    };
-
-                    // 5. 生成缩略图 (thumbnail 会保持宽高比)
-                    let thumb = img.thumbnail(new_w, new_h);
-
-                    // 6. 使用与输入相同的格式保存
-                    let mut output_file = BufWriter::new(std::fs::File::create(&th_p)?);
-                    thumb.write_to(&mut output_file, format)?;
-
-                    Ok(())
-                })();
-
-                if let Err(e) = res {
-                    error!("Image processing failed: {}", e);
-                }
-            })
-            .await
-            .map_err(|_| {
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "Thumb gen failed".to_string(),
-                )
-            })?;
-        }
-        temp_guard.persist();
+    // 表单中没有对应字段时，从 X-Image-Name / X-Image-Desc / X-Image-Tags 头读取，方便脚本调用
+    if names.is_empty() {
+        names.extend(header_text(&headers, "x-image-name"));
+    }
+    if descs.is_empty() {
+        descs.extend(header_text(&headers, "x-image-desc"));
+    }
+    if tags.is_empty()
+        && let Some(text) = header_text(&headers, "x-image-tags")
+    {
+        tags.extend(text.split(',').map(str::to_string));
     }
 
-    let meta = ImageMeta {
-        name: name.clone(),
-        desc,
-        hash: file_hash.clone(),
-        created_at: chrono::Utc::now(),
+    let fields = UploadFields {
+        names,
+        descs,
+        tags,
+        strip_metadata,
+        captured_at: None,
     };
+    let mut metas = store_files(&state, &addr, token, files, fields).await?;
 
-    let mut config = state.config.write().await;
-    config.images.push(meta.clone());
-
-    if let Err(e) = save_config(&state.config_path, &config) {
-        error!("Failed to save config: {}", e);
-        return Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Save config failed".to_string(),
-        ));
+    // 单个文件时返回对象，多个文件时返回数组
+    if metas.len() == 1 {
+        Ok(Json(metas.remove(0)).into_response())
+    } else {
+        Ok(Json(metas).into_response())
     }
-
-    info!(
-        "addr: {:?}, action: upload, name: {:?}, hash: {:?}",
-        addr, meta.name, meta.hash
-    );
-    Ok(Json(meta))
 }
 
-// 下载图片
-#[derive(Deserialize)]
-pub struct DownloadParams {
-    thumb: Option<bool>,
+fn header_text(headers: &header::HeaderMap, key: &str) -> Option<String> {
+    headers
+        .get(key)
+        .and_then(|v| std::str::from_utf8(v.as_bytes()).ok())
+        .map(str::to_string)
 }
 
-pub async fn download_image(
-    State(state): State<Arc<AppState>>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Path(id): Path<String>,
-    Query(params): Query<DownloadParams>,
-) -> Result<Response, (StatusCode, String)> {
-    let config = state.config.read().await;
-    check_ip(&config, &addr)?;
-
-    // 查找逻辑：先匹配 Name，如果没找到且 id 看起来像 hash，则匹配 Hash
-    let hash = if let Some(img) = config.images.iter().find(|i| i.name == id) {
-        img.hash.clone()
-    } else if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) {
-        id.clone()
-    } else {
-        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
-    };
-
-    let is_thumb = params.thumb.unwrap_or(false);
-    let dir = if is_thumb {
-        &config.thumbs_dir()
-    } else {
-        &config.images_dir()
-    };
-    let path = dir.join(&hash);
-
-    if !path.exists() {
-        // 如果请求缩略图但不存在，回退到原图（可选策略，这里直接返回404）
-        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+fn parse_bool(text: &str, field: &str) -> Result<bool, (StatusCode, String)> {
+    match text.trim() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err((StatusCode::BAD_REQUEST, format!("Invalid '{}'", field))),
     }
+}
 
-    // 核心要求：Async Read -> Async Write
-    let file = File::open(&path)
-        .await
-        .map_err(|_| (StatusCode::NOT_FOUND, "File open error".to_string()))?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    info!(
-        "addr: {:?}, action: download, id: {:?}, thumb: {:?}",
-        addr, id, is_thumb
-    );
-
-    Ok(Response::builder()
-        .header(header::CONTENT_TYPE, "application/octet-stream") // 前端处理 Content-Type
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("inline; filename=\"{}\"", hash),
-        )
-        .body(body)
-        .unwrap())
+// X-Strip-Metadata 头：单次上传覆盖 strip_metadata 配置
+fn strip_metadata_header(
+    headers: &header::HeaderMap,
+) -> Result<Option<bool>, (StatusCode, String)> {
+    header_text(headers, "x-strip-metadata")
+        .map(|v| parse_bool(&v, "X-Strip-Metadata"))
+        .transpose()
 }
 
-// 列出图片
-#[derive(Deserialize)]
-pub struct ListParams {
-    page: Option<usize>,
-    page_size: Option<usize>,
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct JsonUpload {
+    name: Option<String>,
+    #[serde(default)]
+    desc: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Override the strip_metadata setting for this upload
+    strip_metadata: Option<bool>,
+    #[schema(format = Byte)]
+    data_base64: String,
 }
 
-pub async fn list_images(
+// 以 JSON 上传单个文件，文件内容为 base64，适用于不方便构造 multipart 的客户端
+// 请求体同样受 max_size_mb 限制，因此文件本身最大约为限制的 3/4
+pub async fn upload_image_json(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    Query(params): Query<ListParams>,
-) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
-    let config = state.config.read().await;
-    check_ip(&config, &addr)?;
-
-    let page = params.page.unwrap_or(1).max(1);
-    let page_size = params.page_size.unwrap_or(20).clamp(1, 100);
-
-    let total = config.images.len();
-    let skip = (page - 1) * page_size;
-
-    let data: Vec<_> = config
-        .images
-        .iter()
-        .rev()
-        .skip(skip)
-        .take(page_size)
-        .collect();
+    headers: header::HeaderMap,
+    Json(payload): Json<JsonUpload>,
+) -> Result<Json<StoredImage>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let (temp_dir, blob_key, max_file) = {
+        let config = state.read_config("upload_image_json").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+        (
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.max_file_bytes(),
+        )
+    };
 
-    info!("addr: {:?}, action: list, page: {:?}", addr, page);
+    let data = BASE64_STANDARD
+        .decode(payload.data_base64.trim())
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid data_base64: {}", e),
+            )
+        })?;
+    let stream = futures::stream::once(async {
+        Ok::<_, std::convert::Infallible>(axum::body::Bytes::from(data))
+    });
+    let file = receive_file(Box::pin(stream), &temp_dir, blob_key.as_ref(), max_file).await?;
 
-    Ok(Json(serde_json::json!({
-        "total": total,
-        "page": page,
-        "page_size": page_size,
-        "data": data
-    })))
+    let fields = UploadFields {
+        names: payload.name.into_iter().collect(),
+        descs: vec![payload.desc],
+        tags: payload.tags,
+        strip_metadata: payload.strip_metadata.or(strip_metadata_header(&headers)?),
+        captured_at: None,
+    };
+    let mut metas = store_files(&state, &addr, token, vec![file], fields).await?;
+    Ok(Json(metas.remove(0)))
 }
 
-pub async fn delete_image(
+// 以原始请求体上传单个文件，名称取自路径，描述和标签取自 X-Image-Desc / X-Image-Tags 头
+#[utoipa::path(
+    put,
+    path = "/images/{id}",
+    summary = "Upload an image from the raw request body",
+    params(
+        ("id" = String, Path, description = "Name to store the image under"),
+        ("X-Image-Desc" = Option<String>, Header, nullable = false),
+        (
+            "X-Image-Tags" = Option<String>,
+            Header,
+            nullable = false,
+            description = "Comma-separated tags",
+        ),
+        (
+            "X-Strip-Metadata" = Option<bool>,
+            Header,
+            nullable = false,
+            description = "Override the strip_metadata setting for this upload",
+        ),
+    ),
+    request_body(content(("application/octet-stream"))),
+    responses(
+        (status = 200, description = "The stored metadata", body = StoredImage),
+        (status = 400, description = "Empty or invalid body"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (
+            status = 409,
+            description = "A visually identical image already exists (similar_images = \"reject\")",
+        ),
+        (status = 413, description = "File exceeds max_file_mb"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+        (status = 415, description = "Not an accepted image format"),
+        (status = 503, description = "Image processing pool is busy"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn put_image(
     State(state): State<Arc<AppState>>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    headers: header::HeaderMap,
     Path(name): Path<String>,
-) -> Result<StatusCode, (StatusCode, String)> {
+    request: Request,
+) -> Result<Json<StoredImage>, (StatusCode, String)> {
+    let headers = request.headers().clone();
     let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
-    {
-        let config = state.config.read().await;
+    let (temp_dir, blob_key, max_file) = {
+        let config = state.read_config("put_image").await;
         check_ip(&config, &addr)?;
         check_token(&config, token)?;
-    }
-    let mut config = state.config.write().await;
-
-    let img = if let Some(index) = config.images.iter().position(|i| i.name == name) {
-        config.images.remove(index)
-    } else {
-        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+        check_disk_space(&config)?;
+        (
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.max_file_bytes(),
+        )
     };
 
-    // 检查是否还有其他图片使用相同的 Hash (去重)
-    let hash_in_use = config.images.iter().any(|i| i.hash == img.hash);
-
-    if !hash_in_use {
-        // 忽略文件不存在的错误
-        let _ = fs::remove_file(config.images_dir().join(&img.hash)).await;
-        let _ = fs::remove_file(config.thumbs_dir().join(&img.hash)).await;
+    // with_limited_body 使请求体同样受 DefaultBodyLimit 限制
+    let stream = request.with_limited_body().into_body().into_data_stream();
+    let file = receive_file(stream, &temp_dir, blob_key.as_ref(), max_file).await?;
+    if file.size == 0 {
+        return Err((StatusCode::BAD_REQUEST, "Empty body".to_string()));
     }
 
-    // 保存到磁盘
-    save_config(&state.config_path, &config).map_err(|e| {
-        error!("Failed to save config: {}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
-    })?;
+    let fields = UploadFields {
+        names: vec![name],
+        descs: header_text(&headers, "x-image-desc").into_iter().collect(),
+        tags: header_text(&headers, "x-image-tags")
+            .map(|text| text.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+        strip_metadata: strip_metadata_header(&headers)?,
+        captured_at: None,
+    };
+    let mut metas = store_files(&state, &addr, token, vec![file], fields).await?;
+    Ok(Json(metas.remove(0)))
+}
 
-    info!("addr: {:?}, action: delete, name: {:?}", addr, name);
+// 旋转/翻转图片：变换结果作为新 blob 保存，并成为该记录的新版本
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct TransformRequest {
+    /// Applied in order; rotations are clockwise
+    #[schema(min_items = 1)]
+    operations: Vec<Transform>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/images/{id}/transform",
+    summary = "Rotate or flip an image",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    request_body = TransformRequest,
+    responses(
+        (
+            status = 200,
+            description = "The metadata with the transformed content as its latest version",
+            body = StoredImage,
+        ),
+        (status = 400, description = "No operations"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Image not found"),
+        (
+            status = 422,
+            description = "Unknown operation, or the image could not be decoded or re-encoded",
+        ),
+        (status = 503, description = "Image processing pool is busy"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn transform_image(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: header::HeaderMap,
+    Json(payload): Json<TransformRequest>,
+) -> Result<Json<StoredImage>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    if payload.operations.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No operations".to_string()));
+    }
+    let (name, captured_at, path, temp_dir, limits, blob_key) = {
+        let config = state.read_config("transform_image").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+
+        // 先匹配名称或别名，再按 Hash 匹配
+        let img = config
+            .find_image(&id)
+            .ok_or((StatusCode::NOT_FOUND, "Image not found".to_string()))?;
+        (
+            img.name.clone(),
+            img.captured_at,
+            config.images_dir().join(&img.hash),
+            config.temp_dir().clone(),
+            config.decode_limits(),
+            config.blob_key.clone(),
+        )
+    };
+
+    // 解码、变换并重新编码 (Blocking)
+    let key = blob_key.clone();
+    let operations = payload.operations.clone();
+    let data = state
+        .pool
+        .run(move || {
+            let data = read_blob(&path, key.as_ref()).map_err(image::ImageError::IoError)?;
+            apply_transforms(&data, &operations, limits)
+        })
+        .await
+        .map_err(|e| match e {
+            PoolError::Busy => server_busy(),
+            PoolError::Failed => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Transform failed".to_string(),
+            ),
+        })?
+        .map_err(|e| match e {
+            image::ImageError::IoError(e) => {
+                error!("Failed to read blob for {:?}: {}", id, e);
+                (StatusCode::NOT_FOUND, "File not found".to_string())
+            }
+            e => {
+                warn!("Transform failed for {:?}: {}", id, e);
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Transform failed".to_string(),
+                )
+            }
+        })?;
+
+    let stream = futures::stream::once(async {
+        Ok::<_, std::convert::Infallible>(axum::body::Bytes::from(data))
+    });
+    // 由已有图片生成，不受上传大小限制
+    let file = receive_file(Box::pin(stream), &temp_dir, blob_key.as_ref(), u64::MAX).await?;
+    // 以原名称保存即成为新版本；重新编码会丢失 EXIF，沿用原来的拍摄时间
+    let fields = UploadFields {
+        names: vec![name],
+        strip_metadata: Some(false),
+        captured_at,
+        ..Default::default()
+    };
+    let mut metas = store_files(&state, &addr, token, vec![file], fields).await?;
+    info!(
+        "addr: {:?}, action: transform, id: {:?}, operations: {:?}",
+        addr, id, payload.operations
+    );
+    Ok(Json(metas.remove(0)))
+}
+
+// 分块上传：POST /uploads 创建会话，PUT /uploads/{id}/chunks/{n} 上传分块，
+// POST /uploads/{id}/complete 按序号拼接、校验 Hash 并登记元数据
+// 会话保存在 temp/uploads/{id} 下，分块与普通上传一样按需加密
+#[derive(Deserialize, serde::Serialize, Default, utoipa::ToSchema)]
+pub struct UploadSession {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    desc: String,
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Override the strip_metadata setting for this upload
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    strip_metadata: Option<bool>,
+}
+
+fn upload_session_dir(config: &AppConfig, id: &str) -> Result<PathBuf, (StatusCode, String)> {
+    // 会话 id 必须是 uuid，避免路径穿越
+    let id = uuid::Uuid::parse_str(id).map_err(|_| {
+        (
+            StatusCode::NOT_FOUND,
+            "Upload session not found".to_string(),
+        )
+    })?;
+    let dir = config.temp_dir().join("uploads").join(id.to_string());
+    if !dir.is_dir() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Upload session not found".to_string(),
+        ));
+    }
+    Ok(dir)
+}
+
+// 按序号排列的已上传分块
+async fn list_chunks(dir: &std::path::Path) -> std::io::Result<Vec<(u32, u64)>> {
+    let mut chunks = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(n) = entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+            chunks.push((n, entry.metadata().await?.len()));
+        }
+    }
+    chunks.sort_unstable();
+    Ok(chunks)
+}
+
+async fn open_chunk(
+    path: PathBuf,
+    key: Option<BlobKey>,
+) -> std::io::Result<futures::stream::BoxStream<'static, std::io::Result<axum::body::Bytes>>> {
+    blob_stream(&path, key.as_ref()).await
+}
+
+// 清理超过 upload_session_ttl_hours 未完成的会话
+async fn prune_upload_sessions(root: &std::path::Path, ttl: std::time::Duration) {
+    let Ok(mut entries) = fs::read_dir(root).await else {
+        return;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let expired = entry
+            .metadata()
+            .await
+            .and_then(|m| m.modified())
+            .is_ok_and(|t| t.elapsed().unwrap_or_default() > ttl);
+        if expired {
+            info!("Removing expired upload session {:?}", entry.file_name());
+            let _ = fs::remove_dir_all(entry.path()).await;
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploads",
+    summary = "Open a chunked upload session",
+    request_body(content = Option<UploadSession>, description = "Metadata for the image; may be omitted"),
+    responses(
+        (
+            status = 201,
+            description = "Session created",
+            body = Object,
+            example = json!({"id": "0b6f2a52-6d2e-4a8c-9a0e-5f1c3b7d9e21"}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn create_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    payload: Option<Json<UploadSession>>,
+) -> Result<(StatusCode, Json<serde_json::Value>), (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let (root, ttl) = {
+        let config = state.read_config("create_upload").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+        (
+            config.temp_dir().join("uploads"),
+            std::time::Duration::from_secs(config.upload_session_ttl_hours * 3600),
+        )
+    };
+    prune_upload_sessions(&root, ttl).await;
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let dir = root.join(&id);
+    let session = payload.map(|Json(s)| s).unwrap_or_default();
+    let result = async {
+        fs::create_dir_all(&dir).await?;
+        fs::write(dir.join("session.json"), serde_json::to_vec(&session)?).await
+    }
+    .await;
+    if let Err(e) = result {
+        error!("Failed to create upload session: {}", e);
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string()));
+    }
+
+    info!("addr: {:?}, action: create_upload, id: {:?}", addr, id);
+    Ok((StatusCode::CREATED, Json(serde_json::json!({ "id": id }))))
+}
+
+// 查询会话已收到的分块，用于断点续传
+#[utoipa::path(
+    get,
+    path = "/uploads/{id}",
+    summary = "List the chunks received so far",
+    params(
+        ("id" = String, Path, description = "Upload session id"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Received chunk numbers",
+            body = Object,
+            example = json!({"id": "0b6f2a52-6d2e-4a8c-9a0e-5f1c3b7d9e21", "chunks": [0, 1, 2]}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Upload session not found"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn get_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: header::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let dir = {
+        let config = state.read_config("get_upload").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        upload_session_dir(&config, &id)?
+    };
+    let chunks = list_chunks(&dir).await.map_err(|e| {
+        error!("Failed to read upload session {:?}: {}", dir, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
+    })?;
+    Ok(Json(serde_json::json!({
+        "id": id,
+        "chunks": chunks.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+    })))
+}
+
+#[utoipa::path(
+    put,
+    path = "/uploads/{id}/chunks/{n}",
+    summary = "Upload one chunk",
+    params(
+        ("id" = String, Path, description = "Upload session id"),
+        (
+            "n" = u32,
+            Path,
+            description = "Chunk number, starting at 0; re-sending a number replaces it",
+        ),
+    ),
+    request_body(content(("application/octet-stream"))),
+    responses(
+        (status = 204, description = "Chunk stored"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Upload session not found"),
+        (status = 413, description = "File exceeds max_file_mb"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn put_upload_chunk(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path((id, n)): Path<(String, u32)>,
+    request: Request,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let token = request
+        .headers()
+        .get("x-admin-token")
+        .and_then(|v| v.to_str().ok());
+    let (dir, temp_dir, blob_key, max_file) = {
+        let config = state.read_config("put_upload_chunk").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+        (
+            upload_session_dir(&config, &id)?,
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.max_file_bytes(),
+        )
+    };
+
+    // 分块先写入临时文件再移动，重传同一序号时整体替换
+    let stream = request.with_limited_body().into_body().into_data_stream();
+    let mut received = receive_file(stream, &temp_dir, blob_key.as_ref(), max_file).await?;
+    move_file_async(&received.temp_path, &dir.join(n.to_string()))
+        .await
+        .map_err(|e| {
+            error!("Failed to move chunk: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
+        })?;
+    received.guard.persist();
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct CompleteUpload {
+    /// SHA256 of the whole file
+    hash: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploads/{id}/complete",
+    summary = "Assemble the chunks and store the image",
+    params(
+        ("id" = String, Path, description = "Upload session id"),
+        (
+            "X-Strip-Metadata" = Option<bool>,
+            Header,
+            nullable = false,
+            description = "Override the strip_metadata setting for this upload",
+        ),
+    ),
+    request_body = CompleteUpload,
+    responses(
+        (status = 200, description = "The stored metadata", body = StoredImage),
+        (status = 400, description = "Missing chunk or hash mismatch"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Upload session not found"),
+        (
+            status = 409,
+            description = "A visually identical image already exists (similar_images = \"reject\")",
+        ),
+        (status = 413, description = "Assembled file exceeds max_file_mb"),
+        (status = 507, description = "Free space on the data_dir filesystem is below min_free_mb"),
+        (status = 415, description = "Not an accepted image format"),
+        (status = 503, description = "Image processing pool is busy"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn complete_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: header::HeaderMap,
+    Json(payload): Json<CompleteUpload>,
+) -> Result<Json<StoredImage>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let (dir, temp_dir, blob_key, max_size) = {
+        let config = state.read_config("complete_upload").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        check_disk_space(&config)?;
+        (
+            upload_session_dir(&config, &id)?,
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.max_file_bytes(),
+        )
+    };
+
+    let io_error = |e: std::io::Error| {
+        error!("Failed to read upload session {:?}: {}", dir, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
+    };
+    let session: UploadSession = fs::read(dir.join("session.json"))
+        .await
+        .map_err(io_error)
+        .and_then(|data| {
+            serde_json::from_slice(&data)
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+        })?;
+
+    // 分块序号必须从 0 开始连续
+    let chunks = list_chunks(&dir).await.map_err(io_error)?;
+    if chunks.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "No chunks uploaded".to_string()));
+    }
+    if let Some(missing) = chunks
+        .iter()
+        .enumerate()
+        .find(|(i, (n, _))| *i as u32 != *n)
+        .map(|(i, _)| i)
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Missing chunk {}", missing),
+        ));
+    }
+
+    // 依次解密读取各分块，拼接后重新计算 Hash 并写入临时文件
+    let paths: Vec<_> = chunks
+        .iter()
+        .map(|(n, _)| dir.join(n.to_string()))
+        .collect();
+    let stream = futures::stream::iter(paths)
+        .then({
+            let key = blob_key.clone();
+            move |path| open_chunk(path, key.clone())
+        })
+        .try_flatten();
+    let received = receive_file(Box::pin(stream), &temp_dir, blob_key.as_ref(), max_size).await?;
+    if !received.hash.eq_ignore_ascii_case(payload.hash.trim()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Hash mismatch: got {}", received.hash),
+        ));
+    }
+
+    let fields = UploadFields {
+        names: session.name.into_iter().collect(),
+        descs: vec![session.desc],
+        tags: session.tags,
+        strip_metadata: session.strip_metadata.or(strip_metadata_header(&headers)?),
+        captured_at: None,
+    };
+    let mut metas = store_files(&state, &addr, token, vec![received], fields).await?;
+    if let Err(e) = fs::remove_dir_all(&dir).await {
+        warn!("Failed to remove upload session {:?}: {}", dir, e);
+    }
+    info!("addr: {:?}, action: complete_upload, id: {:?}", addr, id);
+    Ok(Json(metas.remove(0)))
+}
+
+// 放弃会话并删除已上传的分块
+#[utoipa::path(
+    delete,
+    path = "/uploads/{id}",
+    summary = "Abort an upload session",
+    params(
+        ("id" = String, Path, description = "Upload session id"),
+    ),
+    responses(
+        (status = 204, description = "Session removed"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Upload session not found"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn abort_upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: header::HeaderMap,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let dir = {
+        let config = state.read_config("abort_upload").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+        upload_session_dir(&config, &id)?
+    };
+    fs::remove_dir_all(&dir).await.map_err(|e| {
+        error!("Failed to remove upload session {:?}: {}", dir, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
+    })?;
+    info!("addr: {:?}, action: abort_upload, id: {:?}", addr, id);
     Ok(StatusCode::NO_CONTENT)
 }
+
+// 将上传的数据流写入临时文件，同时计算 Hash (上传接口与 gRPC 共用)
+// 写入的字节数超过 max_size 时立即中止并返回 413，临时文件随守卫删除
+// span 中记录文件大小和计算 Hash 的累计耗时 (Hash 在接收过程中边读边算)
+#[tracing::instrument(
+    level = "debug",
+    name = "multipart_read",
+    skip_all,
+    fields(size, hash_ms)
+)]
+pub(crate) async fn receive_file<S, E>(
+    mut stream: S,
+    temp_dir: &std::path::Path,
+    blob_key: Option<&BlobKey>,
+    max_size: u64,
+) -> Result<ReceivedFile, (StatusCode, String)>
+where
+    S: futures::Stream<Item = Result<axum::body::Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    // 生成临时文件路径 (使用 uuid 避免冲突)
+    let temp_file_path = temp_dir.join(uuid::Uuid::new_v4().to_string());
+    // **创建守卫**：如果本函数中途报错退出，这个守卫会自动删除临时文件
+    let temp_guard = TempFileGuard::new(temp_file_path.clone());
+
+    // 打开临时文件准备写入；请求体的分块通常很小，经缓冲后再写入
+    let file = File::create(&temp_file_path).await.map_err(|e| {
+        error!("Failed to create temp file: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "IO Error".to_string())
+    })?;
+    let mut file = tokio::io::BufWriter::with_capacity(write_buffer(), file);
+
+    let mut hasher = Sha256::new();
+    let mut hash_time = std::time::Duration::ZERO;
+    let mut file_size = 0u64;
+    // 配置了密钥时边写边加密，明文不落盘；Hash 始终基于明文计算
+    let mut encoder = BlobEncoder::new(blob_key);
+
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?
+    {
+        file_size += chunk.len() as u64;
+        if file_size > max_size {
+            return Err((
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("File exceeds {} bytes", max_size),
+            ));
+        }
+        let started = std::time::Instant::now();
+        hasher.update(&chunk);
+        hash_time += started.elapsed();
+        let res = match encoder.update(&chunk) {
+            Ok(data) => file.write_all(&data).await,
+            Err(e) => Err(e),
+        };
+        res.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    let res = match encoder.finish() {
+        Ok(data) => file.write_all(&data).await,
+        Err(e) => Err(e),
+    };
+    res.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    // 刷入磁盘
+    file.flush()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let span = tracing::Span::current();
+    span.record("size", file_size);
+    span.record("hash_ms", hash_time.as_secs_f64() * 1000.0);
+    Ok(ReceivedFile {
+        temp_path: temp_file_path,
+        guard: temp_guard,
+        hash: hex::encode(hasher.finalize()),
+        size: file_size,
+    })
+}
+
+// 将接收到的文件移入存储并创建元数据 (上传接口与 gRPC 共用)
+// names/descs 按顺序与 files 对应，tags 作用于全部文件
+// 上传结果：元数据之外说明内容是否与已有 blob 重复，以及引用同一 blob 的其他名称
+// 和视觉上相同 (感知哈希相近) 的其他记录
+#[derive(serde::Serialize, utoipa::ToSchema)]
+#[schema(as = UploadResult)]
+pub struct StoredImage {
+    #[serde(flatten)]
+    pub meta: ImageMeta,
+    /// The content was already stored; no new storage was used
+    pub deduplicated: bool,
+    /// Other names and aliases referencing the same blob
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<String>,
+    /// Other images that look the same (perceptual hash within similar_distance)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub similar: Vec<String>,
+}
+
+// 上传请求中除文件以外的字段；names/descs 按顺序与文件对应，tags 作用于全部文件
+#[derive(Default)]
+pub(crate) struct UploadFields {
+    pub names: Vec<String>,
+    pub descs: Vec<String>,
+    pub tags: Vec<String>,
+    // 覆盖 strip_metadata 配置
+    pub strip_metadata: Option<bool>,
+    // 文件中没有拍摄时间时使用的值 (例如变换后重新编码的图片)
+    pub captured_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[tracing::instrument(
+    level = "debug",
+    name = "store_files",
+    skip_all,
+    fields(files = files.len())
+)]
+pub(crate) async fn store_files(
+    state: &Arc<AppState>,
+    addr: &SocketAddr,
+    token: Option<&str>,
+    mut files: Vec<ReceivedFile>,
+    fields: UploadFields,
+) -> Result<Vec<StoredImage>, (StatusCode, String)> {
+    let UploadFields {
+        names,
+        descs,
+        tags,
+        strip_metadata,
+        captured_at: fallback_capture,
+    } = fields;
+    let (
+        images_dir,
+        thumbnails,
+        limits,
+        blob_key,
+        strip_metadata,
+        allowed,
+        similar,
+        moderation_url,
+    ) = {
+        let config = state.read_config("store_files").await;
+        let allowed: Vec<_> = config
+            .allowed_formats
+            .iter()
+            .filter_map(|ext| extension_mime(ext))
+            .collect();
+        (
+            config.images_dir().clone(),
+            config.thumbnail_pixels.is_some(),
+            config.decode_limits(),
+            config.blob_key.clone(),
+            strip_metadata.unwrap_or(config.strip_metadata),
+            allowed,
+            config.similar_images,
+            config.moderation_url.clone(),
+        )
+    };
+
+    // 按文件头识别格式，任一文件不是允许的图片格式时拒绝整个请求，避免被当作任意文件的存储
+    let mut mimes = Vec::with_capacity(files.len());
+    for received in &files {
+        let (path, key) = (received.temp_path.clone(), blob_key.clone());
+        let mime = tokio::task::spawn_blocking(move || sniff_content_type(&path, key.as_ref()))
+            .instrument(tracing::debug_span!("sniff_content_type"))
+            .await
+            .unwrap_or_default();
+        if !mime.as_deref().is_some_and(|m| allowed.contains(&m)) {
+            warn!(
+                "addr: {:?}, action: upload, rejected content type: {:?}",
+                addr, mime
+            );
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Unsupported media type".to_string(),
+            ));
+        }
+        mimes.push(mime);
+    }
+
+    // 计算感知哈希 (去除元数据不影响像素，在此之前计算即可)；无法解码的文件 (如视频) 没有感知哈希
+    let mut phashes = Vec::with_capacity(files.len());
+    for received in &files {
+        let (path, key) = (received.temp_path.clone(), blob_key.clone());
+        let phash = match state
+            .pool
+            .run(move || perceptual_hash(&path, limits, key.as_ref()).ok())
+            .instrument(tracing::debug_span!("perceptual_hash"))
+            .await
+        {
+            Ok(phash) => phash,
+            Err(PoolError::Busy) => return Err(server_busy()),
+            Err(PoolError::Failed) => None,
+        };
+        phashes.push(phash);
+    }
+    // reject 时，任一文件与已有图片视觉上相同则拒绝整个请求；以相同名称上传的新版本除外
+    if similar == SimilarImages::Reject {
+        let config = state.read_config("store_files").await;
+        for (i, (received, phash)) in files.iter().zip(&phashes).enumerate() {
+            let Some(phash) = phash else {
+                continue;
+            };
+            if let Some(existing) = config
+                .similar_images(*phash, &received.hash)
+                .into_iter()
+                .find(|n| Some(n) != names.get(i))
+            {
+                warn!(
+                    "addr: {:?}, action: upload, rejected similar to: {:?}",
+                    addr, existing
+                );
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Similar image already exists: {}", existing),
+                ));
+            }
+        }
+    }
+
+    // 内容审核：被标记的内容隔离等待管理员审核；审核服务不可用时同样隔离，不直接公开
+    let mut verdicts = vec![None; files.len()];
+    if let Some(url) = &moderation_url {
+        for (received, (verdict, mime)) in files.iter().zip(verdicts.iter_mut().zip(&mimes)) {
+            let (path, key) = (received.temp_path.clone(), blob_key.clone());
+            let result = async {
+                match tokio::task::spawn_blocking(move || read_blob(&path, key.as_ref())).await {
+                    Ok(Ok(data)) => moderation::check(url, data, mime.as_deref()).await,
+                    Ok(Err(e)) => Err(e.into()),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            .instrument(tracing::debug_span!("moderation"))
+            .await;
+            *verdict = result.unwrap_or_else(|e| {
+                warn!("Moderation failed for {:?}: {}", received.hash, e);
+                Some("moderation unavailable".to_string())
+            });
+        }
+    }
+
+    // 3. 文件移动处理 (I/O 阶段，不持有锁)
+    // 逻辑：基于 Hash 去重。如果目标文件已存在，则直接复用，删除临时文件。
+    let mut captured = Vec::with_capacity(files.len());
+    for received in &mut files {
+        // 去除元数据会改变内容，需在按 Hash 去重之前完成；拍摄时间在去除前读取
+        let mut stripped_capture = None;
+        if strip_metadata {
+            let temp_path = received.temp_path.clone();
+            let key = blob_key.clone();
+            let stripped = tokio::task::spawn_blocking(move || {
+                let data = read_blob(&temp_path, key.as_ref())?;
+                let Some(new) = strip_jpeg_metadata(&data) else {
+                    return Ok(None);
+                };
+                write_blob(&temp_path, &new, key.as_ref())?;
+                Ok::<_, std::io::Error>(Some((
+                    capture_time_from(&data),
+                    hex::encode(Sha256::digest(&new)),
+                    new.len() as u64,
+                )))
+            })
+            .instrument(tracing::debug_span!("strip_metadata"))
+            .await
+            .map_err(|_| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Metadata strip failed".to_string(),
+                )
+            })?
+            .map_err(|e| {
+                error!("Failed to strip metadata: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Metadata strip failed".to_string(),
+                )
+            })?;
+            if let Some((captured_at, hash, size)) = stripped {
+                received.hash = hash;
+                received.size = size;
+                stripped_capture = Some(captured_at);
+            }
+        }
+
+        let target_path = images_dir.join(&received.hash);
+
+        let deduplicated = target_path.exists();
+        if deduplicated {
+            // 文件已存在，不需要移动，不需要生成缩略图
+            // 这里的 temp_guard 在函数结束或 drop 时会自动删除临时文件，符合预期
+        } else {
+            // 文件不存在，移动临时文件到目标位置
+            move_file_async(&received.temp_path, &target_path)
+                .instrument(tracing::debug_span!("rename", hash = %received.hash))
+                .await
+                .map_err(|e| {
+                    error!("Failed to move file: {}", e);
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "File move failed".to_string(),
+                    )
+                })?;
+            // 缩略图在元数据保存后交给后台队列生成
+            received.guard.persist();
+        }
+
+        // 读取 EXIF 拍摄时间并识别格式 (Blocking)
+        let key = blob_key.clone();
+        let (captured_at, content_type) = tokio::task::spawn_blocking(move || {
+            (
+                stripped_capture
+                    .unwrap_or_else(|| capture_time(&target_path, key.as_ref()))
+                    .or(fallback_capture),
+                sniff_content_type(&target_path, key.as_ref()),
+            )
+        })
+        .await
+        .unwrap_or_default();
+        captured.push((captured_at, content_type, deduplicated));
+    }
+
+    let mut config = state
+        .write_config("store_files")
+        .instrument(tracing::debug_span!("config_lock"))
+        .await;
+    let mut names = names.into_iter();
+    let mut descs = descs.into_iter();
+    let mut metas = Vec::with_capacity(files.len());
+
+    for (((received, (captured_at, content_type, deduplicated)), phash), quarantined) in
+        files.iter().zip(captured).zip(phashes).zip(verdicts)
+    {
+        // 重复内容沿用已有记录的 BlurHash；新内容的缩略图和 BlurHash 由后台队列生成
+        let blurhash = config.blurhash_of(&received.hash);
+        let thumbnail_pending = thumbnails
+            && (!deduplicated
+                || config
+                    .images_with_hash(&received.hash)
+                    .any(|i| i.thumbnail_pending));
+        // 未提供 name 时按配置的 id_strategy 生成
+        let name = match names.next().filter(|n| !n.is_empty()) {
+            Some(name) => name,
+            None => config.next_image_name(),
+        };
+        let desc = descs.next().unwrap_or_default();
+
+        // 名称已存在时作为该记录的新版本；名称原是其他记录的别名时，别名改为指向新内容
+        let existing = config
+            .image_id(&name)
+            .filter(|&id| config.image(id).name == name);
+        if existing.is_none()
+            && let Some(index) = config.image_id(&name)
+        {
+            config.update_image(index, |img| img.aliases.retain(|a| *a != name));
+        }
+
+        // 开启 alias_duplicates 时，相同内容以新名称上传只记录为已有记录的别名
+        let canonical = if config.alias_duplicates && existing.is_none() {
+            config.hash_id(&received.hash)
+        } else {
+            None
+        };
+        let meta = if let Some(index) = existing {
+            config.update_image(index, |img| {
+                // 内容未变化时不产生新版本
+                if img.hash != received.hash {
+                    img.push_version(ImageVersion {
+                        hash: received.hash.clone(),
+                        size: received.size,
+                        created_at: chrono::Utc::now(),
+                        captured_at,
+                        content_type,
+                        uploaded_by: token.map(token_fingerprint),
+                        blurhash,
+                        phash: phash.map(|p| format!("{:016x}", p)),
+                    });
+                    img.quarantined = quarantined.clone();
+                    img.thumbnail_pending = thumbnail_pending;
+                }
+                if !desc.is_empty() {
+                    img.desc = desc;
+                }
+                img.add_tags(tags.clone());
+                img.clone()
+            })
+        } else if let Some(index) = canonical {
+            config.update_image(index, |canonical| {
+                canonical.aliases.push(name.clone());
+                canonical.add_tags(tags.clone());
+                canonical.clone()
+            })
+        } else {
+            let mut meta = ImageMeta {
+                name: name.clone(),
+                desc,
+                hash: received.hash.clone(),
+                size: received.size,
+                created_at: chrono::Utc::now(),
+                captured_at,
+                content_type,
+                aliases: Vec::new(),
+                tags: Vec::new(),
+                pinned: false,
+                uploaded_by: token.map(token_fingerprint),
+                broken_sources: Vec::new(),
+                album: None,
+                private: false,
+                versions: Vec::new(),
+                blurhash,
+                phash: phash.map(|p| format!("{:016x}", p)),
+                quarantined: quarantined.clone(),
+                thumbnail_pending,
+            };
+            meta.add_tags(tags.clone());
+            config.push_image(meta.clone());
+            meta
+        };
+
+        info!(
+            "addr: {:?}, action: upload, name: {:?}, hash: {:?}, deduplicated: {:?}",
+            addr, name, meta.hash, deduplicated
+        );
+        if let Some(reason) = &quarantined {
+            warn!(
+                "addr: {:?}, action: upload, name: {:?}, quarantined: {:?}",
+                addr, name, reason
+            );
+        }
+        // 引用同一 blob 的其他名称 (含别名)
+        let duplicates = config
+            .images_with_hash(&meta.hash)
+            .flat_map(|i| std::iter::once(&i.name).chain(&i.aliases))
+            .filter(|n| **n != name)
+            .cloned()
+            .collect();
+        // 视觉上相同但内容不同的其他记录
+        let similar = match (similar, phash) {
+            (SimilarImages::Off, _) | (_, None) => Vec::new(),
+            (_, Some(phash)) => config
+                .similar_images(phash, &meta.hash)
+                .into_iter()
+                .filter(|n| *n != meta.name)
+                .collect(),
+        };
+        metas.push(StoredImage {
+            meta,
+            deduplicated,
+            duplicates,
+            similar,
+        });
+    }
+
+    if let Err(e) = state.persist(&config) {
+        error!("Failed to save config: {}", e);
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Save config failed".to_string(),
+        ));
+    }
+
+    for m in &metas {
+        state.stats.uploads.fetch_add(1, Ordering::Relaxed);
+        state
+            .stats
+            .upload_bytes
+            .fetch_add(m.meta.size, Ordering::Relaxed);
+        if m.deduplicated {
+            state.stats.dedup_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        state.usage.record_upload(
+            m.meta.uploaded_by.as_deref().unwrap_or("unknown"),
+            m.meta.size,
+        );
+    }
+
+    // 新内容的缩略图交给后台队列，上传无需等待
+    let mut pending: Vec<_> = metas
+        .iter()
+        .filter(|m| !m.deduplicated && m.meta.thumbnail_pending)
+        .map(|m| m.meta.hash.clone())
+        .collect();
+    pending.dedup();
+    for hash in pending {
+        state.queue_thumbnail(hash);
+    }
+
+    // 新的 JPEG/PNG 内容交给后台任务：无损优化、预先生成 original_formats 的副本
+    let mut fresh: Vec<_> = metas
+        .iter()
+        .filter(|m| !m.deduplicated)
+        .filter(|m| {
+            matches!(
+                m.meta.content_type.as_deref(),
+                Some("image/jpeg" | "image/png")
+            )
+        })
+        .map(|m| m.meta.hash.clone())
+        .collect();
+    fresh.dedup();
+    if !fresh.is_empty() && (config.optimize_uploads || !config.original_formats.is_empty()) {
+        tokio::spawn(tasks::process_uploads(state.clone(), fresh));
+    }
+
+    Ok(metas)
+}
+
+// 下载图片
+#[derive(Deserialize)]
+pub struct DownloadParams {
+    thumb: Option<bool>,
+    // 相册 token，用于读取相册中的私有图片
+    token: Option<String>,
+    // 历史版本号，缺省为当前版本
+    version: Option<usize>,
+    // 转换为指定格式输出：webp、jpeg、png 或 avif
+    format: Option<String>,
+}
+
+// 下载时允许转换的目标格式
+fn download_format(name: &str) -> Result<image::ImageFormat, (StatusCode, String)> {
+    match name.to_ascii_lowercase().as_str() {
+        "webp" => Ok(image::ImageFormat::WebP),
+        "jpeg" | "jpg" => Ok(image::ImageFormat::Jpeg),
+        "png" => Ok(image::ImageFormat::Png),
+        "avif" => Ok(image::ImageFormat::Avif),
+        _ => Err((StatusCode::BAD_REQUEST, "Unsupported 'format'".to_string())),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/images/{id}",
+    summary = "Download an image",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+        (
+            "thumb" = Option<bool>,
+            Query,
+            description = "Return the thumbnail instead of the original",
+        ),
+        (
+            "version" = Option<usize>,
+            Query,
+            minimum = 1,
+            description = "Download an earlier version (by name only)",
+        ),
+        (
+            "format" = Option<String>,
+            Query,
+            description = "Transcode to webp, jpeg, png or avif (cached); overrides Accept negotiation for thumbnails",
+        ),
+        (
+            "token" = Option<String>,
+            Query,
+            description = "Album token granting read access to private images in that album",
+        ),
+        ("Range" = Option<String>, Header, nullable = false),
+    ),
+    responses(
+        (status = 200, description = "Image content", content(("application/octet-stream"))),
+        (status = 206, description = "Partial content"),
+        (status = 304, description = "Not modified (If-None-Match or If-Modified-Since)"),
+        (status = 416, description = "Range not satisfiable"),
+        (status = 404, description = "Image not found"),
+        (status = 422, description = "The image could not be converted to the requested format"),
+        (status = 400, description = "Unsupported format"),
+        (status = 503, description = "Image processing pool is busy"),
+    ),
+)]
+pub async fn download_image(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let is_thumb = params.thumb.unwrap_or(false);
+    let format = params.format.as_deref().map(download_format).transpose()?;
+    let (
+        hash,
+        mime,
+        modified,
+        temp_dir,
+        images_dir,
+        thumbs_dir,
+        limits,
+        blob_key,
+        upstream,
+        variant,
+        vary,
+        served_to,
+    ) = {
+        let config = state.read_config("download_image").await;
+        check_ip(&config, &addr)?;
+
+        // 查找逻辑：先匹配 Name，如果没找到且 id 看起来像 hash，则匹配 Hash
+        let img = config.image_id(&id).map(|i| config.image(i));
+        let hash = if let Some(version) = params.version {
+            // 指定版本时只按名称查找
+            let img = img.ok_or((StatusCode::NOT_FOUND, "Version not found".to_string()))?;
+            check_readable(&config, &headers, params.token.as_deref(), [img])?;
+            let (hash, _) = img
+                .version_blob(version)
+                .ok_or((StatusCode::NOT_FOUND, "Version not found".to_string()))?;
+            Some(hash.to_string())
+        } else if let Some(img) = img {
+            check_readable(&config, &headers, params.token.as_deref(), [img])?;
+            Some(img.hash.clone())
+        } else if id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            // 按 Hash 下载时，引用该内容的记录中至少要有一条可读
+            check_readable(
+                &config,
+                &headers,
+                params.token.as_deref(),
+                config.images_with_hash(&id),
+            )?;
+            Some(id.clone())
+        } else {
+            None
+        };
+        // 按名称访问时，内容在该名称下出现的时间即最后修改时间；按 Hash 访问时使用文件时间
+        let modified = match params.version {
+            Some(version) if img.is_some_and(|i| i.version() != version) => img
+                .and_then(|i| i.versions.get(version.checked_sub(1)?))
+                .map(|v| v.created_at),
+            _ => img.map(|i| i.created_at),
+        };
+        // 用于摘要报告中下载最多的图片，缩略图不计
+        if let Some(img) = img
+            && !is_thumb
+        {
+            state.stats.record_download(&img.name);
+        }
+        // 与读取权限的检查范围一致：按名称时为该记录，按 Hash 时为引用该内容的记录
+        let token = params.token.as_deref();
+        let served_to = match img {
+            Some(img) => served_to(&config, &headers, token, [img]),
+            None => served_to(&config, &headers, token, config.images_with_hash(&id)),
+        };
+        // 相同 hash 的记录内容相同，取任意一条记录 (或历史版本) 的类型即可
+        let mime = hash.as_ref().and_then(|hash| {
+            config.images().find_map(|i| {
+                std::iter::once((&i.hash, &i.content_type))
+                    .chain(i.versions.iter().map(|v| (&v.hash, &v.content_type)))
+                    .find(|(h, t)| *h == hash && t.is_some())
+                    .and_then(|(_, t)| t.clone())
+            })
+        });
+        let mime = match is_thumb {
+            true => mime.map(|m| thumbnail_content_type(&m).to_string()),
+            false => mime,
+        };
+        // 未指定 format 时按 Accept 协商输出格式：缩略图使用 thumbnail_formats，
+        // JPEG/PNG 原图使用 original_formats；None 表示未开启协商
+        let formats = if is_thumb {
+            &config.thumbnail_formats[..]
+        } else if matches!(mime.as_deref(), Some("image/jpeg" | "image/png")) {
+            &config.original_formats[..]
+        } else {
+            &[]
+        };
+        let negotiated = (format.is_none() && !formats.is_empty()).then(|| {
+            let accept = headers
+                .get(header::ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("");
+            formats
+                .iter()
+                .filter_map(image::ImageFormat::from_extension)
+                .find(|f| accept.contains(f.to_mime_type()))
+        });
+        // 需要输出的格式副本；与原图格式相同时不转换
+        let kind = if is_thumb { "thumb" } else { "orig" };
+        let variant = format
+            .or(negotiated.flatten())
+            .filter(|f| mime.as_deref() != Some(f.to_mime_type()))
+            .zip(hash.as_ref())
+            .map(|(format, hash)| (config.variant_path(hash, kind, format), format));
+        // 协商结果随 Accept 变化，需要 Vary
+        let vary = negotiated.is_some();
+        (
+            hash,
+            mime,
+            modified,
+            config.temp_dir().clone(),
+            config.images_dir().clone(),
+            config.thumbs_dir().clone(),
+            config.decode_limits(),
+            config.blob_key.clone(),
+            config.upstream.clone(),
+            variant,
+            vary,
+            served_to,
+        )
+    };
+
+    let Some(hash) = hash else {
+        let Some(upstream) = upstream else {
+            return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+        };
+        // 本地还没有该名称的元数据，直接转发上游的响应
+        let stream = upstream::proxy(&upstream, &id, is_thumb)
+            .await
+            .map_err(|e| {
+                warn!("Upstream proxy failed for {:?}: {}", id, e);
+                (StatusCode::NOT_FOUND, "Image not found".to_string())
+            })?;
+        info!(
+            "addr: {:?}, action: download, id: {:?}, thumb: {:?}, upstream: true",
+            addr, id, is_thumb
+        );
+        return Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(Body::from_stream(stream))
+            .unwrap());
+    };
+
+    let dir = if is_thumb { thumbs_dir } else { images_dir };
+    let path = dir.join(&hash);
+
+    if !path.exists() {
+        // 本地缺失时从上游拉取并缓存，否则返回 404
+        let Some(upstream) = upstream else {
+            return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+        };
+        upstream::fetch_blob(
+            &upstream,
+            &hash,
+            is_thumb,
+            &path,
+            &temp_dir,
+            blob_key.as_ref(),
+        )
+        .await
+        .map_err(|e| {
+            warn!("Upstream fetch failed for {:?}: {}", hash, e);
+            (StatusCode::NOT_FOUND, "File not found".to_string())
+        })?;
+        info!("Fetched {:?} (thumb: {:?}) from upstream", hash, is_thumb);
+    }
+    let modified = match modified {
+        Some(modified) => Some(modified),
+        None => file_modified(&path).await,
+    };
+
+    // 请求的格式副本，首次请求时转换并缓存
+    let mut content_type = None;
+    let path = match variant.as_ref() {
+        Some((variant_path, format)) => {
+            let ready = if variant_path.exists() {
+                touch_variant(variant_path.clone());
+                true
+            } else {
+                let (src, dst, format, key) = (
+                    path.clone(),
+                    variant_path.clone(),
+                    *format,
+                    blob_key.clone(),
+                );
+                let res = state
+                    .pool
+                    .run(move || convert_image(&src, &dst, format, limits, key.as_ref()))
+                    .await;
+                match res {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        warn!("Conversion to {:?} failed for {:?}: {}", format, hash, e);
+                        false
+                    }
+                    // 繁忙时协商的格式退回原图，显式指定的格式返回 503
+                    Err(PoolError::Busy) if params.format.is_some() => {
+                        return Err(server_busy());
+                    }
+                    Err(_) => false,
+                }
+            };
+            // 显式指定的格式无法满足时报错；协商失败时退回原格式
+            if !ready && params.format.is_some() {
+                return Err((
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Conversion failed".to_string(),
+                ));
+            }
+            if ready {
+                content_type = Some(format.to_mime_type().to_string());
+                variant_path.clone()
+            } else {
+                path
+            }
+        }
+        None => path,
+    };
+
+    // 内容由 Hash 唯一确定，可以直接作为 ETag；缩略图及其格式副本加上后缀区分
+    let etag = match (&content_type, is_thumb) {
+        (Some(_), _) => format!("\"{}\"", path.file_name().unwrap_or_default().display()),
+        (None, true) => format!("\"{}.thumb\"", hash),
+        (None, false) => format!("\"{}\"", hash),
+    };
+    let converted = content_type.is_some();
+    // 缩略图与原图格式一致；旧记录没有保存类型时按文件头识别
+    let content_type = match (content_type, mime) {
+        (Some(content_type), _) | (None, Some(content_type)) => Some(content_type),
+        (None, None) => {
+            let (p, key) = (path.clone(), blob_key.clone());
+            tokio::task::spawn_blocking(move || sniff_content_type(&p, key.as_ref()))
+                .await
+                .unwrap_or_default()
+        }
+    };
+    let not_modified = is_not_modified(&headers, &etag, modified);
+
+    let mut response = if not_modified {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap()
+    } else {
+        let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+        blob_response(&state, served_to, path, blob_key.as_ref(), &hash, range).await?
+    };
+    response
+        .headers_mut()
+        .insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+    if let Some(modified) = modified {
+        response
+            .headers_mut()
+            .insert(header::LAST_MODIFIED, http_date(modified));
+    }
+    // 原图的内容 Hash 即表示摘要 (RFC 9530)，客户端明确拒绝 sha-256 时不发送；转换后的副本不适用
+    if !is_thumb && !converted && wants_sha256_digest(&headers) {
+        let digest = hex::decode(&hash).unwrap_or_default();
+        let value = format!("sha-256=:{}:", BASE64_STANDARD.encode(digest));
+        response.headers_mut().insert(
+            "repr-digest",
+            header::HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+    if let Some(value) = content_type
+        .filter(|_| !not_modified)
+        .and_then(|t| header::HeaderValue::from_str(&t).ok())
+    {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    if vary {
+        response
+            .headers_mut()
+            .insert(header::VARY, header::HeaderValue::from_static("accept"));
+    }
+
+    info!(
+        "addr: {:?}, action: download, id: {:?}, thumb: {:?}, not_modified: {:?}",
+        addr, id, is_thumb, not_modified
+    );
+    Ok(response)
+}
+
+// 更新副本的 mtime 作为最近访问时间，供缓存淘汰使用
+fn touch_variant(path: PathBuf) {
+    tokio::task::spawn_blocking(move || {
+        let _ = std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(std::time::SystemTime::now()));
+    });
+}
+
+// 裁剪
+#[derive(Deserialize)]
+pub struct CropParams {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+    // 输出尺寸；只给出一边时按裁剪区域的比例计算另一边
+    width: Option<u32>,
+    height: Option<u32>,
+    // 输出格式，缺省与原图相同
+    format: Option<String>,
+    // 相册 token，用于裁剪相册中的私有图片
+    token: Option<String>,
+}
+
+// 裁剪结果的最大边长
+const MAX_CROP_SIZE: u32 = 4096;
+
+// 返回图片的一个裁剪区域 (可缩放)，首次请求时生成并缓存为副本
+#[utoipa::path(
+    get,
+    path = "/images/{id}/crop",
+    summary = "Crop a region of an image",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+        ("x" = u32, Query, description = "Left edge of the region, in pixels"),
+        ("y" = u32, Query, description = "Top edge of the region, in pixels"),
+        ("w" = u32, Query, minimum = 1, description = "Region width"),
+        ("h" = u32, Query, minimum = 1, description = "Region height"),
+        (
+            "width" = Option<u32>,
+            Query,
+            minimum = 1,
+            maximum = 4096,
+            description = "Output width; with only one of width/height the other follows the region's aspect ratio",
+        ),
+        ("height" = Option<u32>, Query, minimum = 1, maximum = 4096, description = "Output height"),
+        (
+            "format" = Option<String>,
+            Query,
+            description = "Output format (webp, jpeg, png or avif), defaults to the original's",
+        ),
+        (
+            "token" = Option<String>,
+            Query,
+            description = "Album token granting read access to private images in that album",
+        ),
+        ("Range" = Option<String>, Header, nullable = false),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Cropped image (cached as a variant)",
+            content(("application/octet-stream")),
+        ),
+        (status = 304, description = "Not modified (If-None-Match or If-Modified-Since)"),
+        (
+            status = 400,
+            description = "Empty or out-of-bounds region, output size too large, or unsupported format",
+        ),
+        (status = 404, description = "Image not found"),
+        (status = 422, description = "The image could not be decoded"),
+        (status = 503, description = "Image processing pool is busy"),
+    ),
+)]
+pub async fn download_crop(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<CropParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let region = (params.x, params.y, params.w, params.h);
+    if params.w == 0 || params.h == 0 {
+        return Err((StatusCode::BAD_REQUEST, "Empty crop region".to_string()));
+    }
+    let scale = |a: u32, b: u32, c: u32| ((a as u64 * b as u64 / c as u64) as u32).max(1);
+    let size = match (params.width, params.height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (Some(width), None) => Some((width, scale(width, params.h, params.w))),
+        (None, Some(height)) => Some((scale(height, params.w, params.h), height)),
+        (None, None) => None,
+    };
+    let (out_w, out_h) = size.unwrap_or((params.w, params.h));
+    if !(1..=MAX_CROP_SIZE).contains(&out_w) || !(1..=MAX_CROP_SIZE).contains(&out_h) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Output size must be between 1 and {}", MAX_CROP_SIZE),
+        ));
+    }
+    let format = params.format.as_deref().map(download_format).transpose()?;
+
+    let (hash, modified, path, variant_path, format, limits, blob_key, served_to) = {
+        let config = state.read_config("download_crop").await;
+        check_ip(&config, &addr)?;
+
+        // 先匹配名称或别名，再按 Hash 匹配；与下载一样检查读取权限
+        let token = params.token.as_deref();
+        let (img, served_to) = match config.image_id(&id).map(|i| config.image(i)) {
+            Some(img) => {
+                check_readable(&config, &headers, token, [img])?;
+                (img, served_to(&config, &headers, token, [img]))
+            }
+            None => {
+                let owners = config.images_with_hash(&id);
+                check_readable(&config, &headers, token, owners)?;
+                let img = config
+                    .find_image(&id)
+                    .ok_or((StatusCode::NOT_FOUND, "Image not found".to_string()))?;
+                let owners = config.images_with_hash(&id);
+                (img, served_to(&config, &headers, token, owners))
+            }
+        };
+        // 未指定格式时沿用原图格式，无法编码的格式 (或类型未知) 输出 PNG
+        let format = format
+            .or_else(|| {
+                img.content_type
+                    .as_deref()
+                    .and_then(image::ImageFormat::from_mime_type)
+                    .filter(|f| f.writing_enabled())
+            })
+            .unwrap_or(image::ImageFormat::Png);
+        let mut kind = format!("crop-{}-{}-{}-{}", params.x, params.y, params.w, params.h);
+        if let Some((width, height)) = size {
+            kind.push_str(&format!("-{}x{}", width, height));
+        }
+        (
+            img.hash.clone(),
+            img.created_at,
+            config.images_dir().join(&img.hash),
+            config.variant_path(&img.hash, &kind, format),
+            format,
+            config.decode_limits(),
+            config.blob_key.clone(),
+            served_to,
+        )
+    };
+    if !path.exists() {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    }
+
+    if variant_path.exists() {
+        touch_variant(variant_path.clone());
+    } else {
+        let (dst, key) = (variant_path.clone(), blob_key.clone());
+        let res = state
+            .pool
+            .run(move || crop_image(&path, &dst, region, size, format, limits, key.as_ref()))
+            .await;
+        match res {
+            Ok(Ok(())) => {}
+            Ok(Err(image::ImageError::Parameter(_))) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "Crop region out of bounds".to_string(),
+                ));
+            }
+            Ok(Err(e)) => {
+                warn!("Crop failed for {:?}: {}", hash, e);
+                return Err((StatusCode::UNPROCESSABLE_ENTITY, "Crop failed".to_string()));
+            }
+            Err(PoolError::Busy) => return Err(server_busy()),
+            Err(PoolError::Failed) => {
+                return Err((StatusCode::INTERNAL_SERVER_ERROR, "Crop failed".to_string()));
+            }
+        }
+    }
+
+    // 副本文件名由 Hash、区域、尺寸和格式唯一确定
+    let etag = format!(
+        "\"{}\"",
+        variant_path.file_name().unwrap_or_default().display()
+    );
+    let not_modified = is_not_modified(&headers, &etag, Some(modified));
+    let mut response = if not_modified {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap()
+    } else {
+        let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+        let mut response = blob_response(
+            &state,
+            served_to,
+            variant_path,
+            blob_key.as_ref(),
+            &hash,
+            range,
+        )
+        .await?;
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_static(format.to_mime_type()),
+        );
+        response
+    };
+    response
+        .headers_mut()
+        .insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+    response
+        .headers_mut()
+        .insert(header::LAST_MODIFIED, http_date(modified));
+
+    info!(
+        "addr: {:?}, action: crop, id: {:?}, region: {:?}, size: {:?}, not_modified: {:?}",
+        addr, id, region, size, not_modified
+    );
+    Ok(response)
+}
+
+// 条件请求是否命中缓存：有 If-None-Match 时只比较 ETag，否则按 If-Modified-Since 比较 (精确到秒)
+fn is_not_modified(
+    headers: &header::HeaderMap,
+    etag: &str,
+    modified: Option<chrono::DateTime<chrono::Utc>>,
+) -> bool {
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        return etag_matches(headers, etag);
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| chrono::DateTime::parse_from_rfc2822(v).ok());
+    match (since, modified) {
+        (Some(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+        _ => false,
+    }
+}
+
+// HTTP 日期格式 (IMF-fixdate)
+fn http_date(time: chrono::DateTime<chrono::Utc>) -> header::HeaderValue {
+    header::HeaderValue::from_str(&time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()).unwrap()
+}
+
+// 文件的修改时间
+async fn file_modified(path: &std::path::Path) -> Option<chrono::DateTime<chrono::Utc>> {
+    let modified = fs::metadata(path).await.ok()?.modified().ok()?;
+    Some(modified.into())
+}
+
+// If-None-Match 是否包含 etag (忽略弱校验前缀)
+fn etag_matches(headers: &header::HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| {
+            v.split(',')
+                .map(|t| t.trim().trim_start_matches("W/"))
+                .any(|t| t == etag || t == "*")
+        })
+}
+
+// Want-Repr-Digest 未给出或 sha-256 的偏好不为 0 时返回 true
+fn wants_sha256_digest(headers: &header::HeaderMap) -> bool {
+    let Some(want) = headers
+        .get("want-repr-digest")
+        .and_then(|v| v.to_str().ok())
+    else {
+        return true;
+    };
+    want.split(',')
+        .filter_map(|item| item.split_once('='))
+        .find(|(alg, _)| alg.trim().eq_ignore_ascii_case("sha-256"))
+        .is_none_or(|(_, pref)| pref.trim() != "0")
+}
+
+// 解析单个 Range (bytes=a-b / bytes=a- / bytes=-n)，返回闭区间 [start, end]
+// 无法识别的格式 (包括多段 Range) 返回 None，按完整内容响应；越界返回 Some(Err)
+fn parse_range(range: &str, total: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = range.strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 {
+                return Some(Err(()));
+            }
+            (total.saturating_sub(suffix), total.saturating_sub(1))
+        }
+        (start, "") => (start.parse().ok()?, total.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            (start, end.min(total.saturating_sub(1)))
+        }
+    };
+    if start >= total || start > end {
+        return Some(Err(()));
+    }
+    Some(Ok((start, end)))
+}
+
+// 下载流量的归属：有效的 admin token 或相册 token (均为指纹，相册 token 带 album: 前缀)，都没有时为 public
+// 相册 token 只在它确实允许读取 images 中的某条记录时计入，否则 (例如读取公开图片时顺带的 token) 计为 public
+fn served_to<'a>(
+    config: &AppConfig,
+    headers: &header::HeaderMap,
+    album_token: Option<&str>,
+    images: impl IntoIterator<Item = &'a ImageMeta>,
+) -> String {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    match (token, album_token) {
+        (Some(token), _) if check_token(config, Some(token)).is_ok() => token_fingerprint(token),
+        (_, Some(token))
+            if images
+                .into_iter()
+                .any(|i| config.album_token_allows(token, i)) =>
+        {
+            format!("album:{}", token_fingerprint(token))
+        }
+        _ => "public".to_string(),
+    }
+}
+
+// 以流的形式返回 blob 内容，range 为请求的 Range 头
+// 实际发送的字节按 served_to 计入用量统计，中断的下载只计已发送的部分
+async fn blob_response(
+    state: &Arc<AppState>,
+    served_to: String,
+    path: PathBuf,
+    key: Option<&BlobKey>,
+    hash: &str,
+    range: Option<&str>,
+) -> Result<Response, (StatusCode, String)> {
+    let open_error = |e: std::io::Error| {
+        error!("Failed to open blob {:?}: {}", path, e);
+        (StatusCode::NOT_FOUND, "File open error".to_string())
+    };
+    let total = blob_len(&path).await.map_err(open_error)?;
+
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream") // 前端处理 Content-Type
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("inline; filename=\"{}\"", hash),
+        )
+        .header(header::ACCEPT_RANGES, "bytes");
+    let state = state.clone();
+    let metered =
+        move |stream: futures::stream::BoxStream<'static, std::io::Result<axum::body::Bytes>>| {
+            stream
+                .inspect_ok(move |chunk| state.usage.record_served(&served_to, chunk.len() as u64))
+        };
+
+    match range.and_then(|r| parse_range(r, total)) {
+        Some(Ok((start, end))) => {
+            let len = end - start + 1;
+            let stream = blob_stream_range(&path, key, start, len)
+                .await
+                .map_err(open_error)?;
+            Ok(builder
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", start, end, total),
+                )
+                .header(header::CONTENT_LENGTH, len)
+                .body(Body::from_stream(metered(stream)))
+                .unwrap())
+        }
+        Some(Err(())) => Ok(builder
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_RANGE, format!("bytes */{}", total))
+            .body(Body::empty())
+            .unwrap()),
+        None => {
+            // 核心要求：Async Read -> Async Write (加密的 blob 在流中解密)
+            let stream = blob_stream(&path, key).await.map_err(open_error)?;
+            Ok(builder
+                .header(header::CONTENT_LENGTH, total)
+                .body(Body::from_stream(metered(stream)))
+                .unwrap())
+        }
+    }
+}
+
+// 列出图片
+#[derive(Deserialize)]
+pub struct ListParams {
+    page: Option<usize>,
+    page_size: Option<usize>,
+    // 只列出带有该标签的图片
+    tag: Option<String>,
+    // 按名称和描述搜索 (不区分大小写的子串匹配)
+    q: Option<String>,
+    // 排序字段：created_at (默认)、captured_at、name 或 size
+    sort: Option<String>,
+    // asc 或 desc (默认)
+    order: Option<String>,
+    // 逗号分隔的返回字段，例如 name,hash,thumb_url
+    fields: Option<String>,
+    // 上一页返回的 next_cursor，提供时忽略 page
+    cursor: Option<String>,
+}
+
+// 游标为 (created_at, hash) 的不透明编码，翻页期间有上传或删除也不会重复或遗漏
+fn encode_cursor(img: &ImageMeta) -> String {
+    let raw = format!(
+        "{}:{}",
+        img.created_at.timestamp_nanos_opt().unwrap_or_default(),
+        img.hash
+    );
+    BASE64_URL_SAFE_NO_PAD.encode(raw)
+}
+
+fn decode_cursor(cursor: &str) -> Option<(chrono::DateTime<chrono::Utc>, String)> {
+    let raw = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+    let (nanos, hash) = raw.split_once(':')?;
+    Some((
+        chrono::DateTime::from_timestamp_nanos(nanos.parse().ok()?),
+        hash.to_string(),
+    ))
+}
+
+// 只保留 fields 中列出的字段；url 和 thumb_url 为根据名称生成的下载地址
+fn project(img: &ImageMeta, fields: &[&str], versioned: bool) -> serde_json::Value {
+    let mut full = serde_json::to_value(img).unwrap_or_default();
+    let mut out = serde_json::Map::new();
+    for &field in fields {
+        let value = match field {
+            "url" => serde_json::json!(img.url(false, versioned)),
+            "thumb_url" => serde_json::json!(img.url(true, versioned)),
+            _ => match full.get_mut(field) {
+                Some(value) => value.take(),
+                None => continue,
+            },
+        };
+        out.insert(field.to_string(), value);
+    }
+    serde_json::Value::Object(out)
+}
+
+#[utoipa::path(
+    get,
+    path = "/images",
+    summary = "List images",
+    description = "Private images are only listed for admins.",
+    params(
+        ("page" = Option<usize>, Query),
+        ("page_size" = Option<usize>, Query),
+        ("tag" = Option<String>, Query),
+        (
+            "q" = Option<String>,
+            Query,
+            description = "Case-insensitive search in names and descriptions",
+        ),
+        (
+            "sort" = Option<String>,
+            Query,
+            description = "created_at (default), captured_at, name or size",
+        ),
+        ("order" = Option<String>, Query, description = "asc or desc (default)"),
+        (
+            "fields" = Option<String>,
+            Query,
+            description = "Comma-separated fields to return; url and thumb_url are virtual",
+        ),
+        (
+            "cursor" = Option<String>,
+            Query,
+            description = "next_cursor from the previous page; overrides page. Only with sort=created_at",
+        ),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "A page of images (`data`); `next_cursor` is null on the last page or when not sorting by created_at",
+            body = Object,
+            example = json!({"total": 1, "page": 1, "page_size": 20, "next_cursor": null, "data": []}),
+        ),
+    ),
+    security((), ("adminToken" = [])),
+)]
+pub async fn list_images(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Query(params): Query<ListParams>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let config = state.read_config("list_images").await;
+    check_ip(&config, &addr)?;
+    // 私有图片只在管理员的列表中出现
+    let admin = check_token(&config, token).is_ok();
+
+    let page = params.page.unwrap_or(1).max(1);
+    let page_size = params
+        .page_size
+        .unwrap_or(config.page_size)
+        .clamp(1, config.max_page_size.max(1));
+
+    let query = params.q.as_deref().map(str::to_lowercase);
+    let mut images: Vec<_> = config
+        .images()
+        .filter(|i| i.quarantined.is_none() && (admin || !i.private))
+        .filter(|i| params.tag.as_ref().is_none_or(|t| i.tags.contains(t)))
+        .filter(|i| query.as_ref().is_none_or(|q| i.matches(q)))
+        .collect();
+    let cursor = match params.cursor.as_deref() {
+        Some(c) => Some(
+            decode_cursor(c).ok_or((StatusCode::BAD_REQUEST, "Invalid 'cursor'".to_string()))?,
+        ),
+        None => None,
+    };
+    let by_created = matches!(params.sort.as_deref(), None | Some("created_at"));
+    if cursor.is_some() && !by_created {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "'cursor' requires sort=created_at".to_string(),
+        ));
+    }
+    match params.sort.as_deref() {
+        // 记录基本按上传顺序追加，这里再按 (created_at, hash) 排序以保证游标稳定
+        None | Some("created_at") => {
+            images.sort_by(|a, b| (a.created_at, &a.hash).cmp(&(b.created_at, &b.hash)))
+        }
+        // 没有拍摄时间的图片按上传时间参与排序
+        Some("captured_at") => images.sort_by_key(|i| i.captured_at.unwrap_or(i.created_at)),
+        Some("name") => images.sort_by(|a, b| a.name.cmp(&b.name)),
+        Some("size") => images.sort_by_key(|i| i.size),
+        Some(_) => {
+            return Err((StatusCode::BAD_REQUEST, "Unsupported 'sort'".to_string()));
+        }
+    }
+    let desc = match params.order.as_deref() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(_) => {
+            return Err((StatusCode::BAD_REQUEST, "Unsupported 'order'".to_string()));
+        }
+    };
+    if desc {
+        images.reverse();
+    }
+    let total = images.len();
+
+    // 有游标时从游标之后开始，否则按 page 偏移
+    let skip = match &cursor {
+        Some((created_at, hash)) => images.partition_point(|i| {
+            let key = (i.created_at, &i.hash);
+            if desc {
+                key >= (*created_at, hash)
+            } else {
+                key <= (*created_at, hash)
+            }
+        }),
+        None => (page - 1) * page_size,
+    };
+    let data: Vec<_> = images.iter().skip(skip).take(page_size).copied().collect();
+    // 按 created_at 排序时返回下一页的游标，没有更多数据时为 null
+    let next_cursor = data
+        .last()
+        .filter(|_| by_created && skip + data.len() < total)
+        .map(|i| encode_cursor(i));
+    let data = match &params.fields {
+        Some(fields) => {
+            let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+            serde_json::Value::Array(
+                data.into_iter()
+                    .map(|i| project(i, &fields, config.versioned_urls))
+                    .collect(),
+            )
+        }
+        None => serde_json::json!(data),
+    };
+
+    info!(
+        "addr: {:?}, action: list, page: {:?}, q: {:?}",
+        addr, page, params.q
+    );
+
+    Ok(Json(serde_json::json!({
+        "total": total,
+        "page": page,
+        "page_size": page_size,
+        "next_cursor": next_cursor,
+        "data": data
+    })))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/images/{id}",
+    summary = "Delete an image",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "Image not found"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn delete_image(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(name): Path<String>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("delete_image").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    let mut config = state.write_config("delete_image").await;
+
+    let Some(hashes) = config.remove_image(&name) else {
+        // 没有记录引用的 Hash：blob 仍存在时 (例如手动编辑元数据后残留的文件) 直接清理
+        let is_hash = name.len() == 64 && name.chars().all(|c| c.is_ascii_hexdigit());
+        if is_hash && config.images_dir().join(&name).exists() {
+            remove_unused_blobs(&config, std::slice::from_ref(&name)).await;
+            info!("addr: {:?}, action: delete_orphan, hash: {:?}", addr, name);
+            return Ok(StatusCode::NO_CONTENT);
+        }
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    };
+    remove_unused_blobs(&config, &hashes).await;
+
+    // 保存到磁盘
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+    state.stats.deletes.fetch_add(1, Ordering::Relaxed);
+
+    info!("addr: {:?}, action: delete, name: {:?}", addr, name);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// 删除不再被任何记录引用的原图和缩略图 (去重)
+pub(crate) async fn remove_unused_blobs(config: &AppConfig, hashes: &[String]) {
+    for hash in hashes {
+        if !config.hash_in_use(hash) {
+            // 忽略文件不存在的错误
+            let _ = fs::remove_file(config.images_dir().join(hash)).await;
+            let _ = fs::remove_file(config.thumbs_dir().join(hash)).await;
+            for variant in config.variants_of(hash) {
+                let _ = fs::remove_file(variant).await;
+            }
+        }
+    }
+}
+
+// 批量删除，只写一次配置
+#[utoipa::path(
+    post,
+    path = "/admin/images/batch-delete",
+    summary = "Delete several images",
+    request_body(content = Vec<String>, description = "Image names, aliases or hashes"),
+    responses(
+        (
+            status = 200,
+            description = "Per-item results",
+            body = Object,
+            example = json!({"results": [{"id": "cat.png", "ok": true}, {"id": "missing.png", "ok": false, "error": "Image not found"}]}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn batch_delete(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Json(ids): Json<Vec<String>>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("batch_delete").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    let mut config = state.write_config("batch_delete").await;
+
+    let mut removed = Vec::new();
+    let mut deleted = 0;
+    let results: Vec<_> = ids
+        .iter()
+        .map(|id| match config.remove_image(id) {
+            Some(hashes) => {
+                removed.extend(hashes);
+                deleted += 1;
+                serde_json::json!({ "id": id, "ok": true })
+            }
+            None => serde_json::json!({ "id": id, "ok": false, "error": "Image not found" }),
+        })
+        .collect();
+    remove_unused_blobs(&config, &removed).await;
+
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+    state.stats.deletes.fetch_add(deleted, Ordering::Relaxed);
+
+    info!("addr: {:?}, action: batch_delete, ids: {:?}", addr, ids);
+    Ok(Json(serde_json::json!({ "results": results })))
+}
+
+// 修改图片元数据 (部分更新)
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct UpdateImage {
+    name: Option<String>,
+    desc: Option<String>,
+    // 所属相册，空字符串表示移出相册
+    /// Album name; an empty string removes the image from its album
+    album: Option<String>,
+    // 私有图片只对管理员和所在相册的 token 可见
+    /// Make the image private (see albums)
+    private: Option<bool>,
+    // 替换全部标签
+    /// Replaces all tags
+    tags: Option<Vec<String>>,
+    pinned: Option<bool>,
+    // false 表示审核通过、解除隔离；true 表示手动隔离
+    /// false approves a quarantined image; true quarantines it manually
+    quarantined: Option<bool>,
+}
+
+#[utoipa::path(
+    patch,
+    path = "/images/{id}",
+    summary = "Edit metadata",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    request_body = UpdateImage,
+    responses(
+        (status = 200, description = "Updated metadata", body = ImageMeta),
+        (status = 400, description = "Pinned set size limit exceeded"),
+        (status = 409, description = "Name already taken"),
+        (status = 404, description = "Image not found"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn update_image(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(id): Path<String>,
+    Json(update): Json<UpdateImage>,
+) -> Result<Json<ImageMeta>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("update_image").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    let mut config = state.write_config("update_image").await;
+
+    let Some(index) = config.image_id(&id) else {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    };
+
+    // 先完成所有检查再修改，请求被拒绝时记录保持不变
+    let new_name = update.name.filter(|n| *n != id);
+    if let Some(new_name) = &new_name {
+        if new_name.is_empty() {
+            return Err((StatusCode::BAD_REQUEST, "Empty 'name'".to_string()));
+        }
+        if config.image_id(new_name).is_some() {
+            return Err((StatusCode::CONFLICT, "Name already exists".to_string()));
+        }
+    }
+    let img = config.image(index);
+    if update.pinned == Some(true) && !img.pinned {
+        // 相同内容已在置顶集合中时不增加大小
+        let added = match config.images_with_hash(&img.hash).any(|i| i.pinned) {
+            true => 0,
+            false => img.size,
+        };
+        if config.pinned_size() + added > config.max_pinned_mb * 1024 * 1024 {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Pinned set size limit exceeded".to_string(),
+            ));
+        }
+    }
+
+    if let Some(new_name) = new_name {
+        // id 可能是别名，只修改被请求的那个名称
+        config.rename_image(index, &id, new_name);
+    }
+    config.update_image(index, |img| {
+        if let Some(desc) = update.desc {
+            // 已不在描述中的失效链接不再保留
+            let urls = extract_urls(&desc);
+            img.broken_sources.retain(|u| urls.contains(u));
+            img.desc = desc;
+        }
+        if let Some(album) = update.album {
+            let album = album.trim();
+            img.album = (!album.is_empty()).then(|| album.to_string());
+        }
+        if let Some(private) = update.private {
+            img.private = private;
+        }
+        if let Some(tags) = update.tags {
+            img.tags.clear();
+            img.add_tags(tags);
+        }
+        if let Some(pinned) = update.pinned {
+            img.pinned = pinned;
+        }
+        if let Some(quarantined) = update.quarantined {
+            img.quarantined = match quarantined {
+                true => img
+                    .quarantined
+                    .take()
+                    .or_else(|| Some("manual".to_string())),
+                false => None,
+            };
+        }
+    });
+
+    let meta = config.image(index).clone();
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!(
+        "addr: {:?}, action: update, id: {:?}, name: {:?}",
+        addr, id, meta.name
+    );
+    Ok(Json(meta))
+}
+
+// 重命名图片
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct RenameImage {
+    name: String,
+    // 名称冲突时追加数字后缀而不是拒绝
+    /// Append a numeric suffix instead of failing when the name is taken
+    #[serde(default)]
+    force: bool,
+}
+
+#[utoipa::path(
+    put,
+    path = "/images/{id}/name",
+    summary = "Rename an image",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    request_body = RenameImage,
+    responses(
+        (status = 200, description = "Renamed", body = ImageMeta),
+        (status = 409, description = "Name already taken"),
+        (status = 404, description = "Image not found"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn rename_image(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(id): Path<String>,
+    Json(rename): Json<RenameImage>,
+) -> Result<Json<ImageMeta>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("rename_image").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    if rename.name.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Empty 'name'".to_string()));
+    }
+
+    // 检查与修改在同一把写锁内完成，保证原子性
+    let mut config = state.write_config("rename_image").await;
+
+    let Some(index) = config.image_id(&id) else {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    };
+
+    let new_name = match config.image_id(&rename.name) {
+        None => rename.name,
+        // 改为自己当前的名称，无需处理
+        Some(_) if rename.name == id => rename.name,
+        Some(_) if rename.force => config.unique_name(&rename.name),
+        Some(_) => return Err((StatusCode::CONFLICT, "Name already exists".to_string())),
+    };
+    config.rename_image(index, &id, new_name.clone());
+
+    let meta = config.image(index).clone();
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!(
+        "addr: {:?}, action: rename, id: {:?}, name: {:?}",
+        addr, id, new_name
+    );
+    Ok(Json(meta))
+}
+
+// 创建一次性下载链接
+#[derive(Deserialize)]
+pub struct OneTimeParams {
+    // 有效期 (秒)，默认 24 小时
+    expires_in: Option<i64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/images/{id}/one-time",
+    summary = "Create a one-time download link",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+        ("expires_in" = Option<i64>, Query, description = "Lifetime in seconds (default 86400)"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Link",
+            body = Object,
+            example = json!({"url": "/one-time/abc123", "expires_at": "2026-01-02T00:00:00Z"}),
+        ),
+        (status = 404, description = "Image not found"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn create_one_time_link(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(id): Path<String>,
+    Query(params): Query<OneTimeParams>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("create_one_time_link").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+    let mut config = state.write_config("create_one_time_link").await;
+
+    let hash = match config.image_id(&id) {
+        Some(index) => config.image(index).hash.clone(),
+        None => return Err((StatusCode::NOT_FOUND, "Image not found".to_string())),
+    };
+
+    let now = chrono::Utc::now();
+    let expires_at = now + chrono::Duration::seconds(params.expires_in.unwrap_or(86400).max(1));
+    // 顺便清理已过期的链接
+    config
+        .one_time_links
+        .retain(|_, link| link.expires_at > now);
+
+    let link_token = random_string(32);
+    config.one_time_links.insert(
+        link_token.clone(),
+        OneTimeLink {
+            hash,
+            expires_at,
+            used: false,
+            downloading: false,
+        },
+    );
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!("addr: {:?}, action: one_time_create, id: {:?}", addr, id);
+
+    Ok(Json(serde_json::json!({
+        "url": format!("/one-time/{}", link_token),
+        "expires_at": expires_at,
+    })))
+}
+
+// 一次性链接的下载占用：响应体完整发送后将链接标记为已使用，中断 (连接断开、读取出错) 时释放，可以重新下载
+struct OneTimeClaim {
+    state: Arc<AppState>,
+    link_token: String,
+    completed: bool,
+}
+
+impl Drop for OneTimeClaim {
+    fn drop(&mut self) {
+        let state = self.state.clone();
+        let link_token = std::mem::take(&mut self.link_token);
+        let completed = self.completed;
+        tokio::spawn(async move {
+            let mut config = state.write_config("download_one_time").await;
+            if let Some(link) = config.one_time_links.get_mut(&link_token) {
+                link.downloading = false;
+                link.used |= completed;
+            }
+            if completed && let Err(e) = state.persist(&config) {
+                error!("Failed to save config: {}", e);
+            }
+        });
+    }
+}
+
+// 通过一次性链接下载
+#[utoipa::path(
+    get,
+    path = "/one-time/{token}",
+    summary = "Download through a one-time link",
+    description = "The link is marked as used only after the whole file has been sent; an interrupted download can be retried.",
+    params(
+        ("token" = String, Path),
+    ),
+    responses(
+        (status = 200, description = "Image content", content(("application/octet-stream"))),
+        (status = 404, description = "Link not found"),
+        (status = 409, description = "Another download through this link is in progress"),
+        (status = 410, description = "Link used or expired"),
+    ),
+)]
+pub async fn download_one_time(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(link_token): Path<String>,
+) -> Result<Response, (StatusCode, String)> {
+    // 在写锁内检查并占用链接，同一时间只有一个下载，保证只能成功下载一次
+    // 打开 blob 前释放写锁，占用由 claim 持有，打开失败时随 claim 一起释放
+    let (hash, path, key, content_type, mut claim) = {
+        let mut config = state.write_config("download_one_time").await;
+        check_ip(&config, &addr)?;
+
+        let link = config
+            .one_time_links
+            .get(&link_token)
+            .ok_or((StatusCode::NOT_FOUND, "Link not found".to_string()))?;
+        if link.used || link.expires_at <= chrono::Utc::now() {
+            return Err((StatusCode::GONE, "Link expired".to_string()));
+        }
+        if link.downloading {
+            return Err((StatusCode::CONFLICT, "Link is being downloaded".to_string()));
+        }
+        let hash = link.hash.clone();
+        let path = config.images_dir().join(&hash);
+        if !path.exists() {
+            return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+        }
+        if let Some(link) = config.one_time_links.get_mut(&link_token) {
+            link.downloading = true;
+        }
+        let content_type = config
+            .images_with_hash(&hash)
+            .find_map(|i| i.content_type.as_deref())
+            .and_then(|t| header::HeaderValue::from_str(t).ok());
+        let claim = OneTimeClaim {
+            state: state.clone(),
+            link_token: link_token.clone(),
+            completed: false,
+        };
+        (hash, path, config.blob_key.clone(), content_type, claim)
+    };
+
+    // 一次性链接只能使用一次，不支持 Range 续传
+    let served_to = format!("link:{}", token_fingerprint(&link_token));
+    let mut response = blob_response(&state, served_to, path, key.as_ref(), &hash, None).await?;
+    if let Some(value) = content_type {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+
+    // 响应体的最后一块交给连接时才算下载完成 (连接按 Content-Length 发送完毕后不会再读取流的结尾)；
+    // 之前被丢弃则释放占用
+    let (parts, body) = response.into_parts();
+    let mut remaining = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+        .unwrap_or_default();
+    claim.completed = remaining == 0;
+    let body = Body::from_stream(body.into_data_stream().inspect_ok(move |chunk| {
+        remaining = remaining.saturating_sub(chunk.len() as u64);
+        // 闭包需要持有整个 claim (而不只是其中的字段)，在响应体被丢弃时才释放
+        let claim = &mut claim;
+        claim.completed = remaining == 0;
+    }));
+
+    info!(
+        "addr: {:?}, action: one_time_download, hash: {:?}",
+        addr, hash
+    );
+    Ok(Response::from_parts(parts, body))
+}
+
+// 按 token 统计上传和下载用量 (见 usage.rs)
+#[derive(Deserialize)]
+pub struct UsageParams {
+    // 统计月份，格式 YYYY-MM，缺省时统计全部
+    month: Option<String>,
+    group_by: Option<String>,
+    format: Option<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/usage",
+    summary = "Usage per token",
+    params(
+        ("month" = Option<String>, Query, description = "YYYY-MM"),
+        ("group_by" = Option<String>, Query, description = "Only token is supported"),
+        ("format" = Option<String>, Query, description = "json (default) or csv"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Usage report; `token` is a token fingerprint, album:<fingerprint>, link:<fingerprint>, public or unknown",
+            content(
+                (
+                    Object = "application/json",
+                    example = json!({"month": "2026-01", "data": [{"token": "e632b7095b0b", "images": 1, "bytes_uploaded": 2180, "bytes_served": 4360}]})
+                ),
+                (String = "text/csv")
+            ),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn usage_report(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Query(params): Query<UsageParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("usage_report").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+
+    if params.group_by.as_deref().is_some_and(|g| g != "token") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "Unsupported 'group_by'".to_string(),
+        ));
+    }
+    if let Some(month) = &params.month
+        && chrono::NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_err()
+    {
+        return Err((StatusCode::BAD_REQUEST, "Invalid 'month'".to_string()));
+    }
+
+    // token 或链接的指纹 -> 用量
+    let usage = state.usage.by_token(params.month.as_deref());
+
+    info!("addr: {:?}, action: usage, month: {:?}", addr, params.month);
+
+    if params.format.as_deref() == Some("csv") {
+        let mut csv = String::from("token,images,bytes_uploaded,bytes_served\n");
+        for (token, c) in &usage {
+            csv.push_str(&format!(
+                "{},{},{},{}\n",
+                token, c.images, c.bytes_uploaded, c.bytes_served
+            ));
+        }
+        return Ok(Response::builder()
+            .header(header::CONTENT_TYPE, "text/csv")
+            .body(Body::from(csv))
+            .unwrap());
+    }
+
+    let data: Vec<_> = usage
+        .iter()
+        .map(|(token, c)| {
+            serde_json::json!({
+                "token": token,
+                "images": c.images,
+                "bytes_uploaded": c.bytes_uploaded,
+                "bytes_served": c.bytes_served,
+            })
+        })
+        .collect();
+    Ok(Json(serde_json::json!({
+        "month": params.month,
+        "data": data
+    }))
+    .into_response())
+}
+
+// GraphQL 查询入口 (只读)
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    summary = "Read-only GraphQL query over image metadata",
+    description = "Queries: image(id), images(tag, q, createdAfter, createdBefore, sort, order, offset, limit), tags.",
+    request_body(
+        content = Object,
+        description = "GraphQL request: `query`, optional `variables` and `operationName`",
+        example = json!({"query": "{ tags { name count } }"})
+    ),
+    responses(
+        (status = 200, description = "GraphQL response with `data` and `errors`", body = Object),
+    ),
+)]
+pub async fn graphql(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(request): Json<async_graphql::Request>,
+) -> Result<Json<async_graphql::Response>, (StatusCode, String)> {
+    {
+        let config = state.read_config("graphql").await;
+        check_ip(&config, &addr)?;
+    }
+    let response = crate::graphql::SCHEMA.execute(request.data(state)).await;
+    info!(
+        "addr: {:?}, action: graphql, errors: {:?}",
+        addr,
+        response.errors.len()
+    );
+    Ok(Json(response))
+}
+
+// 列出所有标签及使用次数
+#[utoipa::path(
+    get,
+    path = "/tags",
+    summary = "List tags with image counts",
+    responses(
+        (status = 200, description = "Tags", body = Object),
+    ),
+)]
+pub async fn list_tags(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let config = state.read_config("list_tags").await;
+    check_ip(&config, &addr)?;
+
+    let mut counts: std::collections::BTreeMap<&str, usize> = Default::default();
+    // 私有图片的标签不对外公开
+    for tag in config
+        .images()
+        .filter(|i| i.is_public())
+        .flat_map(|i| &i.tags)
+    {
+        *counts.entry(tag).or_default() += 1;
+    }
+    let data: Vec<_> = counts
+        .into_iter()
+        .map(|(tag, count)| serde_json::json!({ "tag": tag, "count": count }))
+        .collect();
+
+    info!("addr: {:?}, action: tags", addr);
+
+    Ok(Json(serde_json::json!({
+        "total": data.len(),
+        "data": data
+    })))
+}
+
+// 运行时统计
+#[utoipa::path(
+    get,
+    path = "/admin/stats",
+    summary = "Runtime counters",
+    description = "Server version, uptime, storage usage and in-memory counters since the server started.",
+    responses(
+        (
+            status = 200,
+            description = "Counters",
+            body = Object,
+            example = json!({"version": "0.1.0", "uptime_secs": 3600, "images": 42, "blobs": 40, "blob_bytes": 1048576}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn get_stats(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let config = state.read_config("get_stats").await;
+    check_ip(&config, &addr)?;
+    check_token(&config, token)?;
+
+    let mut stats = state.stats.to_json();
+    let (blobs, blob_bytes) = config.blob_usage();
+    stats["images"] = serde_json::json!(config.images().len());
+    stats["blobs"] = serde_json::json!(blobs);
+    stats["blob_bytes"] = serde_json::json!(blob_bytes);
+    stats["processing"] = state.pool.to_json();
+    Ok(Json(stats))
+}
+
+// Prometheus 指标：请求数和耗时、上传/下载字节数、去重次数、缩略图队列长度和存储用量
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    summary = "Prometheus metrics",
+    description = "Request counts and latencies per route, upload/download bytes, dedup hits, thumbnail queue depth and storage usage. Only available when `metrics = true`.",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", body = String),
+        (status = 403, description = "IP blocked"),
+        (status = 404, description = "Metrics disabled"),
+    ),
+)]
+pub async fn metrics(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Result<Response, (StatusCode, String)> {
+    let mut out = String::new();
+    {
+        let config = state.read_config("metrics").await;
+        check_ip(&config, &addr)?;
+        if !config.metrics {
+            return Err((StatusCode::NOT_FOUND, "Not found".to_string()));
+        }
+
+        let (blobs, blob_bytes) = config.blob_usage();
+        let pinned: u64 = config.images().filter(|i| i.pinned).map(|i| i.size).sum();
+        let quarantined = config.images().filter(|i| i.quarantined.is_some()).count();
+        let gauges = [
+            ("images", "Image records", config.images().len() as u64),
+            ("blobs", "Unique stored blobs", blobs as u64),
+            ("blob_bytes", "Bytes of unique stored blobs", blob_bytes),
+            ("pinned_bytes", "Bytes of pinned images", pinned),
+            (
+                "quarantined_images",
+                "Images awaiting moderation review",
+                quarantined as u64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            stats::write_metric(&mut out, name, "gauge", help, value);
+        }
+    }
+    let pool = state.pool.to_json();
+    for (name, help) in [
+        ("workers", "Processing pool workers"),
+        ("active", "Processing tasks running"),
+        ("queued", "Processing tasks waiting for a worker"),
+    ] {
+        stats::write_metric(
+            &mut out,
+            &format!("processing_{}", name),
+            "gauge",
+            help,
+            pool[name].as_u64().unwrap_or(0),
+        );
+    }
+    state.stats.write_prometheus(&mut out);
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        out,
+    )
+        .into_response())
+}
+
+// 轮换 label 对应的 token
+#[derive(Deserialize)]
+pub struct RotateTokenParams {
+    // 旧 token 的宽限期 (小时)，缺省时使用配置的 token_grace_hours
+    grace_hours: Option<u64>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/tokens/{label}/rotate",
+    summary = "Rotate a labeled token",
+    params(
+        ("label" = String, Path),
+        ("grace_hours" = Option<u64>, Query, description = "Hours the previous token stays valid"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "New token",
+            body = Object,
+            example = json!({"label": "ci", "token": "NEW_TOKEN", "previous_expires_at": "2026-01-02T00:00:00Z"}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn rotate_token(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(label): Path<String>,
+    Query(params): Query<RotateTokenParams>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("rotate_token").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+
+    let mut config = state.write_config("rotate_token").await;
+    let grace_hours = params.grace_hours.unwrap_or(config.token_grace_hours);
+    let (new_token, expires_at) =
+        config.rotate_token(&label, chrono::Duration::hours(grace_hours as i64));
+    state.persist(&config).map_err(|e| {
+        error!("Failed to save config: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "Save failed".to_string())
+    })?;
+
+    info!(
+        "addr: {:?}, action: token_rotate, label: {:?}, fingerprint: {:?}",
+        addr,
+        label,
+        token_fingerprint(&new_token)
+    );
+    Ok(Json(serde_json::json!({
+        "label": label,
+        "token": new_token,
+        "previous_expires_at": expires_at,
+    })))
+}
+
+// 查看日志级别
+#[utoipa::path(
+    get,
+    path = "/admin/log-level",
+    summary = "Current log level",
+    responses(
+        (
+            status = 200,
+            description = "Log level in RUST_LOG syntax",
+            body = Object,
+            example = json!({"level": "info"}),
+        ),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn get_log_level(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let config = state.read_config("get_log_level").await;
+    check_ip(&config, &addr)?;
+    check_token(&config, token)?;
+    Ok(Json(serde_json::json!({ "level": logging::log_level() })))
+}
+
+#[derive(Deserialize, utoipa::ToSchema)]
+pub struct LogLevelRequest {
+    #[schema(example = "info,img_server::handler=debug")]
+    level: String,
+    // 到期后恢复原来的级别；未设置时一直有效，直到再次修改或重启
+    /// Restore the previous level after this many seconds
+    duration_secs: Option<u64>,
+}
+
+// 运行时修改日志级别，便于临时打开 debug 日志排查问题
+#[utoipa::path(
+    put,
+    path = "/admin/log-level",
+    summary = "Change the log level",
+    description = "Uses the RUST_LOG syntax. With `duration_secs`, the previous level is restored afterwards unless it was changed again.",
+    request_body = LogLevelRequest,
+    responses(
+        (
+            status = 200,
+            description = "New log level",
+            body = Object,
+            example = json!({"level": "info,img_server::handler=debug"}),
+        ),
+        (status = 400, description = "Invalid level"),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn set_log_level(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Json(req): Json<LogLevelRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    {
+        let config = state.read_config("set_log_level").await;
+        check_ip(&config, &addr)?;
+        check_token(&config, token)?;
+    }
+
+    info!(
+        "addr: {:?}, action: set_log_level, level: {:?}, duration_secs: {:?}",
+        addr, req.level, req.duration_secs
+    );
+    logging::set_log_level(
+        &req.level,
+        req.duration_secs.map(std::time::Duration::from_secs),
+    )
+    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid level: {}", e)))?;
+    Ok(Json(serde_json::json!({
+        "level": logging::log_level(),
+        "duration_secs": req.duration_secs,
+    })))
+}
+
+// 查看图片的别名
+#[utoipa::path(
+    get,
+    path = "/images/{id}/aliases",
+    summary = "List aliases",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Aliases",
+            body = Object,
+            example = json!({"name": "cat.png", "hash": "5c9063b4...", "aliases": ["kitten.png"]}),
+        ),
+        (status = 404, description = "Image not found"),
+    ),
+)]
+pub async fn list_aliases(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let config = state.read_config("list_aliases").await;
+    check_ip(&config, &addr)?;
+
+    // 先匹配名称或别名，再按 Hash 匹配
+    let img = config
+        .find_image(&id)
+        .filter(|i| i.is_public())
+        .ok_or((StatusCode::NOT_FOUND, "Image not found".to_string()))?;
+
+    info!("addr: {:?}, action: aliases, id: {:?}", addr, id);
+
+    Ok(Json(serde_json::json!({
+        "name": img.name,
+        "hash": img.hash,
+        "aliases": img.aliases,
+    })))
+}
+
+// 列出图片的所有版本，最新版本在前
+#[utoipa::path(
+    get,
+    path = "/images/{id}/versions",
+    summary = "List versions",
+    params(
+        ("id" = String, Path, description = "Image name or alias"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Versions, newest first; each entry is an ImageVersion plus its `version` number and `url`",
+            body = Object,
+            example = json!({"name": "cat.png", "current": 2, "versions": [{"version": 2, "url": "/images/cat.png?version=2", "hash": "9fc24935...", "size": 2182, "created_at": "2026-01-02T00:00:00Z"}]}),
+        ),
+        (status = 404, description = "Image not found"),
+    ),
+)]
+pub async fn list_versions(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let config = state.read_config("list_versions").await;
+    check_ip(&config, &addr)?;
+
+    let img = config
+        .image_id(&id)
+        .map(|i| config.image(i))
+        .filter(|i| i.is_public())
+        .ok_or((StatusCode::NOT_FOUND, "Image not found".to_string()))?;
+    let current = ImageVersion {
+        hash: img.hash.clone(),
+        size: img.size,
+        created_at: img.created_at,
+        captured_at: img.captured_at,
+        content_type: img.content_type.clone(),
+        uploaded_by: img.uploaded_by.clone(),
+        blurhash: img.blurhash.clone(),
+        phash: img.phash.clone(),
+    };
+    let mut versions: Vec<_> = img
+        .versions
+        .iter()
+        .chain(std::iter::once(&current))
+        .enumerate()
+        .map(|(i, v)| {
+            let mut value = serde_json::to_value(v).unwrap_or_default();
+            value["version"] = (i + 1).into();
+            value["url"] = format!("{}?version={}", img.url(false, false), i + 1).into();
+            value
+        })
+        .collect();
+    versions.reverse();
+
+    info!("addr: {:?}, action: versions, id: {:?}", addr, id);
+
+    Ok(Json(serde_json::json!({
+        "name": img.name,
+        "current": img.version(),
+        "versions": versions,
+    })))
+}
+
+// 严格按内容 Hash 下载，不查找名称；内容不可变，允许长期缓存
+#[utoipa::path(
+    get,
+    path = "/blob/{hash}",
+    summary = "Download by content hash",
+    params(
+        ("hash" = String, Path),
+        (
+            "thumb" = Option<bool>,
+            Query,
+            description = "Return the thumbnail instead of the original",
+        ),
+        (
+            "token" = Option<String>,
+            Query,
+            description = "Album token granting read access to private images in that album",
+        ),
+        ("Range" = Option<String>, Header, nullable = false),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Image content (immutable)",
+            content(("application/octet-stream")),
+        ),
+        (status = 304, description = "Not modified (If-None-Match or If-Modified-Since)"),
+        (status = 404, description = "Image not found"),
+    ),
+)]
+pub async fn download_blob(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+    Path(hash): Path<String>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Response, (StatusCode, String)> {
+    let is_thumb = params.thumb.unwrap_or(false);
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err((StatusCode::NOT_FOUND, "Image not found".to_string()));
+    }
+    let (path, blob_key, mime, served_to) = {
+        let config = state.read_config("download_blob").await;
+        check_ip(&config, &addr)?;
+        check_readable(
+            &config,
+            &headers,
+            params.token.as_deref(),
+            config.images_with_hash(&hash),
+        )?;
+        let dir = if is_thumb {
+            config.thumbs_dir()
+        } else {
+            config.images_dir()
+        };
+        let mime = config
+            .images_with_hash(&hash)
+            .find_map(|i| i.content_type.as_deref())
+            .map(|m| match is_thumb {
+                true => thumbnail_content_type(m).to_string(),
+                false => m.to_string(),
+            });
+        let served_to = served_to(
+            &config,
+            &headers,
+            params.token.as_deref(),
+            config.images_with_hash(&hash),
+        );
+        (dir.join(&hash), config.blob_key.clone(), mime, served_to)
+    };
+    if !path.exists() {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    }
+
+    let etag = match is_thumb {
+        true => format!("\"{}.thumb\"", hash),
+        false => format!("\"{}\"", hash),
+    };
+    let modified = file_modified(&path).await;
+    let not_modified = is_not_modified(&headers, &etag, modified);
+    let mut response = if not_modified {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap()
+    } else {
+        let range = headers.get(header::RANGE).and_then(|v| v.to_str().ok());
+        let mut response =
+            blob_response(&state, served_to, path, blob_key.as_ref(), &hash, range).await?;
+        if let Some(value) = mime.and_then(|t| header::HeaderValue::from_str(&t).ok()) {
+            response.headers_mut().insert(header::CONTENT_TYPE, value);
+        }
+        response
+    };
+    response
+        .headers_mut()
+        .insert(header::ETAG, header::HeaderValue::from_str(&etag).unwrap());
+    if let Some(modified) = modified {
+        response
+            .headers_mut()
+            .insert(header::LAST_MODIFIED, http_date(modified));
+    }
+    response.headers_mut().insert(
+        header::CACHE_CONTROL,
+        header::HeaderValue::from_static("public, max-age=31536000, immutable"),
+    );
+
+    info!(
+        "addr: {:?}, action: download_blob, hash: {:?}, thumb: {:?}, not_modified: {:?}",
+        addr, hash, is_thumb, not_modified
+    );
+    Ok(response)
+}
+
+// 查看图片详情：元数据以及尺寸、格式等派生属性
+#[utoipa::path(
+    get,
+    path = "/images/{id}/info",
+    summary = "Image details",
+    params(
+        ("id" = String, Path, description = "Image name, alias, or SHA256 hash"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "Metadata plus size, width, height, content_type, has_thumbnail and url",
+            body = ImageMeta,
+        ),
+        (status = 404, description = "Image not found"),
+    ),
+)]
+pub async fn image_info(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let (img, path, thumb_path, blob_key, versioned) = {
+        let config = state.read_config("image_info").await;
+        check_ip(&config, &addr)?;
+
+        // 先匹配名称或别名，再按 Hash 匹配
+        let img = config
+            .find_image(&id)
+            .filter(|i| i.is_public())
+            .cloned()
+            .ok_or((StatusCode::NOT_FOUND, "Image not found".to_string()))?;
+        let path = config.images_dir().join(&img.hash);
+        let thumb_path = config.thumbs_dir().join(&img.hash);
+        let versioned = config.versioned_urls;
+        (img, path, thumb_path, config.blob_key.clone(), versioned)
+    };
+    if !path.exists() {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    }
+
+    // 旧记录没有 size 和类型，从文件补全 (Blocking)
+    let size = match img.size {
+        0 => blob_len(&path).await.unwrap_or_default(),
+        size => size,
+    };
+    let (p, key, content_type) = (path.clone(), blob_key.clone(), img.content_type.clone());
+    let (dimensions, content_type) = tokio::task::spawn_blocking(move || {
+        (
+            image_dimensions(&p, key.as_ref()).ok(),
+            content_type.or_else(|| sniff_content_type(&p, key.as_ref())),
+        )
+    })
+    .await
+    .unwrap_or_default();
+
+    info!("addr: {:?}, action: info, id: {:?}", addr, id);
+
+    let mut info = serde_json::to_value(&img).unwrap_or_default();
+    info["size"] = serde_json::json!(size);
+    info["content_type"] = serde_json::json!(content_type);
+    info["width"] = serde_json::json!(dimensions.map(|(w, _)| w));
+    info["height"] = serde_json::json!(dimensions.map(|(_, h)| h));
+    info["has_thumbnail"] = serde_json::json!(thumb_path.exists());
+    info["url"] = serde_json::json!(img.url(false, versioned));
+    Ok(Json(info))
+}
+
+// 列出描述中含有失效来源链接的图片
+#[utoipa::path(
+    get,
+    path = "/admin/brokensources",
+    summary = "Images with broken source links",
+    responses(
+        (status = 200, description = "Images", body = Object),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn list_broken_sources(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let config = state.read_config("list_broken_sources").await;
+    check_ip(&config, &addr)?;
+    check_token(&config, token)?;
+
+    let data: Vec<_> = config
+        .images()
+        .filter(|i| !i.broken_sources.is_empty())
+        .map(|i| {
+            serde_json::json!({
+                "name": i.name,
+                "broken_sources": i.broken_sources,
+            })
+        })
+        .collect();
+
+    info!("addr: {:?}, action: broken_sources", addr);
+
+    Ok(Json(serde_json::json!({
+        "total": data.len(),
+        "data": data
+    })))
+}
+
+// 列出隔离中等待审核的图片；通过 PATCH quarantined=false 放行，或直接删除
+#[utoipa::path(
+    get,
+    path = "/admin/quarantine",
+    summary = "Quarantined images awaiting moderation review",
+    responses(
+        (status = 200, description = "Images", body = Object),
+        (status = 401, description = "Invalid or missing token"),
+        (status = 403, description = "IP blocked"),
+    ),
+    security(("adminToken" = [])),
+)]
+pub async fn list_quarantine(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: header::HeaderMap,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let token = headers.get("x-admin-token").and_then(|v| v.to_str().ok());
+    let config = state.read_config("list_quarantine").await;
+    check_ip(&config, &addr)?;
+    check_token(&config, token)?;
+
+    let data: Vec<_> = config
+        .images()
+        .filter(|i| i.quarantined.is_some())
+        .map(|i| {
+            serde_json::json!({
+                "name": i.name,
+                "hash": i.hash,
+                "reason": i.quarantined,
+                "content_type": i.content_type,
+                "uploaded_by": i.uploaded_by,
+                "created_at": i.created_at,
+                "url": i.url(false, config.versioned_urls),
+            })
+        })
+        .collect();
+
+    info!("addr: {:?}, action: quarantine", addr);
+
+    Ok(Json(serde_json::json!({
+        "total": data.len(),
+        "data": data
+    })))
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+// 上传时未提供 name 时，用于生成公开标识符的策略
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IdStrategy {
+    // 36 位 UUID v4
+    #[default]
+    Uuid,
+    // 指定长度的随机字母数字串
+    Nanoid {
+        #[serde(default = "default_nanoid_length")]
+        length: usize,
+    },
+    // 自增序号经 hashids 编码，短且不易猜测顺序
+    Sequential {
+        #[serde(default)]
+        salt: String,
+        #[serde(default)]
+        min_length: usize,
+    },
+    // 日期前缀 + 随机后缀，例如 20250101-a1B2c3
+    Date {
+        #[serde(default = "default_date_suffix_length")]
+        suffix_length: usize,
+    },
+}
+
+fn default_nanoid_length() -> usize {
+    10
+}
+
+fn default_date_suffix_length() -> usize {
+    6
+}
+
+impl IdStrategy {
+    pub fn generator(&self) -> Box<dyn IdGenerator> {
+        match self {
+            Self::Uuid => Box::new(UuidGenerator),
+            Self::Nanoid { length } => Box::new(NanoidGenerator { length: *length }),
+            Self::Sequential { salt, min_length } => Box::new(Hashids::new(salt, *min_length)),
+            Self::Date { suffix_length } => Box::new(DateGenerator {
+                suffix_length: *suffix_length,
+            }),
+        }
+    }
+}
+
+// 标识符生成器
+// sequence 为持久化在配置中的自增计数，不需要它的实现直接忽略
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self, sequence: u64) -> String;
+}
+
+pub fn random_string(len: usize) -> String {
+    (0..len)
+        .map(|_| ALPHANUMERIC[rand::random_range(0..ALPHANUMERIC.len())] as char)
+        .collect()
+}
+
+pub struct UuidGenerator;
+
+impl IdGenerator for UuidGenerator {
+    fn generate(&self, _sequence: u64) -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+}
+
+pub struct NanoidGenerator {
+    pub length: usize,
+}
+
+impl IdGenerator for NanoidGenerator {
+    fn generate(&self, _sequence: u64) -> String {
+        random_string(self.length.max(1))
+    }
+}
+
+pub struct DateGenerator {
+    pub suffix_length: usize,
+}
+
+impl IdGenerator for DateGenerator {
+    fn generate(&self, _sequence: u64) -> String {
+        format!(
+            "{}-{}",
+            chrono::Utc::now().format("%Y%m%d"),
+            random_string(self.suffix_length.max(1))
+        )
+    }
+}
+
+// hashids 算法 (https://hashids.org) 的单数字实现，输出与其他语言的实现兼容
+pub struct Hashids {
+    salt: Vec<u8>,
+    min_length: usize,
+    alphabet: Vec<u8>,
+    guards: Vec<u8>,
+}
+
+impl Hashids {
+    const SEPS: &[u8] = b"cfhistuCFHISTU";
+    const SEP_DIV: f64 = 3.5;
+    const GUARD_DIV: f64 = 12.0;
+
+    pub fn new(salt: &str, min_length: usize) -> Self {
+        let salt = salt.as_bytes().to_vec();
+        let mut alphabet: Vec<u8> =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890"
+                .iter()
+                .copied()
+                .filter(|c| !Self::SEPS.contains(c))
+                .collect();
+        // seps 仅在多数字编码时用作分隔符，这里只需要它对 alphabet 的影响
+        let mut seps = Self::SEPS.to_vec();
+        consistent_shuffle(&mut seps, &salt);
+
+        let seps_len = ((alphabet.len() as f64 / Self::SEP_DIV).ceil() as usize).max(2);
+        if seps_len > seps.len() {
+            let diff = seps_len - seps.len();
+            seps.extend(alphabet.drain(..diff));
+        } else {
+            seps.truncate(seps_len);
+        }
+        consistent_shuffle(&mut alphabet, &salt);
+
+        let guard_count = (alphabet.len() as f64 / Self::GUARD_DIV).ceil() as usize;
+        let guards = alphabet.drain(..guard_count).collect();
+
+        Self {
+            salt,
+            min_length,
+            alphabet,
+            guards,
+        }
+    }
+
+    pub fn encode(&self, number: u64) -> String {
+        let mut alphabet = self.alphabet.clone();
+        let numbers_hash = number % 100;
+        let lottery = alphabet[(numbers_hash % alphabet.len() as u64) as usize];
+
+        let mut buffer = vec![lottery];
+        buffer.extend_from_slice(&self.salt);
+        buffer.extend_from_slice(&alphabet);
+        buffer.truncate(alphabet.len());
+        consistent_shuffle(&mut alphabet, &buffer);
+
+        let mut ret = vec![lottery];
+        ret.extend(hash(number, &alphabet));
+
+        if ret.len() < self.min_length {
+            let index = (numbers_hash + ret[0] as u64) % self.guards.len() as u64;
+            ret.insert(0, self.guards[index as usize]);
+            if ret.len() < self.min_length {
+                let index = (numbers_hash + ret[2] as u64) % self.guards.len() as u64;
+                ret.push(self.guards[index as usize]);
+            }
+        }
+
+        let half = alphabet.len() / 2;
+        while ret.len() < self.min_length {
+            let key = alphabet.clone();
+            consistent_shuffle(&mut alphabet, &key);
+            let mut padded = alphabet[half..].to_vec();
+            padded.extend_from_slice(&ret);
+            padded.extend_from_slice(&alphabet[..half]);
+            ret = padded;
+            let excess = ret.len().saturating_sub(self.min_length);
+            if excess > 0 {
+                let start = excess / 2;
+                ret = ret[start..start + self.min_length].to_vec();
+            }
+        }
+
+        String::from_utf8(ret).expect("hashids alphabet is ascii")
+    }
+}
+
+impl IdGenerator for Hashids {
+    fn generate(&self, sequence: u64) -> String {
+        self.encode(sequence)
+    }
+}
+
+fn consistent_shuffle(alphabet: &mut [u8], salt: &[u8]) {
+    if salt.is_empty() {
+        return;
+    }
+    let (mut v, mut p) = (0usize, 0usize);
+    for i in (1..alphabet.len()).rev() {
+        v %= salt.len();
+        let integer = salt[v] as usize;
+        p += integer;
+        let j = (integer + v + p) % i;
+        alphabet.swap(i, j);
+        v += 1;
+    }
+}
+
+fn hash(mut input: u64, alphabet: &[u8]) -> Vec<u8> {
+    let len = alphabet.len() as u64;
+    let mut out = Vec::new();
+    loop {
+        out.insert(0, alphabet[(input % len) as usize]);
+        input /= len;
+        if input == 0 {
+            break out;
+        }
+    }
+}
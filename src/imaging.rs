@@ -0,0 +1,752 @@
+use std::{
+    io::{Cursor, Read as _},
+    path::Path,
+};
+
+use image::{DynamicImage, GenericImageView as _, GrayImage, ImageReader, RgbImage};
+
+use crate::{
+    optimize::{interlace_png, progressive_jpeg},
+    raw::{raw_mime, raw_preview},
+    storage::{BlobKey, open_blob, read_blob, write_blob},
+    video::{poster_frame, video_mime},
+};
+
+// 解码前检查的尺寸上限：文件头声明了超大尺寸的图片 (解压炸弹) 不解码，避免耗尽内存
+#[derive(Debug, Clone, Copy)]
+pub struct DecodeLimits {
+    pub max_pixels: u64,
+    pub max_dimension: u32,
+}
+
+impl DecodeLimits {
+    // 只解析文件头检查尺寸，超出限制时返回 LimitError
+    pub fn check(&self, data: &[u8]) -> image::ImageResult<()> {
+        let (width, height) = dimensions(data)?;
+        if width.max(height) > self.max_dimension || width as u64 * height as u64 > self.max_pixels
+        {
+            return Err(image::ImageError::Limits(
+                image::error::LimitError::from_kind(image::error::LimitErrorKind::DimensionError),
+            ));
+        }
+        Ok(())
+    }
+
+    // 检查尺寸后返回用于解码的 reader；解码器同样受 max_dimension 限制
+    fn reader<'a>(&self, data: &'a [u8]) -> image::ImageResult<ImageReader<Cursor<&'a [u8]>>> {
+        self.check(data)?;
+        let mut reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        let mut limits = image::Limits::default();
+        limits.max_image_width = Some(self.max_dimension);
+        limits.max_image_height = Some(self.max_dimension);
+        reader.limits(limits);
+        Ok(reader)
+    }
+
+    // 检查尺寸后解码，HEIF 交给 libheif，RAW 解码内嵌的预览图
+    pub fn decode(&self, data: &[u8]) -> image::ImageResult<DynamicImage> {
+        if let Some(preview) = raw_preview(data) {
+            return self.decode(preview);
+        }
+        if is_heif(data) {
+            self.check(data)?;
+            return decode_heif(data);
+        }
+        self.reader(data)?.decode()
+    }
+}
+
+// HEIC/HEIF：ISO BMFF 容器，ftyp 的主品牌为 HEVC 系列，或为 mif1/msf1 且兼容品牌中没有 avif
+// (AVIF 使用同样的容器，由 image 自行识别)
+pub fn is_heif(data: &[u8]) -> bool {
+    if data.get(4..8) != Some(b"ftyp") {
+        return false;
+    }
+    let size = data
+        .get(..4)
+        .map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    match data.get(8..12) {
+        Some(b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis") => true,
+        Some(b"mif1" | b"msf1") => !data
+            .get(16..size.min(data.len()))
+            .unwrap_or_default()
+            .chunks_exact(4)
+            .any(|brand| brand == b"avif" || brand == b"avis"),
+        _ => false,
+    }
+}
+
+// 从文件头读取尺寸，不解码像素
+fn dimensions(data: &[u8]) -> image::ImageResult<(u32, u32)> {
+    if let Some(preview) = raw_preview(data) {
+        return dimensions(preview);
+    }
+    if is_heif(data) {
+        return heif_dimensions(data);
+    }
+    ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_dimensions()
+}
+
+#[cfg(feature = "heic")]
+fn heif_error(e: libheif_rs::HeifError) -> image::ImageError {
+    image::ImageError::Decoding(image::error::DecodingError::new(
+        image::error::ImageFormatHint::Name("HEIF".to_string()),
+        e,
+    ))
+}
+
+#[cfg(feature = "heic")]
+fn heif_dimensions(data: &[u8]) -> image::ImageResult<(u32, u32)> {
+    let ctx = libheif_rs::HeifContext::read_from_bytes(data).map_err(heif_error)?;
+    let handle = ctx.primary_image_handle().map_err(heif_error)?;
+    Ok((handle.width(), handle.height()))
+}
+
+// 解码主图像；libheif 会应用容器中的旋转/镜像 (irot/imir)，结果已是显示方向
+#[cfg(feature = "heic")]
+fn decode_heif(data: &[u8]) -> image::ImageResult<DynamicImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let ctx = HeifContext::read_from_bytes(data).map_err(heif_error)?;
+    let handle = ctx.primary_image_handle().map_err(heif_error)?;
+    let alpha = handle.has_alpha_channel();
+    let chroma = if alpha {
+        RgbChroma::Rgba
+    } else {
+        RgbChroma::Rgb
+    };
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(heif_error)?;
+    let planes = image.planes();
+    let plane = planes.interleaved.ok_or_else(heif_unsupported)?;
+
+    // 每行末尾可能有对齐用的填充，逐行复制
+    let row = plane.width as usize * if alpha { 4 } else { 3 };
+    let mut pixels = Vec::with_capacity(row * plane.height as usize);
+    for line in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(line.get(..row).ok_or_else(heif_unsupported)?);
+    }
+    let img = if alpha {
+        image::RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgba8)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+    };
+    img.ok_or_else(heif_unsupported)
+}
+
+#[cfg(not(feature = "heic"))]
+fn heif_dimensions(_data: &[u8]) -> image::ImageResult<(u32, u32)> {
+    Err(heif_unsupported())
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heif(_data: &[u8]) -> image::ImageResult<DynamicImage> {
+    Err(heif_unsupported())
+}
+
+// 未启用 heic feature，或解码结果不是 8 位 RGB(A)
+fn heif_unsupported() -> image::ImageError {
+    let hint = image::error::ImageFormatHint::Name("HEIF".to_string());
+    image::ImageError::Unsupported(image::error::UnsupportedError::from_format_and_kind(
+        hint.clone(),
+        image::error::UnsupportedErrorKind::Format(hint),
+    ))
+}
+
+// 重新编码时使用的格式：与原图相同，无法编码的 HEIF 和 RAW 使用 JPEG
+fn output_format(data: &[u8]) -> image::ImageFormat {
+    if is_heif(data) || raw_mime(data).is_some() {
+        return image::ImageFormat::Jpeg;
+    }
+    image::guess_format(data).unwrap_or(image::ImageFormat::Png)
+}
+
+// 按 EXIF 方向旋转/翻转为显示方向；HEIF 的方向由容器描述，解码时已应用
+fn apply_exif_orientation(data: &[u8], img: &mut DynamicImage) {
+    if is_heif(data) {
+        return;
+    }
+    if let Some(orientation) = exif_orientation(data)
+        .and_then(|o| u8::try_from(o).ok())
+        .and_then(image::metadata::Orientation::from_exif)
+    {
+        img.apply_orientation(orientation);
+    }
+}
+
+// 为 src 生成像素数约为 thumbnail_pixels 的缩略图，写入 dst；视频使用第一帧
+pub fn generate_thumbnail(
+    src: &Path,
+    dst: &Path,
+    thumbnail_pixels: u32,
+    progressive: bool,
+    limits: DecodeLimits,
+    key: Option<&BlobKey>,
+) -> image::ImageResult<String> {
+    // 1. 读取 (必要时解密) 文件，视频换成 ffmpeg 提取的第一帧
+    let data = read_blob(src, key)?;
+    let video = video_mime(&data).is_some();
+    let data = match video {
+        true => poster_frame(&data)?,
+        false => data,
+    };
+
+    // 2. 尺寸超出限制时不解码
+    limits.check(&data)?;
+
+    // 3. 在解码前获取格式，用于后续保存；HEIF、RAW 和视频的缩略图保存为 JPEG
+    let format = match video {
+        true => image::ImageFormat::Jpeg,
+        false => output_format(&data),
+    };
+
+    // 4. 解码图片；JPEG (含 RAW 的预览图) 优先在解码时按 DCT 缩放，不支持时退回完整解码
+    let img = match format {
+        image::ImageFormat::Jpeg if !is_heif(&data) => {
+            decode_jpeg_scaled(raw_preview(&data).unwrap_or(&data), thumbnail_pixels)
+        }
+        _ => None,
+    };
+    let img = match img {
+        Some(img) => img,
+        None => limits.decode(&data)?,
+    };
+
+    // 5. 计算缩放后的尺寸
+    let (width, height) = img.dimensions();
+    let current_pixels = (width * height) as f64;
+
+    // 计算缩放比例：sqrt(目标像素 / 当前像素)
+    let scale_factor = (thumbnail_pixels as f64 / current_pixels).sqrt();
+
+    // 如果当前像素已经小于目标值，可以选择不缩放，或者仍然强制缩放
+    // 这里假设：如果图片太大，就缩小；如果本来就小，保持原样 (scale_factor > 1.0)
+    let (new_w, new_h) = if scale_factor < 1.0 {
+        (
+            (width as f64 * scale_factor) as u32,
+            (height as f64 * scale_factor) as u32,
+        )
+    } else {
+        (width, height)
+    };
+
+    // 6. 生成缩略图 (thumbnail 会保持宽高比)
+    let mut thumb = img.thumbnail(new_w, new_h);
+
+    // 7. 按 EXIF 方向旋转/翻转，使缩略图与原图的显示方向一致 (缩小后再处理开销更小)
+    apply_exif_orientation(&data, &mut thumb);
+
+    // 8. 使用与输入相同的格式保存；progressive 时 JPEG 转为渐进式、PNG 转为隔行，便于逐步显示
+    let mut output = Cursor::new(Vec::new());
+    thumb.write_to(&mut output, format)?;
+    let mut output = output.into_inner();
+    if progressive {
+        let converted = match format {
+            image::ImageFormat::Jpeg => progressive_jpeg(&output),
+            image::ImageFormat::Png => interlace_png(&output),
+            _ => None,
+        };
+        if let Some(converted) = converted {
+            output = converted;
+        }
+    }
+    write_blob(dst, &output, key)?;
+
+    // 9. 顺便计算 BlurHash 占位图
+    Ok(blurhash(&thumb))
+}
+
+// BlurHash (https://blurha.sh)：4×3 个 DCT 分量编码成的短字符串，前端可据此立即绘制模糊占位图
+// 先缩小到 32×32 以内再计算，结果与原尺寸计算几乎相同
+pub fn blurhash(img: &DynamicImage) -> String {
+    const X: usize = 4;
+    const Y: usize = 3;
+    let pixels = img.thumbnail(32, 32).to_rgb8();
+    let (width, height) = (pixels.width() as usize, pixels.height() as usize);
+    let to_linear = |v: u8| {
+        let v = v as f64 / 255.0;
+        if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let to_srgb = |v: f64| {
+        let v = v.clamp(0.0, 1.0);
+        let v = if v <= 0.0031308 {
+            v * 12.92
+        } else {
+            1.055 * v.powf(1.0 / 2.4) - 0.055
+        };
+        (v * 255.0 + 0.5) as u32
+    };
+
+    let mut factors = Vec::with_capacity(X * Y);
+    for j in 0..Y {
+        for i in 0..X {
+            let mut sum = [0.0; 3];
+            for (x, y, pixel) in pixels.enumerate_pixels() {
+                let basis = (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos()
+                    * (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+                for (s, &c) in sum.iter_mut().zip(&pixel.0) {
+                    *s += basis * to_linear(c);
+                }
+            }
+            let scale = if i == 0 && j == 0 { 1.0 } else { 2.0 } / (width * height) as f64;
+            factors.push(sum.map(|s| s * scale));
+        }
+    }
+
+    const CHARS: &[u8] =
+        b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+    let mut hash = String::new();
+    let mut encode = |value: u32, len: u32| {
+        for i in (0..len).rev() {
+            hash.push(CHARS[(value / 83u32.pow(i) % 83) as usize] as char);
+        }
+    };
+    encode(((X - 1) + (Y - 1) * 9) as u32, 1);
+    let max_ac = factors[1..]
+        .iter()
+        .flatten()
+        .fold(0.0f64, |max, v| max.max(v.abs()));
+    let quantised_max = (max_ac * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+    let max_value = (quantised_max + 1) as f64 / 166.0;
+    encode(quantised_max, 1);
+    let [r, g, b] = factors[0];
+    encode((to_srgb(r) << 16) + (to_srgb(g) << 8) + to_srgb(b), 4);
+    for factor in &factors[1..] {
+        let [r, g, b] = factor.map(|v| {
+            let v = v / max_value;
+            (v.signum() * v.abs().sqrt() * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        encode(r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+// 感知哈希 (dHash)：缩小为 9×8 的灰度图，逐行比较相邻像素的明暗，得到 64 位指纹
+// 重新压缩或缩放后的图片指纹几乎不变，汉明距离很小即可视为同一张图片
+pub fn perceptual_hash(
+    src: &Path,
+    limits: DecodeLimits,
+    key: Option<&BlobKey>,
+) -> image::ImageResult<u64> {
+    let data = read_blob(src, key)?;
+    limits.check(&data)?;
+    let img = match output_format(&data) {
+        image::ImageFormat::Jpeg if !is_heif(&data) => {
+            decode_jpeg_scaled(raw_preview(&data).unwrap_or(&data), 64 * 64)
+        }
+        _ => None,
+    };
+    let mut img = match img {
+        Some(img) => img,
+        None => limits.decode(&data)?,
+    };
+    // 方向不同的同一张图片 (例如被聊天软件按 EXIF 旋转后重新编码) 指纹相同
+    apply_exif_orientation(&data, &mut img);
+    let small = img
+        .resize_exact(9, 8, image::imageops::FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    Ok(hash)
+}
+
+// 利用 JPEG 的 DCT 缩放 (1/2、1/4、1/8) 直接解码出接近目标像素数的图片
+// 大图无需把全尺寸像素解码到内存；像素格式不是 L8/RGB24 或解码失败时返回 None
+fn decode_jpeg_scaled(data: &[u8], thumbnail_pixels: u32) -> Option<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(data));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+    let scale = (thumbnail_pixels as f64 / (info.width as f64 * info.height as f64))
+        .sqrt()
+        .min(1.0);
+    // 解码尺寸不小于请求的尺寸，之后仍由 thumbnail 缩放到精确大小
+    let (width, height) = decoder
+        .scale(
+            ((info.width as f64 * scale).ceil() as u16).max(1),
+            ((info.height as f64 * scale).ceil() as u16).max(1),
+        )
+        .ok()?;
+    let pixels = decoder.decode().ok()?;
+    let (width, height) = (width as u32, height as u32);
+    match decoder.info()?.pixel_format {
+        jpeg_decoder::PixelFormat::L8 => {
+            GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+        }
+        jpeg_decoder::PixelFormat::RGB24 => {
+            RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+        }
+        _ => None,
+    }
+}
+
+// 将 src 转换为 format 格式写入 dst (用于下载时的格式转换和缩略图的格式协商)
+pub fn convert_image(
+    src: &Path,
+    dst: &Path,
+    format: image::ImageFormat,
+    limits: DecodeLimits,
+    key: Option<&BlobKey>,
+) -> image::ImageResult<()> {
+    let data = read_blob(src, key)?;
+    // RAW 转为 JPEG 且不需要旋转时直接使用内嵌的预览图，避免重新编码
+    if format == image::ImageFormat::Jpeg
+        && exif_orientation(&data).is_none_or(|o| o == 1)
+        && let Some(preview) = raw_preview(&data)
+    {
+        limits.check(preview)?;
+        write_variant(dst, preview, key)?;
+        return Ok(());
+    }
+    let mut img = limits.decode(&data)?;
+    // 转换后不再带有 EXIF，先按方向旋转
+    apply_exif_orientation(&data, &mut img);
+    // JPEG 不支持透明通道
+    if format == image::ImageFormat::Jpeg && img.color().has_alpha() {
+        img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let mut output = Cursor::new(Vec::new());
+    img.write_to(&mut output, format)?;
+    write_variant(dst, output.get_ref(), key)?;
+    Ok(())
+}
+
+// 裁剪 src 中 (x, y, w, h) 的区域，可选缩放到 size，以 format 格式写入 dst
+// 坐标基于按 EXIF 方向旋转后的图片，即客户端看到的方向；区域超出图片时返回参数错误
+pub fn crop_image(
+    src: &Path,
+    dst: &Path,
+    (x, y, w, h): (u32, u32, u32, u32),
+    size: Option<(u32, u32)>,
+    format: image::ImageFormat,
+    limits: DecodeLimits,
+    key: Option<&BlobKey>,
+) -> image::ImageResult<()> {
+    let data = read_blob(src, key)?;
+    let mut img = limits.decode(&data)?;
+    apply_exif_orientation(&data, &mut img);
+
+    let (width, height) = img.dimensions();
+    if w == 0 || h == 0 || x.saturating_add(w) > width || y.saturating_add(h) > height {
+        return Err(image::ImageError::Parameter(
+            image::error::ParameterError::from_kind(
+                image::error::ParameterErrorKind::DimensionMismatch,
+            ),
+        ));
+    }
+    let mut img = img.crop_imm(x, y, w, h);
+    if let Some((w, h)) = size {
+        img = img.resize_exact(w, h, image::imageops::FilterType::Lanczos3);
+    }
+    if format == image::ImageFormat::Jpeg && img.color().has_alpha() {
+        img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    let mut output = Cursor::new(Vec::new());
+    img.write_to(&mut output, format)?;
+    write_variant(dst, output.get_ref(), key)?;
+    Ok(())
+}
+
+// 旋转/翻转操作，旋转方向为顺时针
+#[derive(Debug, Clone, Copy, serde::Deserialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipH,
+    FlipV,
+}
+
+// 依次应用 ops 并以原格式重新编码；EXIF 方向会先应用到像素上，结果不再带有 EXIF
+pub fn apply_transforms(
+    data: &[u8],
+    ops: &[Transform],
+    limits: DecodeLimits,
+) -> image::ImageResult<Vec<u8>> {
+    // HEIF 和 RAW 无法编码，以 JPEG 保存
+    let format = output_format(data);
+    let mut img = limits.decode(data)?;
+    apply_exif_orientation(data, &mut img);
+    if format == image::ImageFormat::Jpeg && img.color().has_alpha() {
+        img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+    }
+    for op in ops {
+        img = match op {
+            Transform::Rotate90 => img.rotate90(),
+            Transform::Rotate180 => img.rotate180(),
+            Transform::Rotate270 => img.rotate270(),
+            Transform::FlipH => img.fliph(),
+            Transform::FlipV => img.flipv(),
+        };
+    }
+
+    let mut output = Cursor::new(Vec::new());
+    match format {
+        // 默认质量 75 偏低，重新编码时尽量减少损失
+        image::ImageFormat::Jpeg => img.write_with_encoder(
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut output, 90),
+        )?,
+        _ => img.write_to(&mut output, format)?,
+    }
+    Ok(output.into_inner())
+}
+
+// 先写临时文件再 rename，避免并发请求读到不完整的文件
+fn write_variant(dst: &Path, data: &[u8], key: Option<&BlobKey>) -> std::io::Result<()> {
+    let temp = dst.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+    write_blob(&temp, data, key)?;
+    std::fs::rename(&temp, dst).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp);
+    })
+}
+
+// 读取 EXIF 中的拍摄时间 (DateTimeOriginal，缺失时退回 DateTime)
+// EXIF 时间不带时区，按 UTC 处理；没有 EXIF 或无法解析时返回 None
+pub fn capture_time(src: &Path, key: Option<&BlobKey>) -> Option<chrono::DateTime<chrono::Utc>> {
+    capture_time_from(&read_blob(src, key).ok()?)
+}
+
+pub fn capture_time_from(data: &[u8]) -> Option<chrono::DateTime<chrono::Utc>> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| {
+            let field = exif.get_field(tag, exif::In::PRIMARY)?;
+            let exif::Value::Ascii(ref values) = field.value else {
+                return None;
+            };
+            let text = std::str::from_utf8(values.first()?).ok()?;
+            chrono::NaiveDateTime::parse_from_str(text.trim(), "%Y:%m:%d %H:%M:%S").ok()
+        })
+        .map(|t| t.and_utc())
+}
+
+// EXIF 方向标签 (1-8)；没有 EXIF 或没有该标签时返回 None
+pub fn exif_orientation(data: &[u8]) -> Option<u16> {
+    let exif = exif::Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok()?;
+    let value = exif
+        .get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    (1..=8).contains(&value).then_some(value as u16)
+}
+
+// 去除 JPEG 中可能泄露隐私的元数据段：EXIF/XMP (APP1)、IPTC (APP13) 和注释 (COM)
+// ICC 配置等影响显示的段保留；原图带有旋转方向时写回只含方向标签的最小 EXIF
+// 不是 JPEG 或没有需要去除的段时返回 None
+pub fn strip_jpeg_metadata(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&[0xFF, 0xD8]);
+    let mut pos = 2;
+    let mut stripped = false;
+    let mut exif_at = None;
+    loop {
+        // 每个段以 0xFF 开头，之后为标记和 2 字节的长度 (含长度字段本身)
+        if data.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        // 标记前可以有任意个 0xFF 填充字节
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        // SOS 之后是压缩数据，原样复制
+        if marker == 0xDA {
+            out.extend_from_slice(&data[pos..]);
+            break;
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let end = pos + 2 + len;
+        let segment = data.get(pos..end)?;
+        if matches!(marker, 0xE1 | 0xED | 0xFE) {
+            stripped = true;
+            exif_at.get_or_insert(out.len());
+        } else {
+            out.extend_from_slice(segment);
+        }
+        pos = end;
+    }
+    if !stripped {
+        return None;
+    }
+
+    if let Some(orientation) = exif_orientation(data).filter(|&o| o != 1) {
+        // 大端 TIFF 头 + 只有一项 (0x0112 Orientation, SHORT) 的 IFD0
+        let mut app1 = vec![0xFF, 0xE1, 0x00, 0x22];
+        app1.extend_from_slice(b"Exif\0\0MM\0\x2A\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01");
+        app1.extend_from_slice(&orientation.to_be_bytes());
+        app1.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        out.splice(exif_at.unwrap_or(2)..exif_at.unwrap_or(2), app1);
+    }
+    Some(out)
+}
+
+// 根据文件头的魔数判断图片 (或视频) 格式，返回对应的 MIME 类型；无法识别时返回 None
+// RAW 需要读取 TIFF 的 IFD0 (Make 标签)，因此读取开头的 64 KiB
+pub fn sniff_content_type(src: &Path, key: Option<&BlobKey>) -> Option<String> {
+    let mut head = Vec::with_capacity(64 * 1024);
+    open_blob(src, key)
+        .ok()?
+        .take(64 * 1024)
+        .read_to_end(&mut head)
+        .ok()?;
+    if let Some(mime) = raw_mime(&head).or_else(|| video_mime(&head)) {
+        return Some(mime.to_string());
+    }
+    if is_heif(&head) {
+        return Some("image/heic".to_string());
+    }
+    image::guess_format(&head)
+        .ok()
+        .map(|f| f.to_mime_type().to_string())
+}
+
+// 缩略图的 MIME 类型：与原图相同，HEIF、RAW 和视频的缩略图为 JPEG
+pub fn thumbnail_content_type(mime: &str) -> &str {
+    match mime {
+        "video/mp4" | "video/webm" => "image/jpeg",
+        "image/heic" | "image/heif" => "image/jpeg",
+        "image/x-canon-cr2" | "image/x-nikon-nef" | "image/x-sony-arw" => "image/jpeg",
+        mime => mime,
+    }
+}
+
+// 配置中的格式名 (扩展名) 对应的 MIME 类型
+pub fn extension_mime(ext: &str) -> Option<&'static str> {
+    match ext.to_ascii_lowercase().as_str() {
+        "heic" | "heif" => Some("image/heic"),
+        "cr2" => Some("image/x-canon-cr2"),
+        "nef" => Some("image/x-nikon-nef"),
+        "arw" => Some("image/x-sony-arw"),
+        "mp4" => Some("video/mp4"),
+        "webm" => Some("video/webm"),
+        _ => image::ImageFormat::from_extension(ext).map(|f| f.to_mime_type()),
+    }
+}
+
+// 只解析文件头读取图片尺寸，不解码像素
+pub fn image_dimensions(src: &Path, key: Option<&BlobKey>) -> image::ImageResult<(u32, u32)> {
+    dimensions(&read_blob(src, key)?)
+}
+
+// 缩略图的峰值内存基准，默认不运行：
+//   cargo test --release -- --ignored thumbnail_peak_rss --nocapture
+// 生成 12000x8000 (96 MP) 的 JPEG，分别在子进程中执行 generate_thumbnail 和完整解码 (对照)，输出各自的峰值 RSS 和耗时
+// 生成测试图片本身就需要数百 MB 内存，峰值 RSS 只增不减，因此不能在同一进程中测量
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::{path::PathBuf, process::Command, time::Instant};
+
+    use super::*;
+    use crate::config::AppConfig;
+
+    const SRC_ENV: &str = "IMG_SERVER_RSS_SRC";
+    const MODE_ENV: &str = "IMG_SERVER_RSS_MODE";
+
+    #[test]
+    #[ignore]
+    fn thumbnail_peak_rss() {
+        let dir = std::env::temp_dir().join(format!("img-server-rss-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("big.jpg");
+        RgbImage::from_fn(12000, 8000, |x, y| {
+            image::Rgb([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8])
+        })
+        .save(&src)
+        .unwrap();
+        println!(
+            "source: 12000x8000 JPEG, {} MiB",
+            std::fs::metadata(&src).unwrap().len() >> 20
+        );
+
+        for mode in ["thumbnail", "full-decode"] {
+            let output = Command::new(std::env::current_exe().unwrap())
+                .args(["--ignored", "--exact", "--nocapture"])
+                .arg("imaging::tests::thumbnail_peak_rss_child")
+                .env(SRC_ENV, &src)
+                .env(MODE_ENV, mode)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            // 子进程的输出与 libtest 的 "test ... " 在同一行
+            let marker = format!("{}: peak RSS", mode);
+            for line in stdout
+                .lines()
+                .filter_map(|l| l.find(&marker).map(|i| &l[i..]))
+            {
+                println!("{}", line);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // 由 thumbnail_peak_rss 在子进程中运行；直接运行时什么也不做
+    #[test]
+    #[ignore]
+    fn thumbnail_peak_rss_child() {
+        let (Some(src), Ok(mode)) = (std::env::var_os(SRC_ENV), std::env::var(MODE_ENV)) else {
+            return;
+        };
+        let src = PathBuf::from(src);
+        let config = AppConfig::default();
+        let started = Instant::now();
+        match mode.as_str() {
+            "thumbnail" => {
+                generate_thumbnail(
+                    &src,
+                    &src.with_extension("thumb"),
+                    config.thumbnail_pixels.unwrap(),
+                    config.progressive_thumbnails,
+                    config.decode_limits(),
+                    None,
+                )
+                .unwrap();
+            }
+            _ => {
+                config
+                    .decode_limits()
+                    .decode(&read_blob(&src, None).unwrap())
+                    .unwrap();
+            }
+        }
+        let elapsed = started.elapsed();
+        // 使用 VmHWM 而不是 getrusage：ru_maxrss 会经 fork/exec 继承父进程的峰值
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let peak_kib: u64 = status
+            .lines()
+            .find_map(|l| l.strip_prefix("VmHWM:"))
+            .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+            .unwrap();
+        println!(
+            "{}: peak RSS {} MiB, {:.2} s",
+            mode,
+            peak_kib >> 10,
+            elapsed.as_secs_f64()
+        );
+    }
+}
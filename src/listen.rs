@@ -0,0 +1,186 @@
+// 监听 socket 的来源：由 systemd 的 socket activation 传入，或自行绑定 --addr (可指定多个，同一个 Router 在所有 socket 上提供服务)
+//
+// systemd 启动服务时通过环境变量 LISTEN_PID / LISTEN_FDS 告知已打开的 socket，
+// 第一个 fd 固定为 3 (SD_LISTEN_FDS_START)；只使用第一个 socket
+//
+// --addr 写作 unix:/path 时监听 Unix domain socket，访问控制交给文件权限；
+// 连接没有 IP 地址，handler 中看到的客户端地址统一为 127.0.0.1:0
+use std::{io, net::SocketAddr, path::PathBuf};
+
+use axum::serve::Listener;
+
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+pub enum Bound {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(UnixSocket),
+}
+
+// Unix domain socket 监听；drop 时删除 socket 文件
+#[cfg(unix)]
+pub struct UnixSocket {
+    listener: tokio::net::UnixListener,
+    // 自行创建的 socket 文件；systemd 传入的 socket 由 systemd 管理，为 None
+    path: Option<PathBuf>,
+}
+
+// Unix socket 连接对应的客户端地址
+#[cfg(unix)]
+const UNIX_PEER: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 0);
+
+#[cfg(unix)]
+impl Listener for UnixSocket {
+    type Io = tokio::net::UnixStream;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        let (io, _) = Listener::accept(&mut self.listener).await;
+        (io, UNIX_PEER)
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(UNIX_PEER)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// 取得 systemd 传入的监听 socket；未通过 socket activation 启动时返回 None
+#[cfg(unix)]
+fn systemd_listener() -> anyhow::Result<Option<Bound>> {
+    use std::os::fd::{FromRawFd as _, IntoRawFd as _};
+
+    // LISTEN_PID 与本进程不同时，环境变量是从父进程继承的，不属于本进程
+    let pid = std::env::var("LISTEN_PID").ok();
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(std::process::id()) {
+        return Ok(None);
+    }
+    let fds: usize = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    if fds == 0 {
+        return Ok(None);
+    }
+    if fds > 1 {
+        log::warn!("systemd passed {} sockets, only the first one is used", fds);
+    }
+    // SAFETY: LISTEN_PID 指向本进程时，fd 3 由 systemd 打开并交给本进程，此外没有其他所有者
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(LISTEN_FDS_START) };
+    // ListenStream 为路径时是 Unix socket，否则按 TCP socket 处理
+    if let Ok(addr) = unix.local_addr()
+        && let Some(path) = addr.as_pathname()
+    {
+        log::info!("Using socket passed by systemd: unix:{}", path.display());
+        unix.set_nonblocking(true)?;
+        return Ok(Some(Bound::Unix(UnixSocket {
+            listener: tokio::net::UnixListener::from_std(unix)?,
+            path: None,
+        })));
+    }
+    // SAFETY: fd 的所有权从上面的 UnixListener 中取回，仍然只有一个所有者
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+    let addr = tcp
+        .local_addr()
+        .map_err(|e| anyhow::anyhow!("unsupported socket passed by systemd: {}", e))?;
+    log::info!("Using socket passed by systemd: {}", addr);
+    tcp.set_nonblocking(true)?;
+    Ok(Some(Bound::Tcp(tokio::net::TcpListener::from_std(tcp)?)))
+}
+
+#[cfg(not(unix))]
+fn systemd_listener() -> anyhow::Result<Option<Bound>> {
+    Ok(None)
+}
+
+// 监听 socket：优先使用 systemd 传入的 socket，否则绑定每个 addr (host:port 或 unix:/path)
+pub async fn bind(addrs: &[String]) -> anyhow::Result<Vec<Bound>> {
+    if let Some(bound) = systemd_listener()? {
+        return Ok(vec![bound]);
+    }
+    // 同时监听多个地址时 IPv6 socket 只接受 IPv6 连接，否则 Linux 上 [::]:port 会占用 0.0.0.0:port
+    let v6only = addrs.len() > 1;
+    let mut listeners = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        listeners.push(bind_one(addr, v6only).await?);
+    }
+    Ok(listeners)
+}
+
+async fn bind_one(addr: &str, v6only: bool) -> anyhow::Result<Bound> {
+    if let Some(path) = addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        return bind_unix(PathBuf::from(path));
+        #[cfg(not(unix))]
+        anyhow::bail!("unix sockets are not supported on this platform: {}", path);
+    }
+    let listener = match addr.parse::<SocketAddr>() {
+        Ok(addr @ SocketAddr::V6(_)) if v6only => bind_v6only(addr)?,
+        _ => tokio::net::TcpListener::bind(addr).await?,
+    };
+    log::info!("Listening on {}", addr);
+    Ok(Bound::Tcp(listener))
+}
+
+// 设置 IPV6_V6ONLY 后绑定；其他平台 (Windows) 的 IPv6 socket 默认即为 v6only
+#[cfg(unix)]
+fn bind_v6only(addr: SocketAddr) -> io::Result<tokio::net::TcpListener> {
+    use std::os::fd::AsRawFd as _;
+
+    let socket = tokio::net::TcpSocket::new_v6()?;
+    socket.set_reuseaddr(true)?;
+    let on: libc::c_int = 1;
+    // SAFETY: fd 在 socket 的生命周期内有效，传入的指针和长度对应一个 c_int
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            (&on as *const libc::c_int).cast(),
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+#[cfg(not(unix))]
+fn bind_v6only(addr: SocketAddr) -> io::Result<tokio::net::TcpListener> {
+    let listener = std::net::TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(listener)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: PathBuf) -> anyhow::Result<Bound> {
+    use std::os::unix::fs::FileTypeExt as _;
+
+    // 上次未正常退出时残留的 socket 文件；路径上是其他文件时不覆盖
+    if let Ok(meta) = std::fs::symlink_metadata(&path) {
+        anyhow::ensure!(
+            meta.file_type().is_socket(),
+            "{:?} exists and is not a socket",
+            path
+        );
+        std::fs::remove_file(&path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    log::info!("Listening on unix:{}", path.display());
+    Ok(Bound::Unix(UnixSocket {
+        listener,
+        path: Some(path),
+    }))
+}
@@ -1,15 +1,57 @@
-use std::path::PathBuf;
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use flexi_logger::{
-    Age, Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, Logger, LoggerHandle, Naming,
-    Record, WriteMode,
+    Age, Cleanup, Criterion, DeferredNow, Duplicate, FileSpec, LogSpecification, Logger,
+    LoggerHandle, Naming, Record, WriteMode,
+    writers::{FileLogWriter, LogWriter},
 };
+use serde::{Deserialize, Serialize};
+
+tokio::task_local! {
+    // 当前请求的 ID，由请求中间件设置；处理请求期间的日志都附带该 ID
+    pub static REQUEST_ID: String;
+}
+
+// 当前任务所处理请求的 ID；后台任务和 spawn_blocking 中为 None
+pub(crate) fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+// 日志格式；环境变量 IMG_SERVER_LOG_FORMAT (text / json) 优先于配置
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    // [时间] 等级 - 内容
+    #[default]
+    Text,
+    // 每行一个 JSON 对象，便于 Loki / ELK 等直接采集
+    Json,
+}
+
+impl LogFormat {
+    // 应用环境变量的覆盖；无法识别的值忽略
+    pub fn with_env_override(self) -> Self {
+        match std::env::var("IMG_SERVER_LOG_FORMAT").as_deref() {
+            Ok("text") => LogFormat::Text,
+            Ok("json") => LogFormat::Json,
+            _ => self,
+        }
+    }
+}
 
 pub struct LoggerGuard(LoggerHandle);
 
 impl LoggerGuard {
     pub fn new(dir: PathBuf) -> Self {
-        let handle = init_logger(dir).unwrap();
+        let handle = init_logger(dir, LogFormat::Text.with_env_override(), false).unwrap();
         Self(handle)
     }
 }
@@ -26,26 +68,221 @@ fn my_log_format(
     now: &mut DeferredNow,
     record: &Record,
 ) -> std::io::Result<()> {
+    // 处理请求期间的日志在等级后附带请求 ID
+    let id = request_id().map(|id| format!(" [{}]", id));
     write!(
         w,
-        "[{time}] {level} - {message}",
+        "[{time}] {level}{id} - {message}",
         time = now.format("%Y-%m-%d %H:%M:%S"), // 时间
         level = record.level(),                 // 等级
+        id = id.unwrap_or_default(),            // 请求 ID
         message = record.args()                 // 日志内容
     )
 }
 
-pub fn init_logger(dir: PathBuf) -> Result<LoggerHandle, flexi_logger::FlexiLoggerError> {
-    let handle = Logger::try_with_env_or_str("info")?
+// JSON 格式：timestamp、level、target、message，以及从消息中提取的字段
+// 消息按惯例写作 "addr: {:?}, action: upload, name: {:?}"，这些键值对作为同名字段输出，
+// Debug 格式的字符串去掉引号，数字和布尔值保持原类型；不符合该格式的消息只有 message
+fn json_log_format(
+    w: &mut dyn std::io::Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    let message = record.args().to_string();
+    let mut line = serde_json::Map::new();
+    line.insert("timestamp".into(), now.now().to_rfc3339().into());
+    line.insert("level".into(), record.level().as_str().into());
+    line.insert("target".into(), record.target().into());
+    if let Some(id) = request_id() {
+        line.insert("request_id".into(), id.into());
+    }
+    for (key, value) in message_fields(&message) {
+        if !line.contains_key(key) && key != "message" {
+            line.insert(key.into(), value);
+        }
+    }
+    line.insert("message".into(), message.into());
+    write!(w, "{}", serde_json::Value::Object(line))
+}
+
+// 按 ", " 拆分 "key: value" 对，引号、括号内的内容不拆分；任一部分不是键值对时返回空
+fn message_fields(message: &str) -> Vec<(&str, serde_json::Value)> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quoted, mut escaped, mut start) = (0i32, false, false, 0);
+    for (i, c) in message.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' | '[' | '{' if !quoted => depth += 1,
+            ')' | ']' | '}' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 && message[i..].starts_with(", ") => {
+                parts.push(&message[start..i]);
+                start = i + 2;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&message[start..]);
+
+    let mut fields = Vec::with_capacity(parts.len());
+    for part in parts {
+        let Some((key, value)) = part.split_once(": ") else {
+            return Vec::new();
+        };
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Vec::new();
+        }
+        let value = match serde_json::from_str(value) {
+            Ok(v @ (serde_json::Value::String(_) | serde_json::Value::Number(_))) => v,
+            Ok(v @ serde_json::Value::Bool(_)) => v,
+            _ => value.into(),
+        };
+        fields.push((key, value));
+    }
+    fields
+}
+
+// 访问日志单独写入 <logs>/access/ 下按天轮换的文件，不进入应用日志和 stderr
+// 与应用日志分开目录，避免应用日志 (无文件名前缀) 轮换清理时把访问日志当作自己的文件
+// 直接写入该 writer 而不经过 log 宏，因此不受日志级别 (RUST_LOG、PUT /admin/log-level) 影响；未启用时为空
+static ACCESS_LOG: OnceLock<FileLogWriter> = OnceLock::new();
+
+// 原样输出，行内容由 log_access 生成
+fn access_log_format(
+    w: &mut dyn std::io::Write,
+    _now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    write!(w, "{}", record.args())
+}
+
+// 一次请求的访问日志内容
+pub struct AccessEntry<'a> {
+    pub addr: Option<SocketAddr>,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub version: axum::http::Version,
+    pub status: u16,
+    pub bytes: Option<u64>,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub elapsed: Duration,
+    pub request_id: &'a str,
+}
+
+// 记录一次请求；格式为 Apache combined 日志格式，末尾附加处理耗时 (秒) 和请求 ID：
+// 127.0.0.1 - - [16/Oct/2026:20:55:46 +0800] "GET /images/a HTTP/1.1" 200 259494 "-" "curl/8.5.0" 0.012 4f1c...
+pub fn log_access(entry: &AccessEntry) {
+    let Some(writer) = ACCESS_LOG.get() else {
+        return;
+    };
+    let optional = |v: Option<&str>| v.map_or_else(|| "-".to_string(), |v| v.replace('"', "\\\""));
+    let line = format!(
+        "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\" {:.3} {}",
+        entry
+            .addr
+            .map_or_else(|| "-".to_string(), |a| a.ip().to_string()),
+        chrono::Local::now().format("%d/%b/%Y:%H:%M:%S %z"),
+        entry.method,
+        entry.uri,
+        entry.version,
+        entry.status,
+        entry
+            .bytes
+            .map_or_else(|| "-".to_string(), |b| b.to_string()),
+        optional(entry.referer),
+        optional(entry.user_agent),
+        entry.elapsed.as_secs_f64(),
+        entry.request_id,
+    );
+    if let Err(e) = writer.write(
+        &mut DeferredNow::new(),
+        &Record::builder()
+            .level(log::Level::Info)
+            .args(format_args!("{}", line))
+            .build(),
+    ) {
+        log::warn!("Failed to write access log: {}", e);
+    }
+}
+
+// 写出缓冲中的访问日志并停止其后台线程，在停止服务前调用
+pub fn shutdown_access_log() {
+    if let Some(writer) = ACCESS_LOG.get() {
+        let _ = writer.flush();
+        writer.shutdown();
+    }
+}
+
+// 运行中的日志 handle，用于修改日志级别
+static HANDLE: OnceLock<LoggerHandle> = OnceLock::new();
+
+// 每次修改日志级别时递增；到期恢复时据此判断期间是否又被修改过
+static LEVEL_CHANGES: AtomicU64 = AtomicU64::new(0);
+
+// 当前的日志级别，格式同 RUST_LOG，例如 "info, img_server::handler = debug"
+pub fn log_level() -> Option<String> {
+    HANDLE.get()?.current_log_spec().ok().map(|s| s.to_string())
+}
+
+// 修改日志级别，revert_after 后恢复为修改前的级别 (期间再次修改时以新的修改为准)
+pub fn set_log_level(spec: &str, revert_after: Option<Duration>) -> anyhow::Result<()> {
+    let handle = HANDLE
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("logger is not initialized"))?;
+    let spec = LogSpecification::parse(spec)?;
+    let previous = handle.current_log_spec()?;
+    handle.set_new_spec(spec);
+    let change = LEVEL_CHANGES.fetch_add(1, Ordering::Relaxed) + 1;
+    if let Some(after) = revert_after {
+        tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if LEVEL_CHANGES.load(Ordering::Relaxed) == change {
+                handle.set_new_spec(previous);
+                log::info!("Log level restored to {}", log_level().unwrap_or_default());
+            }
+        });
+    }
+    Ok(())
+}
+
+pub fn init_logger(
+    dir: PathBuf,
+    format: LogFormat,
+    access_log: bool,
+) -> Result<LoggerHandle, flexi_logger::FlexiLoggerError> {
+    let logger = Logger::try_with_env_or_str("info")?;
+    if access_log {
+        let writer = FileLogWriter::builder(
+            FileSpec::default()
+                .directory(dir.join("access"))
+                .basename("access"),
+        )
+        .rotate(
+            Criterion::Age(Age::Day),
+            Naming::Timestamps,
+            Cleanup::KeepLogAndCompressedFiles(5, 30),
+        )
+        .format(access_log_format)
+        .write_mode(WriteMode::BufferAndFlush)
+        .try_build()?;
+        let _ = ACCESS_LOG.set(writer);
+    }
+    let handle = logger
         .log_to_file(FileSpec::default().directory(dir).suppress_basename())
         .rotate(
             Criterion::Age(Age::Day),
             Naming::Timestamps,
             Cleanup::KeepLogAndCompressedFiles(5, 30),
         )
-        .format(my_log_format)
+        .format(match format {
+            LogFormat::Text => my_log_format,
+            LogFormat::Json => json_log_format,
+        })
         .duplicate_to_stderr(Duplicate::All)
         .write_mode(WriteMode::BufferAndFlush)
         .start()?;
+    let _ = HANDLE.set(handle.clone());
     Ok(handle)
 }
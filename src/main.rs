@@ -1,22 +1,66 @@
+pub mod album;
+pub mod catalog;
+pub mod commands;
+pub mod compat;
 pub mod config;
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod handler;
+pub mod id;
+pub mod imaging;
+pub mod listen;
 pub mod logging;
+pub mod migrate;
+pub mod moderation;
+pub mod openapi;
+pub mod optimize;
+pub mod pool;
+pub mod raw;
+pub mod report;
+pub mod sentry;
+pub mod stats;
+pub mod storage;
+pub mod tasks;
+pub mod telemetry;
+pub mod upstream;
+pub mod usage;
+pub mod video;
 
-use std::{net::SocketAddr, path::PathBuf, sync::Arc};
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 use tokio::sync::RwLock;
 
 use axum::{
-    Router,
-    extract::DefaultBodyLimit,
-    routing::{get, post},
+    Router, middleware,
+    routing::{delete, get, post, put},
+    serve::ListenerExt as _,
 };
 use clap::{CommandFactory, Parser, Subcommand};
-use log::info;
+use log::{info, warn};
 use tokio::fs::{self};
 
 use crate::{
-    config::{AppState, CONFIG_DIR, load_config, save_config},
-    handler::{delete_image, download_image, list_images, upload_image},
+    config::{AppState, CONFIG_DIR, generate_token, load_config, save_config},
+    handler::{
+        abort_upload, batch_delete, capabilities, complete_upload, create_one_time_link,
+        create_upload, delete_image, download_blob, download_crop, download_image,
+        download_one_time, get_log_level, get_stats, get_upload, graphql, health, image_info,
+        limit_body, list_aliases, list_broken_sources, list_images, list_quarantine, list_tags,
+        list_versions, metrics, openapi_json, post_images, put_image, put_upload_chunk, readyz,
+        rename_image, rotate_token, set_log_level, swagger_ui, track_in_flight, transform_image,
+        update_image, usage_report,
+    },
+    pool::ProcessingPool,
+    stats::Stats,
+    usage::Usage,
 };
 
 #[derive(Parser)]
@@ -34,10 +78,167 @@ struct Cli {
 enum Commands {
     /// Generate a new admin token
     GenToken,
+    /// Manage admin tokens
+    #[command(alias = "token")]
+    Tokens {
+        #[command(subcommand)]
+        command: TokensCommand,
+    },
+    /// Manage thumbnails
+    Thumbs {
+        #[command(subcommand)]
+        command: ThumbsCommand,
+    },
+    /// Re-hash every stored blob and report corrupted or missing files
+    Verify {
+        /// Remove metadata entries whose blob is missing
+        #[arg(long)]
+        prune: bool,
+    },
+    /// Check the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Upgrade the config file and metadata to the current format, backing up the originals
+    Migrate {
+        /// Only show what would be migrated
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Find groups of visually near-identical images and optionally merge them into one blob
+    Dedupe {
+        /// Maximum perceptual hash distance, defaults to `similar_distance` in the config
+        #[arg(short, long)]
+        threshold: Option<u32>,
+        /// Ask which blob to keep for each group and merge the others into it
+        #[arg(short, long)]
+        interactive: bool,
+    },
+    /// Delete images uploaded before a given age, together with blobs no other image uses
+    Prune {
+        /// Minimum age, e.g. `90d`, `12h` or `2w`
+        #[arg(long, value_parser = commands::parse_age)]
+        older_than: chrono::Duration,
+        /// Only delete images with this tag
+        #[arg(long)]
+        tag: Option<String>,
+        /// Only list what would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Import images from an existing directory
+    Import {
+        /// Directory to import from
+        dir: PathBuf,
+        /// Walk subdirectories too
+        #[arg(short, long)]
+        recursive: bool,
+        /// Where image names come from
+        #[arg(long, value_enum, default_value = "filename")]
+        name_from: commands::NameFrom,
+    },
+    /// Export all image metadata
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value = "json")]
+        format: commands::ExportFormat,
+        /// Write to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Print image counts, storage usage, the biggest files and uploads per month, read directly
+    /// from the data directory (no running server needed)
+    Stats {
+        /// Number of biggest files to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        /// Print the stats as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Probe a running server's /readyz endpoint, exiting non-zero if it is not ready
+    Healthcheck {
+        #[arg(short, long, default_value = "127.0.0.1:3918")]
+        addr: String,
+    },
+    /// Print a running server's version, uptime, image count and storage usage
+    Status {
+        #[arg(short, long, default_value = "127.0.0.1:3918")]
+        addr: String,
+        /// Admin token, defaults to a token from the config file
+        #[arg(long)]
+        token: Option<String>,
+        /// Print the raw stats as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show or change a running server's log level, e.g. `debug` or `info,img_server::handler=debug`
+    LogLevel {
+        /// New level; prints the current level if omitted
+        level: Option<String>,
+        #[arg(short, long, default_value = "127.0.0.1:3918")]
+        addr: String,
+        /// Admin token, defaults to a token from the config file
+        #[arg(long)]
+        token: Option<String>,
+        /// Restore the previous level after this many seconds
+        #[arg(long, requires = "level")]
+        duration_secs: Option<u64>,
+    },
+    /// Print a shell completion script, e.g. `img-server completions bash > /etc/bash_completion.d/img-server`
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
     /// Run the server
     Serve {
+        /// Listen address (host:port or unix:/path/to/socket), repeat to listen on several;
+        /// ignored when started through systemd socket activation
         #[arg(short, long, default_value = "0.0.0.0:3918")]
-        addr: String,
+        addr: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokensCommand {
+    /// List tokens with their fingerprint, label, creation date and expiry
+    List,
+    /// Revoke a token, given the token itself, a prefix of it, or its fingerprint from `tokens list`
+    Revoke { token: String },
+    /// Issue a new token for a label; the label's old token stays valid for a grace period
+    Rotate {
+        label: String,
+        /// Grace period in hours, defaults to `token_grace_hours` in the config
+        #[arg(long)]
+        grace_hours: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Parse the config and check directory permissions, image names, blobs and the blacklist,
+    /// exiting non-zero if problems are found
+    Validate {
+        /// Config file to check, defaults to the one given by --config
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ThumbsCommand {
+    /// Regenerate thumbnails for all stored images
+    Regen {
+        /// Only generate thumbnails that do not exist yet
+        #[arg(long)]
+        missing_only: bool,
+        /// Number of worker threads, defaults to the number of CPUs
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Ignore the progress of an interrupted run and start over
+        #[arg(long)]
+        fresh: bool,
     },
 }
 
@@ -55,14 +256,7 @@ async fn main() -> anyhow::Result<()> {
 
     match cli.command {
         Some(Commands::GenToken) => {
-            let token: String = (0..32)
-                .map(|_| {
-                    let idx: usize = rand::random_range(0..62);
-                    const CHARS: &[u8] =
-                        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
-                    CHARS[idx] as char
-                })
-                .collect();
+            let token = generate_token();
 
             // 加载现有配置并添加 Token
             let mut config = load_config(&config_path)?;
@@ -72,39 +266,332 @@ async fn main() -> anyhow::Result<()> {
             println!("Generated Admin Token: {}", token);
             println!("Token added to config at: {:?}", config_path);
         }
+        Some(Commands::Tokens { command }) => match command {
+            TokensCommand::List => {
+                commands::list_tokens(&config_path)?;
+            }
+            TokensCommand::Revoke { token } => {
+                commands::revoke_token(&config_path, &token)?;
+            }
+            TokensCommand::Rotate { label, grace_hours } => {
+                commands::rotate_token(&config_path, &label, grace_hours)?;
+            }
+        },
+        Some(Commands::Thumbs { command }) => match command {
+            ThumbsCommand::Regen {
+                missing_only,
+                jobs,
+                fresh,
+            } => {
+                commands::regen_thumbs(&config_path, missing_only, jobs, fresh)?;
+            }
+        },
+        Some(Commands::Verify { prune }) => {
+            commands::verify(&config_path, prune)?;
+        }
+        Some(Commands::Config { command }) => match command {
+            ConfigCommand::Validate { path } => {
+                commands::validate_config(&path.unwrap_or(config_path))?;
+            }
+        },
+        Some(Commands::Migrate { dry_run }) => {
+            commands::migrate(&config_path, dry_run)?;
+        }
+        Some(Commands::Dedupe {
+            threshold,
+            interactive,
+        }) => {
+            commands::dedupe(&config_path, threshold, interactive)?;
+        }
+        Some(Commands::Prune {
+            older_than,
+            tag,
+            dry_run,
+        }) => {
+            commands::prune(&config_path, older_than, tag.as_deref(), dry_run)?;
+        }
+        Some(Commands::Import {
+            dir,
+            recursive,
+            name_from,
+        }) => {
+            commands::import(&config_path, &dir, recursive, name_from)?;
+        }
+        Some(Commands::Export { format, output }) => {
+            commands::export(&config_path, format, output.as_deref())?;
+        }
+        Some(Commands::Stats { top, json }) => {
+            commands::stats(&config_path, top, json)?;
+        }
+        Some(Commands::Healthcheck { addr }) => {
+            commands::healthcheck(&addr).await?;
+        }
+        Some(Commands::Status { addr, token, json }) => {
+            commands::status(&config_path, &addr, token, json).await?;
+        }
+        Some(Commands::LogLevel {
+            level,
+            addr,
+            token,
+            duration_secs,
+        }) => {
+            commands::log_level(&config_path, &addr, token, level, duration_secs).await?;
+        }
+        Some(Commands::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+        }
         Some(Commands::Serve { addr }) => {
             let config = load_config(&config_path)?;
-            let _logger = logging::init_logger(config.logs_dir().to_path_buf()).unwrap();
-            let max_size = config.max_size_mb * 1024 * 1024;
+            let logger = logging::init_logger(
+                config.logs_dir().to_path_buf(),
+                config.log_format.with_env_override(),
+                config.access_log,
+            )
+            .unwrap();
+            let telemetry = config.otlp_endpoint.as_deref().and_then(telemetry::init);
+            if let Some(dsn) = &config.sentry_dsn {
+                sentry::init(dsn);
+            }
+            let link_check_interval = config.link_check_interval_hours;
+            let report_interval = config.report_interval;
+            let pin_interval = config.pin_interval_secs;
+            let variants_budget = config.max_variants_mb;
+            let shutdown_timeout = Duration::from_secs(config.shutdown_timeout_secs);
+            let grpc_addr = config.grpc_addr.clone();
+            let persist_interval = Duration::from_millis(config.persist_interval_ms.max(10));
+            storage::configure_buffers(config.read_buffer_kb * 1024, config.write_buffer_kb * 1024);
 
             info!("Server starting with config: {:?}", config_path);
             info!("Images dir: {:?}", config.images_dir());
 
+            let (thumbnails, queue) = tokio::sync::mpsc::unbounded_channel();
+            let pool = ProcessingPool::new(
+                config
+                    .processing_workers
+                    .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get())),
+                config.processing_queue,
+            );
+            let usage = Usage::open(&config)?;
             let state = Arc::new(AppState {
                 config: RwLock::new(config),
+                usage,
                 config_path,
+                stats: Stats::default(),
+                thumbnails,
+                pool,
+                unsaved: AtomicBool::new(false),
             });
+            // 上次退出时仍在排队的缩略图重新加入队列
+            let pending: Vec<_> = {
+                let config = state.read_config("startup").await;
+                config
+                    .images()
+                    .filter(|i| i.thumbnail_pending)
+                    .map(|i| i.hash.clone())
+                    .collect()
+            };
+            for hash in pending {
+                state.queue_thumbnail(hash);
+            }
+            tokio::spawn(tasks::thumbnail_worker(state.clone(), queue));
+            tokio::spawn(tasks::persist_loop(state.clone(), persist_interval));
+            #[cfg(unix)]
+            tokio::spawn(tasks::reload_on_sighup(state.clone()));
+
+            // 后台维护任务
+            if let Some(hours) = link_check_interval {
+                tokio::spawn(tasks::link_check_loop(
+                    state.clone(),
+                    Duration::from_secs(hours * 3600),
+                ));
+            }
+            if let Some(mb) = variants_budget {
+                tokio::spawn(tasks::variant_gc_loop(
+                    state.clone(),
+                    mb * 1024 * 1024,
+                    Duration::from_secs(600),
+                ));
+            }
+            if let Some(interval) = report_interval {
+                tokio::spawn(tasks::summary_loop(state.clone(), interval.period()));
+            }
+            tokio::spawn(tasks::pin_loop(
+                state.clone(),
+                Duration::from_secs(pin_interval.max(1)),
+            ));
+
+            if let Some(grpc_addr) = grpc_addr {
+                #[cfg(feature = "grpc")]
+                {
+                    let grpc_addr: SocketAddr = grpc_addr.parse()?;
+                    let service =
+                        grpc::ImageStoreServer::new(grpc::ImageStoreService::new(state.clone()));
+                    info!("gRPC listening on {}", grpc_addr);
+                    tokio::spawn(async move {
+                        if let Err(e) = tonic::transport::Server::builder()
+                            .add_service(service)
+                            .serve_with_shutdown(grpc_addr, tasks::shutdown_signal())
+                            .await
+                        {
+                            log::error!("gRPC server error: {}", e);
+                        }
+                    });
+                }
+                #[cfg(not(feature = "grpc"))]
+                warn!(
+                    "grpc_addr {} is set but the server was built without the grpc feature",
+                    grpc_addr
+                );
+            }
 
             use tower_http::cors::{Any, CorsLayer};
             let cors = CorsLayer::new()
                 .allow_origin(Any) // 允许任何来源 (生产环境建议指定具体域名)
                 .allow_methods(Any) // 允许 GET, POST, DELETE 等
-                .allow_headers(Any); // 允许 x-admin-token 等 Header
+                .allow_headers(Any) // 允许 x-admin-token 等 Header
+                .expose_headers([handler::REQUEST_ID_HEADER]); // 允许浏览器读取 X-Request-Id
 
             let app = Router::new()
-                .route("/images", post(upload_image).get(list_images))
-                .route("/images/{id}", get(download_image).delete(delete_image))
-                .layer(DefaultBodyLimit::max(max_size)) // 限制上传大小
+                .route("/health", get(health))
+                .route("/readyz", get(readyz))
+                .route("/capabilities", get(capabilities))
+                .route("/openapi.json", get(openapi_json))
+                .route("/docs", get(swagger_ui))
+                .route("/images", post(post_images).get(list_images))
+                .route(
+                    "/images/{id}",
+                    get(download_image)
+                        .put(put_image)
+                        .delete(delete_image)
+                        .patch(update_image),
+                )
+                .route("/images/{id}/name", put(rename_image))
+                .route("/images/{id}/info", get(image_info))
+                .route("/images/{id}/crop", get(download_crop))
+                .route("/images/{id}/transform", post(transform_image))
+                .route("/images/{id}/aliases", get(list_aliases))
+                .route("/images/{id}/versions", get(list_versions))
+                .route("/images/{id}/one-time", post(create_one_time_link))
+                .route("/blob/{hash}", get(download_blob))
+                .route("/uploads", post(create_upload))
+                .route("/uploads/{id}", get(get_upload).delete(abort_upload))
+                .route("/uploads/{id}/chunks/{n}", put(put_upload_chunk))
+                .route("/uploads/{id}/complete", post(complete_upload))
+                .route("/tags", get(list_tags))
+                .route("/graphql", post(graphql))
+                .route("/one-time/{token}", get(download_one_time))
+                .route("/sharex", post(compat::sharex_upload))
+                .route("/picgo", post(compat::picgo_upload))
+                .route(
+                    "/sharex/delete/{key}",
+                    get(compat::sharex_delete_page)
+                        .post(compat::sharex_delete)
+                        .delete(compat::sharex_delete),
+                )
+                .route("/admin/images/batch-delete", post(batch_delete))
+                .route("/admin/brokensources", get(list_broken_sources))
+                .route("/albums/{album}/tokens", post(album::create_album_token))
+                .route(
+                    "/albums/{album}/tokens/{token}",
+                    delete(album::revoke_album_token),
+                )
+                .route("/albums/{album}/embed", get(album::album_embed))
+                .route("/admin/quarantine", get(list_quarantine))
+                .route("/admin/usage", get(usage_report))
+                .route("/admin/stats", get(get_stats))
+                .route("/metrics", get(metrics))
+                .route("/admin/log-level", get(get_log_level).put(set_log_level))
+                .route("/admin/tokens/{label}/rotate", post(rotate_token))
+                .layer(middleware::from_fn_with_state(state.clone(), limit_body)) // 限制上传大小
                 .layer(cors)
-                .with_state(state);
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    track_in_flight,
+                ))
+                .with_state(state.clone());
 
-            let listener = tokio::net::TcpListener::bind(&addr).await?;
-            info!("Listening on {}", addr);
-            axum::serve(
-                listener,
-                app.into_make_service_with_connect_info::<SocketAddr>(),
-            )
-            .await?;
+            // 通过 systemd socket activation 启动时使用传入的 socket，忽略 --addr
+            let listeners = listen::bind(&addr).await?;
+
+            // 收到关闭信号后所有 listener 不再接受新连接，等待进行中的请求完成；超过期限则强制退出
+            let shutdown = Arc::new(tokio::sync::Notify::new());
+            let (stop, stopped) = tokio::sync::watch::channel(false);
+            tokio::spawn({
+                let shutdown = shutdown.clone();
+                async move {
+                    tasks::shutdown_signal().await;
+                    info!("Shutting down, waiting for in-flight requests");
+                    let _ = stop.send(true);
+                    shutdown.notify_one();
+                }
+            });
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            let servers = listeners.into_iter().map(|listener| {
+                let app = app.clone();
+                let mut stopped = stopped.clone();
+                let signal = async move {
+                    let _ = stopped.wait_for(|stopped| *stopped).await;
+                };
+                let server: std::pin::Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> =
+                    match listener {
+                        listen::Bound::Tcp(listener) => Box::pin(
+                            axum::serve(listener, app)
+                                .with_graceful_shutdown(signal)
+                                .into_future(),
+                        ),
+                        // ConnectInfo<SocketAddr> 只对 TcpListener 和 TapIo 实现，经 tap_io 包装后可用
+                        #[cfg(unix)]
+                        listen::Bound::Unix(listener) => Box::pin(
+                            axum::serve(listener.tap_io(|_| {}), app)
+                                .with_graceful_shutdown(signal)
+                                .into_future(),
+                        ),
+                    };
+                server
+            });
+            let server = futures::future::try_join_all(servers);
+            // 等待期间再次收到信号时立即退出
+            let served = tokio::select! {
+                res = server => res.map(|_| ()),
+                _ = async {
+                    shutdown.notified().await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(shutdown_timeout) => {
+                            warn!("Shutdown deadline of {:?} exceeded", shutdown_timeout);
+                        }
+                        _ = tasks::shutdown_signal() => {
+                            warn!("Received a second shutdown signal");
+                        }
+                    }
+                } => {
+                    warn!(
+                        "Abandoning {} in-flight request(s)",
+                        state.stats.in_flight.load(Ordering::Relaxed)
+                    );
+                    Ok(())
+                }
+            };
+            // 服务出错退出时同样写入关闭前合并中的元数据修改
+            if let Err(e) = &served {
+                log::error!("Server error: {}", e);
+            }
+            if let Err(e) = state.flush().await {
+                log::error!("Failed to save config: {}", e);
+            }
+            // 发送剩余的错误报告，导出剩余的 span
+            sentry::flush(Duration::from_secs(5)).await;
+            drop(telemetry);
+            info!("Server stopped");
+            // 写出缓冲中的日志和访问日志，并停止日志的后台线程
+            logging::shutdown_access_log();
+            logger.flush();
+            logger.shutdown();
+            served?;
         }
         None => {
             Cli::command().print_help()?;
@@ -0,0 +1,186 @@
+// 配置文件与数据格式的版本升级
+//
+// 版本 (配置文件中的 schema_version)：
+//   1  图片记录保存在配置文件的 images 中
+//   2  图片记录保存在 <data_dir>/images.jsonl (见 catalog)
+// 旧版本的配置文件没有 schema_version，按内容推断
+//
+// 升级在反序列化为 AppConfig 之前对原始文档进行，改名或改变结构的设置项会被转换，而不是被 serde 默认值悄悄丢弃；
+// 修改任何文件前先备份原文件
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use config_file2::LoadConfigFile;
+use serde_json::{Map, Value};
+
+use crate::{
+    catalog,
+    config::{AppConfig, ImageMeta},
+};
+
+pub const CURRENT_VERSION: u32 = 2;
+
+struct Step {
+    description: &'static str,
+    run: fn(&mut Map<String, Value>) -> anyhow::Result<()>,
+}
+
+// 第 i 项把版本 i + 1 升级到 i + 2
+const STEPS: &[Step] = &[Step {
+    description: "move image records from the config file to <data_dir>/images.jsonl",
+    run: images_to_catalog,
+}];
+
+// 磁盘上的配置文件及其需要的升级
+pub struct Plan {
+    pub version: u32,
+    document: Map<String, Value>,
+}
+
+impl Plan {
+    // 读取配置文件；文件不存在时返回 None
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        let Some(document) = Value::load(path)? else {
+            return Ok(None);
+        };
+        let Value::Object(document) = document else {
+            anyhow::bail!("{:?} is not a table of settings", path);
+        };
+        let version = detect(&document)?;
+        anyhow::ensure!(
+            version <= CURRENT_VERSION,
+            "{:?} has schema version {}, but this build only supports up to {}; upgrade img-server",
+            path,
+            version,
+            CURRENT_VERSION
+        );
+        Ok(Some(Self { version, document }))
+    }
+
+    // 需要执行的步骤：(起始版本, 说明)
+    pub fn steps(&self) -> Vec<(u32, &'static str)> {
+        (self.version..CURRENT_VERSION)
+            .map(|v| (v, STEPS[v as usize - 1].description))
+            .collect()
+    }
+
+    // 当前版本不认识的设置项，加载时会被忽略，重写配置文件时丢失
+    pub fn unknown_settings(&self) -> anyhow::Result<Vec<String>> {
+        let Value::Object(known) = serde_json::to_value(AppConfig::default())? else {
+            unreachable!()
+        };
+        let mut unknown: Vec<String> = self
+            .document
+            .keys()
+            .filter(|key| !known.contains_key(*key) && (*key != "images" || self.version >= 2))
+            .cloned()
+            .collect();
+        unknown.sort();
+        Ok(unknown)
+    }
+
+    // 备份将被修改的文件 (配置文件和图片记录)，返回备份的路径
+    pub fn backup(&self, path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let suffix = format!(
+            "v{}-{}.bak",
+            self.version,
+            chrono::Local::now().format("%Y%m%d%H%M%S")
+        );
+        let mut backups = Vec::new();
+        for file in [
+            path.to_path_buf(),
+            data_dir(&self.document).join(catalog::FILE_NAME),
+        ] {
+            if !file.exists() {
+                continue;
+            }
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            let backup = file.with_file_name(format!("{}.{}", name, suffix));
+            fs::copy(&file, &backup)?;
+            backups.push(backup);
+        }
+        Ok(backups)
+    }
+
+    // 依次执行升级步骤，返回升级后的配置；调用方负责写回配置文件
+    pub fn upgrade(mut self) -> anyhow::Result<AppConfig> {
+        for step in &STEPS[self.version as usize - 1..] {
+            (step.run)(&mut self.document)?;
+        }
+        self.document
+            .insert("schema_version".to_string(), CURRENT_VERSION.into());
+        Ok(serde_json::from_value(Value::Object(self.document))?)
+    }
+}
+
+fn detect(document: &Map<String, Value>) -> anyhow::Result<u32> {
+    match document.get("schema_version") {
+        Some(version) => {
+            let version = version
+                .as_u64()
+                .filter(|v| (1..=u32::MAX as u64).contains(v))
+                .ok_or_else(|| anyhow::anyhow!("invalid schema_version: {}", version))?;
+            Ok(version as u32)
+        }
+        None => match document.get("images") {
+            Some(Value::Array(images)) if !images.is_empty() => Ok(1),
+            _ => Ok(2),
+        },
+    }
+}
+
+fn data_dir(document: &Map<String, Value>) -> PathBuf {
+    document
+        .get("data_dir")
+        .and_then(Value::as_str)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| AppConfig::default().data_dir)
+}
+
+// 1 -> 2：图片记录移到单独的日志；日志已存在时以日志为准 (之前的迁移写入了日志但没有更新配置文件)
+fn images_to_catalog(document: &mut Map<String, Value>) -> anyhow::Result<()> {
+    let mut images: Vec<ImageMeta> = match document.remove("images") {
+        Some(images) => serde_json::from_value(images)?,
+        None => Vec::new(),
+    };
+    let dir = data_dir(document);
+    let file = dir.join(catalog::FILE_NAME);
+    if catalog::read(&file)?.is_none() {
+        for (old, new) in rename_duplicates(&mut images) {
+            eprintln!("Renamed duplicate image name {:?} to {:?}", old, new);
+        }
+        fs::create_dir_all(&dir)?;
+        catalog::write(&file, &(1..).zip(images).collect())?;
+    }
+    Ok(())
+}
+
+// 旧版本允许多条记录使用相同的名称 (按名称访问时只能取到第一条)
+// 之后的记录改用 <名称>-<n>，返回 (原名称, 新名称)
+fn rename_duplicates(images: &mut [ImageMeta]) -> Vec<(String, String)> {
+    let mut all: HashSet<String> = images
+        .iter()
+        .flat_map(|i| std::iter::once(&i.name).chain(&i.aliases))
+        .cloned()
+        .collect();
+    let mut seen = HashSet::new();
+    let mut renamed = Vec::new();
+    for img in images {
+        for name in std::iter::once(&mut img.name).chain(&mut img.aliases) {
+            if seen.insert(name.clone()) {
+                continue;
+            }
+            let new = (1..)
+                .map(|i| format!("{}-{}", name, i))
+                .find(|n| !all.contains(n))
+                .expect("unbounded range");
+            all.insert(new.clone());
+            seen.insert(new.clone());
+            renamed.push((std::mem::replace(name, new.clone()), new));
+        }
+    }
+    renamed
+}
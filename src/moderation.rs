@@ -0,0 +1,54 @@
+use std::{sync::LazyLock, time::Duration};
+
+use serde::Deserialize;
+
+// 内容审核：新上传的图片在公开前发送到外部审核服务，被标记的图片隔离等待管理员审核
+//
+// 请求：POST moderation_url，请求体为原图内容，Content-Type 为识别出的 MIME 类型
+// 响应：{"flagged": bool, "reason": "..."}，reason 可省略
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(60))
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("failed to build http client")
+});
+
+#[derive(Deserialize)]
+struct Verdict {
+    flagged: bool,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// 请求审核，返回隔离原因；未被标记时返回 None
+pub async fn check(
+    url: &str,
+    data: Vec<u8>,
+    content_type: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    let resp = CLIENT
+        .post(url)
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            content_type.unwrap_or("application/octet-stream"),
+        )
+        .body(data)
+        .send()
+        .await?;
+    anyhow::ensure!(
+        resp.status().is_success(),
+        "moderation service returned {}",
+        resp.status()
+    );
+    let verdict: Verdict = serde_json::from_slice(&resp.bytes().await?)?;
+    Ok(verdict
+        .flagged
+        .then(|| verdict.reason.unwrap_or_else(|| "flagged".to_string())))
+}
@@ -0,0 +1,91 @@
+// OpenAPI 描述：由各 handler 上的 #[utoipa::path] 注解以及请求/响应类型的 ToSchema 生成
+// 新增接口时在 handler 上添加注解并加入下面的 paths(...)
+//
+// handler 以不带模块路径的名称列出 (通过 glob 导入)，否则 utoipa 会以模块名作为 tag 给接口分组
+use std::sync::LazyLock;
+
+use utoipa::{
+    Modify, OpenApi,
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+};
+
+use crate::{
+    album::*,
+    compat::*,
+    config::{ImageMeta, ImageVersion},
+    handler::*,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "img-server", description = "Self-hosted image hosting API."),
+    paths(
+        health,
+        readyz,
+        capabilities,
+        post_images,
+        list_images,
+        download_image,
+        put_image,
+        delete_image,
+        update_image,
+        rename_image,
+        image_info,
+        download_crop,
+        transform_image,
+        list_aliases,
+        list_versions,
+        create_one_time_link,
+        download_one_time,
+        sharex_upload,
+        picgo_upload,
+        sharex_delete_page,
+        sharex_delete,
+        download_blob,
+        create_upload,
+        get_upload,
+        abort_upload,
+        put_upload_chunk,
+        complete_upload,
+        list_tags,
+        create_album_token,
+        revoke_album_token,
+        album_embed,
+        graphql,
+        batch_delete,
+        list_broken_sources,
+        list_quarantine,
+        usage_report,
+        get_stats,
+        get_log_level,
+        set_log_level,
+        metrics,
+        rotate_token,
+    ),
+    components(schemas(ImageMeta, ImageVersion, StoredImage)),
+    modifiers(&AdminToken)
+)]
+struct ApiDoc;
+
+// 管理接口通过 x-admin-token 头鉴权
+struct AdminToken;
+
+impl Modify for AdminToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "adminToken",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("x-admin-token"))),
+            );
+    }
+}
+
+// 生成的 JSON，首次请求时生成一次
+pub static SPEC: LazyLock<String> = LazyLock::new(|| {
+    let mut doc = ApiDoc::openapi();
+    // utoipa 从 Cargo.toml 读取 license，未设置时会生成空的 license
+    doc.info.license = None;
+    doc.to_pretty_json().expect("OpenAPI spec is serializable")
+});
@@ -0,0 +1,852 @@
+use std::io::{Cursor, Write as _};
+
+// 无损的重新编码：压缩优化 (PNG 以最高压缩级别重新编码，基线 JPEG 按实际符号频率重建 Huffman 表，
+// 相当于 oxipng / jpegtran -optimize)，以及渐进式 JPEG / 隔行 PNG 转换。像素数据均保持不变
+
+// 按文件头选择优化方式；不支持的格式、优化失败或结果没有变小时返回 None
+pub fn optimize_image(data: &[u8]) -> Option<Vec<u8>> {
+    let optimized = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        optimize_png(data)?
+    } else if data.starts_with(&[0xFF, 0xD8]) {
+        let optimized = optimize_jpeg(data)?;
+        // 重新编码的是压缩数据本身，写回前确认解码结果完全一致
+        if decode_jpeg(&optimized)? != decode_jpeg(data)? {
+            return None;
+        }
+        optimized
+    } else {
+        return None;
+    };
+    (optimized.len() < data.len()).then_some(optimized)
+}
+
+fn decode_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    jpeg_decoder::Decoder::new(Cursor::new(data)).decode().ok()
+}
+
+// PNG：按原始位深和颜色类型解码后重新编码，保留调色板、透明度、色彩空间和文本等附加信息
+// APNG 动画不处理
+fn optimize_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().ok()?;
+    if reader.info().animation_control.is_some() {
+        return None;
+    }
+    let mut pixels = vec![0; reader.output_buffer_size()?];
+    reader.next_frame(&mut pixels).ok()?;
+    let mut info = reader.info().clone();
+    info.interlaced = false;
+
+    // 大多数图片适合自适应过滤；调色板和低位深图片通常不过滤更小，两者都尝试
+    [png::Filter::Adaptive, png::Filter::NoFilter]
+        .into_iter()
+        .filter_map(|filter| {
+            let mut output = Vec::new();
+            let mut encoder = png::Encoder::with_info(&mut output, info.clone()).ok()?;
+            encoder.set_deflate_compression(png::DeflateCompression::Level(9));
+            encoder.set_filter(filter);
+            let mut writer = encoder.write_header().ok()?;
+            writer.write_image_data(&pixels).ok()?;
+            writer.finish().ok()?;
+            Some(output)
+        })
+        .min_by_key(Vec::len)
+}
+
+// Huffman 表：每种码长的符号数 (下标为码长 1-16) 和按码长排列的符号
+#[derive(Clone, Default)]
+struct HuffmanTable {
+    counts: [u8; 17],
+    symbols: Vec<u8>,
+}
+
+impl HuffmanTable {
+    // 规范 Huffman 编码：(码长, 码字)，按符号索引
+    fn codes(&self) -> [(u8, u16); 256] {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut k = 0;
+        for len in 1..=16 {
+            for _ in 0..self.counts[len] {
+                codes[self.symbols[k] as usize] = (len as u8, code);
+                code = code.wrapping_add(1);
+                k += 1;
+            }
+            code <<= 1;
+        }
+        codes
+    }
+
+    // 按符号频率生成码长不超过 16 的最优表 (ITU T.81 附录 K.2)
+    fn optimal(freq: &[u32; 256]) -> Self {
+        // 下标 256 为保留符号，保证不会出现全 1 的码字
+        let mut freq: Vec<u64> = freq.iter().map(|&f| f as u64).chain([1]).collect();
+        let mut code_size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+        loop {
+            // 频率最小的两个符号，频率相同时取下标较大者
+            let smallest = |freq: &[u64], skip: usize| {
+                (0..257)
+                    .filter(|&i| freq[i] > 0 && i != skip)
+                    .min_by_key(|&i| (freq[i], std::cmp::Reverse(i)))
+            };
+            let Some(mut c1) = smallest(&freq, usize::MAX) else {
+                break;
+            };
+            let Some(mut c2) = smallest(&freq, c1) else {
+                break;
+            };
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+            code_size[c1] += 1;
+            while others[c1] != usize::MAX {
+                c1 = others[c1];
+                code_size[c1] += 1;
+            }
+            others[c1] = c2;
+            code_size[c2] += 1;
+            while others[c2] != usize::MAX {
+                c2 = others[c2];
+                code_size[c2] += 1;
+            }
+        }
+
+        let mut bits = [0u32; 33];
+        for &size in code_size.iter().filter(|&&s| s > 0) {
+            bits[size.min(32)] += 1;
+        }
+        // 将超过 16 位的码字移到较短的码长上
+        for i in (17..=32).rev() {
+            while bits[i] > 0 {
+                let mut j = i - 2;
+                while bits[j] == 0 {
+                    j -= 1;
+                }
+                bits[i] -= 2;
+                bits[i - 1] += 1;
+                bits[j + 1] += 2;
+                bits[j] -= 1;
+            }
+        }
+        // 去掉保留符号占用的最长码字
+        let mut i = 16;
+        while bits[i] == 0 {
+            i -= 1;
+        }
+        bits[i] -= 1;
+
+        let mut table = HuffmanTable::default();
+        for (count, &n) in table.counts.iter_mut().zip(&bits).skip(1) {
+            *count = n as u8;
+        }
+        for size in 1..=32 {
+            table
+                .symbols
+                .extend((0..256).filter(|&s| code_size[s] == size).map(|s| s as u8));
+        }
+        table
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Option<u16> {
+        if self.bits == 0 {
+            let byte = *self.data.get(self.pos)?;
+            if byte == 0xFF {
+                // 0xFF 之后必须是填充的 0x00，其他值为标记，说明数据不完整
+                if self.data.get(self.pos + 1) != Some(&0x00) {
+                    return None;
+                }
+                self.pos += 1;
+            }
+            self.pos += 1;
+            self.acc = byte as u32;
+            self.bits = 8;
+        }
+        self.bits -= 1;
+        Some(((self.acc >> self.bits) & 1) as u16)
+    }
+
+    fn bits(&mut self, n: u8) -> Option<u16> {
+        (0..n).try_fold(0, |v, _| Some((v << 1) | self.bit()?))
+    }
+
+    fn decode(&mut self, table: &HuffmanTable) -> Option<u8> {
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut k = 0usize;
+        for len in 1..=16 {
+            code = (code << 1) | self.bit()? as i32;
+            let count = table.counts[len] as i32;
+            if code - first < count {
+                return table.symbols.get(k + (code - first) as usize).copied();
+            }
+            k += count as usize;
+            first = (first + count) << 1;
+        }
+        None
+    }
+
+    // 在重启标记处丢弃剩余的位并跳过标记
+    fn restart(&mut self) -> Option<u8> {
+        self.bits = 0;
+        match self.data.get(self.pos..self.pos + 2)? {
+            &[0xFF, marker @ 0xD0..=0xD7] => {
+                self.pos += 2;
+                Some(marker - 0xD0)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u16, n: u8) {
+        for i in (0..n).rev() {
+            self.acc = (self.acc << 1) | ((value >> i) & 1) as u32;
+            self.bits += 1;
+            if self.bits == 8 {
+                self.out.push(self.acc as u8);
+                if self.acc == 0xFF {
+                    self.out.push(0x00);
+                }
+                self.acc = 0;
+                self.bits = 0;
+            }
+        }
+    }
+
+    // 用 1 填充到字节边界
+    fn flush(&mut self) {
+        if self.bits > 0 {
+            self.put(0x7F, (8 - self.bits) as u8);
+        }
+    }
+}
+
+// 帧中的分量：采样因子和块网格 (按 MCU 补齐后的宽度)
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    // 扫描头中指定的 DC/AC 表号
+    dc: usize,
+    ac: usize,
+    blocks_w: usize,
+    // 不含 MCU 补齐的实际块数，单分量扫描按此遍历
+    real_w: usize,
+    real_h: usize,
+}
+
+// 只支持单次扫描的基线/扩展顺序 Huffman 编码 (SOF0/SOF1)，其他编码方式返回 None
+struct Frame<'a> {
+    // SOS 之前除 DHT 以外的段 (含标记)
+    segments: Vec<&'a [u8]>,
+    components: Vec<Component>,
+    restart_interval: usize,
+    mcus_w: usize,
+    mcus_h: usize,
+    // 各分量的 DCT 系数，按 zigzag 顺序
+    coefficients: Vec<Vec<[i32; 64]>>,
+}
+
+// 附加位表示的有符号数
+fn extend(bits: u16, size: u8) -> i32 {
+    if size == 0 {
+        0
+    } else if (bits as i32) < 1 << (size - 1) {
+        bits as i32 - (1 << size) + 1
+    } else {
+        bits as i32
+    }
+}
+
+// 有符号数的位数和附加位
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = (32 - value.unsigned_abs().leading_zeros()) as u8;
+    let bits = if value < 0 { value - 1 } else { value };
+    (size, (bits & ((1 << size) - 1)) as u16)
+}
+
+fn parse_jpeg(data: &[u8]) -> Option<Frame<'_>> {
+    // 表下标：DC 表为 0-3，AC 表为 4-7
+    let mut tables: [Option<HuffmanTable>; 8] = Default::default();
+    let mut segments = Vec::new();
+    let mut components = Vec::new();
+    let (mut width, mut height) = (0usize, 0usize);
+    let mut restart_interval = 0usize;
+    let mut pos = 2;
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    // 1. 读取 SOS 之前的段
+    let (scan, sos) = loop {
+        if data.get(pos) != Some(&0xFF) {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == 0xFF {
+            pos += 1;
+            continue;
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos..pos + 2 + len.max(2))?;
+        let body = &segment[4..];
+        pos += 2 + len;
+        match marker {
+            0xC0 | 0xC1 => {
+                let &[precision, h1, h0, w1, w0, count, ..] = body else {
+                    return None;
+                };
+                if precision != 8 {
+                    return None;
+                }
+                height = u16::from_be_bytes([h1, h0]) as usize;
+                width = u16::from_be_bytes([w1, w0]) as usize;
+                for c in body.get(6..6 + 3 * count as usize)?.chunks(3) {
+                    components.push(Component {
+                        id: c[0],
+                        h: (c[1] >> 4).max(1) as usize,
+                        v: (c[1] & 0x0F).max(1) as usize,
+                        dc: 0,
+                        ac: 4,
+                        blocks_w: 0,
+                        real_w: 0,
+                        real_h: 0,
+                    });
+                }
+            }
+            // 其他 SOF：渐进式、无损、算术编码等
+            0xC2..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => return None,
+            0xC4 => {
+                let mut rest = body;
+                while !rest.is_empty() {
+                    let (class, id) = (rest[0] >> 4, rest[0] & 0x0F);
+                    if class > 1 || id > 3 {
+                        return None;
+                    }
+                    let mut table = HuffmanTable::default();
+                    table.counts[1..].copy_from_slice(rest.get(1..17)?);
+                    let total = table.counts.iter().map(|&c| c as usize).sum::<usize>();
+                    table.symbols = rest.get(17..17 + total)?.to_vec();
+                    tables[class as usize * 4 + id as usize] = Some(table);
+                    rest = &rest[17 + total..];
+                }
+                continue;
+            }
+            0xDD => {
+                let &[r1, r0, ..] = body else {
+                    return None;
+                };
+                restart_interval = u16::from_be_bytes([r1, r0]) as usize;
+            }
+            0xDA => break (pos, body),
+            _ => {}
+        }
+        segments.push(segment);
+    };
+    if components.is_empty() || width == 0 || height == 0 {
+        return None;
+    }
+
+    // 2. 扫描头：必须一次包含全部分量，频谱范围为完整的 0-63
+    let count = *sos.first()? as usize;
+    if count != components.len() || sos.get(1 + 2 * count..) != Some(&[0, 63, 0]) {
+        return None;
+    }
+    for c in sos.get(1..1 + 2 * count)?.chunks(2) {
+        let comp = components.iter_mut().find(|comp| comp.id == c[0])?;
+        comp.dc = (c[1] >> 4) as usize;
+        comp.ac = 4 + (c[1] & 0x0F) as usize;
+    }
+    let h_max = components.iter().map(|c| c.h).max()?;
+    let v_max = components.iter().map(|c| c.v).max()?;
+    for comp in &mut components {
+        comp.real_w = (width * comp.h).div_ceil(h_max).div_ceil(8);
+        comp.real_h = (height * comp.v).div_ceil(v_max).div_ceil(8);
+    }
+    // 单分量时 MCU 就是一个块；多分量交错时每个 MCU 包含各分量的 h×v 个块
+    let (mcus_w, mcus_h) = if components.len() == 1 {
+        components[0].h = 1;
+        components[0].v = 1;
+        (components[0].real_w, components[0].real_h)
+    } else {
+        (width.div_ceil(8 * h_max), height.div_ceil(8 * v_max))
+    };
+    for comp in &mut components {
+        comp.blocks_w = mcus_w * comp.h;
+    }
+
+    // 3. 熵解码出所有块的系数
+    let mut coefficients: Vec<Vec<[i32; 64]>> = components
+        .iter()
+        .map(|c| vec![[0; 64]; c.blocks_w * mcus_h * c.v])
+        .collect();
+    let mut reader = BitReader {
+        data: &data[scan..],
+        pos: 0,
+        acc: 0,
+        bits: 0,
+    };
+    let mut predictions = vec![0; components.len()];
+    for mcu in 0..mcus_w * mcus_h {
+        if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+            reader.restart()?;
+            predictions.fill(0);
+        }
+        let (mx, my) = (mcu % mcus_w, mcu / mcus_w);
+        for (index, comp) in components.iter().enumerate() {
+            let (dc_table, ac_table) = (tables[comp.dc].as_ref()?, tables[comp.ac].as_ref()?);
+            for by in 0..comp.v {
+                for bx in 0..comp.h {
+                    let block = &mut coefficients[index]
+                        [(my * comp.v + by) * comp.blocks_w + mx * comp.h + bx];
+                    let size = reader.decode(dc_table)?;
+                    if size > 11 {
+                        return None;
+                    }
+                    predictions[index] += extend(reader.bits(size)?, size);
+                    block[0] = predictions[index];
+
+                    let mut k = 1;
+                    while k < 64 {
+                        let symbol = reader.decode(ac_table)?;
+                        let (run, size) = ((symbol >> 4) as usize, symbol & 0x0F);
+                        if size == 0 {
+                            if run != 15 {
+                                break;
+                            }
+                            k += 16;
+                            continue;
+                        }
+                        k += run;
+                        if k > 63 || size > 10 {
+                            return None;
+                        }
+                        block[k] = extend(reader.bits(size)?, size);
+                        k += 1;
+                    }
+                }
+            }
+        }
+    }
+    // 扫描数据之后应紧接着 EOI；之后还有其他扫描时不处理
+    let rest = scan + reader.pos;
+    if data.get(rest..rest + 2) != Some(&[0xFF, 0xD9]) {
+        return None;
+    }
+
+    Some(Frame {
+        segments,
+        components,
+        restart_interval,
+        mcus_w,
+        mcus_h,
+        coefficients,
+    })
+}
+
+// 编码输出：先以统计频率的方式运行一遍生成最优表，再以写入的方式运行一遍
+trait Sink {
+    fn emit(&mut self, table: usize, symbol: u8, bits: u16, size: u8);
+    fn restart(&mut self, n: usize);
+}
+
+struct Counter([[u32; 256]; 8]);
+
+impl Sink for Counter {
+    fn emit(&mut self, table: usize, symbol: u8, _: u16, _: u8) {
+        self.0[table][symbol as usize] += 1;
+    }
+
+    fn restart(&mut self, _: usize) {}
+}
+
+struct Encoder {
+    codes: Vec<[(u8, u16); 256]>,
+    writer: BitWriter,
+}
+
+impl Sink for Encoder {
+    fn emit(&mut self, table: usize, symbol: u8, bits: u16, size: u8) {
+        let (len, code) = self.codes[table][symbol as usize];
+        self.writer.put(code, len);
+        self.writer.put(bits, size);
+    }
+
+    fn restart(&mut self, n: usize) {
+        self.writer.flush();
+        self.writer
+            .out
+            .extend_from_slice(&[0xFF, 0xD0 + (n % 8) as u8]);
+    }
+}
+
+// 一次扫描：包含的分量 (下标) 和频谱范围 Ss..=Se，不使用逐次逼近
+struct Scan {
+    components: Vec<usize>,
+    start: usize,
+    end: usize,
+}
+
+// 按扫描顺序遍历时的事件
+enum Visit<'a> {
+    // 第 n 个重启标记
+    Restart(usize),
+    // 分量下标和块的系数
+    Block(usize, &'a [i32; 64]),
+}
+
+// 输出渐进式 AC 扫描中累计的 EOB 游程
+fn flush_eob_run(sink: &mut impl Sink, table: usize, eob_run: &mut u16) {
+    if *eob_run > 0 {
+        let size = (15 - eob_run.leading_zeros()) as u8;
+        sink.emit(table, size << 4, *eob_run, size);
+        *eob_run = 0;
+    }
+}
+
+impl Frame<'_> {
+    // 按扫描的遍历顺序访问每个块，restart_interval 为 0 时不插入重启标记
+    fn for_each_block(&self, scan: &Scan, restart_interval: usize, mut f: impl FnMut(Visit)) {
+        if let [index] = scan.components[..] {
+            // 单分量扫描不交错，只遍历实际的块
+            let comp = &self.components[index];
+            for (n, (y, x)) in (0..comp.real_h)
+                .flat_map(|y| (0..comp.real_w).map(move |x| (y, x)))
+                .enumerate()
+            {
+                if restart_interval > 0 && n > 0 && n % restart_interval == 0 {
+                    f(Visit::Restart(n / restart_interval - 1));
+                }
+                f(Visit::Block(
+                    index,
+                    &self.coefficients[index][y * comp.blocks_w + x],
+                ));
+            }
+            return;
+        }
+        for mcu in 0..self.mcus_w * self.mcus_h {
+            if restart_interval > 0 && mcu > 0 && mcu % restart_interval == 0 {
+                f(Visit::Restart(mcu / restart_interval - 1));
+            }
+            let (mx, my) = (mcu % self.mcus_w, mcu / self.mcus_w);
+            for &index in &scan.components {
+                let comp = &self.components[index];
+                for by in 0..comp.v {
+                    for bx in 0..comp.h {
+                        let block = (my * comp.v + by) * comp.blocks_w + mx * comp.h + bx;
+                        f(Visit::Block(index, &self.coefficients[index][block]));
+                    }
+                }
+            }
+        }
+    }
+
+    // 编码一次扫描的数据
+    fn encode_scan(&self, scan: &Scan, restart_interval: usize, sink: &mut impl Sink) {
+        let mut predictions = vec![0; self.components.len()];
+        let progressive = scan.start > 0 || scan.end < 63;
+        // 渐进式 AC 扫描中连续以 EOB 结束的块数及所用的表
+        let (mut eob_run, mut eob_table) = (0u16, 0);
+        self.for_each_block(scan, restart_interval, |visit| {
+            let (index, block) = match visit {
+                Visit::Restart(n) => {
+                    predictions.fill(0);
+                    sink.restart(n);
+                    return;
+                }
+                Visit::Block(index, block) => (index, block),
+            };
+            let comp = &self.components[index];
+            if scan.start == 0 {
+                let (size, bits) = magnitude(block[0] - predictions[index]);
+                predictions[index] = block[0];
+                sink.emit(comp.dc, size, bits, size);
+            }
+            if scan.end == 0 {
+                return;
+            }
+            let mut run = 0;
+            for &value in &block[scan.start.max(1)..=scan.end] {
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                flush_eob_run(sink, eob_table, &mut eob_run);
+                while run > 15 {
+                    sink.emit(comp.ac, 0xF0, 0, 0);
+                    run -= 16;
+                }
+                let (size, bits) = magnitude(value);
+                sink.emit(comp.ac, ((run << 4) as u8) | size, bits, size);
+                run = 0;
+            }
+            if run > 0 {
+                if progressive {
+                    eob_run += 1;
+                    eob_table = comp.ac;
+                    if eob_run == 0x7FFF {
+                        flush_eob_run(sink, eob_table, &mut eob_run);
+                    }
+                } else {
+                    sink.emit(comp.ac, 0x00, 0, 0);
+                }
+            }
+        });
+        flush_eob_run(sink, eob_table, &mut eob_run);
+    }
+
+    // 为一次扫描生成最优 Huffman 表并编码，写入 DHT、SOS 和扫描数据
+    fn write_scan(&self, out: &mut Vec<u8>, scan: &Scan, restart_interval: usize, sos: &[u8]) {
+        let mut counter = Counter([[0; 256]; 8]);
+        self.encode_scan(scan, restart_interval, &mut counter);
+        let tables: Vec<Option<HuffmanTable>> = counter
+            .0
+            .iter()
+            .map(|f| f.iter().any(|&n| n > 0).then(|| HuffmanTable::optimal(f)))
+            .collect();
+
+        let mut dht = Vec::new();
+        for (index, table) in tables.iter().enumerate() {
+            if let Some(table) = table {
+                dht.push((((index / 4) << 4) | (index % 4)) as u8);
+                dht.extend_from_slice(&table.counts[1..]);
+                dht.extend_from_slice(&table.symbols);
+            }
+        }
+        out.extend_from_slice(&[0xFF, 0xC4]);
+        out.extend_from_slice(&(dht.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(&dht);
+        out.extend_from_slice(&[0xFF, 0xDA]);
+        out.extend_from_slice(&(sos.len() as u16 + 2).to_be_bytes());
+        out.extend_from_slice(sos);
+
+        let mut encoder = Encoder {
+            codes: tables
+                .iter()
+                .map(|t| t.as_ref().map(HuffmanTable::codes).unwrap_or([(0, 0); 256]))
+                .collect(),
+            writer: BitWriter {
+                out: std::mem::take(out),
+                acc: 0,
+                bits: 0,
+            },
+        };
+        self.encode_scan(scan, restart_interval, &mut encoder);
+        encoder.writer.flush();
+        *out = encoder.writer.out;
+    }
+
+    // 扫描头：分量及其表号、频谱范围
+    fn sos(&self, scan: &Scan) -> Vec<u8> {
+        let mut sos = vec![scan.components.len() as u8];
+        for &index in &scan.components {
+            let comp = &self.components[index];
+            let ac = if scan.end == 0 { 0 } else { comp.ac - 4 };
+            sos.extend_from_slice(&[comp.id, ((comp.dc << 4) | ac) as u8]);
+        }
+        sos.extend_from_slice(&[scan.start as u8, scan.end as u8, 0]);
+        sos
+    }
+}
+
+// JPEG：熵解码出 DCT 系数后按实际符号频率生成最优表重新编码，系数完全不变
+fn optimize_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let frame = parse_jpeg(data)?;
+    let mut out = vec![0xFF, 0xD8];
+    for segment in &frame.segments {
+        out.extend_from_slice(segment);
+    }
+    let scan = Scan {
+        components: (0..frame.components.len()).collect(),
+        start: 0,
+        end: 63,
+    };
+    frame.write_scan(&mut out, &scan, frame.restart_interval, &frame.sos(&scan));
+    out.extend_from_slice(&[0xFF, 0xD9]);
+    Some(out)
+}
+
+// 将基线 JPEG 无损转换为渐进式 (相当于 jpegtran -progressive -optimize)，慢速网络下可逐步显示
+// 扫描顺序：全部分量的 DC、亮度低频 AC、色度 AC、亮度高频 AC；已是渐进式或无法解析时返回 None
+pub fn progressive_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let frame = parse_jpeg(data)?;
+    let mut out = vec![0xFF, 0xD8];
+    for segment in &frame.segments {
+        match segment[1] {
+            // 渐进式编码不使用重启间隔
+            0xDD => {}
+            0xC0 | 0xC1 => {
+                out.extend_from_slice(&[0xFF, 0xC2]);
+                out.extend_from_slice(&segment[2..]);
+            }
+            _ => out.extend_from_slice(segment),
+        }
+    }
+
+    let scan = |components: Vec<usize>, start, end| Scan {
+        components,
+        start,
+        end,
+    };
+    let mut scans = vec![scan((0..frame.components.len()).collect(), 0, 0)];
+    scans.push(scan(vec![0], 1, 5));
+    scans.extend((1..frame.components.len()).map(|index| scan(vec![index], 1, 63)));
+    scans.push(scan(vec![0], 6, 63));
+    for scan in &scans {
+        frame.write_scan(&mut out, scan, 0, &frame.sos(scan));
+    }
+    out.extend_from_slice(&[0xFF, 0xD9]);
+
+    (decode_jpeg(&out)? == decode_jpeg(data)?).then_some(out)
+}
+
+// PNG 块：(类型, 数据)
+fn png_chunks(data: &[u8]) -> Option<Vec<(&[u8], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(pos + 4..pos + 8)?;
+        chunks.push((kind, data.get(pos + 8..pos + 8 + len)?));
+        pos += 12 + len;
+    }
+    Some(chunks)
+}
+
+fn write_png_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32fast::hash(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// 对一行选择使绝对值和最小的过滤方式 (None/Sub/Up/Average/Paeth)，返回带过滤类型字节的行
+fn filter_row(row: &[u8], prev: &[u8], bpp: usize) -> Vec<u8> {
+    let paeth = |a: u8, b: u8, c: u8| {
+        let p = a as i16 + b as i16 - c as i16;
+        let (pa, pb, pc) = (
+            (p - a as i16).abs(),
+            (p - b as i16).abs(),
+            (p - c as i16).abs(),
+        );
+        if pa <= pb && pa <= pc {
+            a
+        } else if pb <= pc {
+            b
+        } else {
+            c
+        }
+    };
+    (0u8..5)
+        .map(|filter| {
+            let mut out = Vec::with_capacity(row.len() + 1);
+            out.push(filter);
+            for i in 0..row.len() {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let b = prev[i];
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                out.push(row[i].wrapping_sub(predicted));
+            }
+            out
+        })
+        .min_by_key(|out| {
+            out[1..]
+                .iter()
+                .map(|&v| (v as i8).unsigned_abs() as u32)
+                .sum::<u32>()
+        })
+        .unwrap_or_default()
+}
+
+// 将 PNG 重新编码为 Adam7 隔行扫描，慢速网络下可先显示低分辨率的整图
+// 其他块原样保留；已是隔行、APNG 或位深低于 8 时返回 None
+pub fn interlace_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().ok()?;
+    let info = reader.info();
+    if info.interlaced || info.animation_control.is_some() || (info.bit_depth as u8) < 8 {
+        return None;
+    }
+    let (width, height) = (info.width as usize, info.height as usize);
+    let bpp = info.color_type.samples() * info.bit_depth as usize / 8;
+    let mut pixels = vec![0; reader.output_buffer_size()?];
+    reader.next_frame(&mut pixels).ok()?;
+
+    // 7 个子图：(起始列, 起始行, 列间隔, 行间隔)
+    const PASSES: [(usize, usize, usize, usize); 7] = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+    let mut raw = Vec::with_capacity(pixels.len() + height * 2);
+    for (x0, y0, dx, dy) in PASSES {
+        if x0 >= width || y0 >= height {
+            continue;
+        }
+        let mut prev = vec![0; (width - x0).div_ceil(dx) * bpp];
+        for y in (y0..height).step_by(dy) {
+            let line = &pixels[y * width * bpp..(y + 1) * width * bpp];
+            let row: Vec<u8> = (x0..width)
+                .step_by(dx)
+                .flat_map(|x| &line[x * bpp..(x + 1) * bpp])
+                .copied()
+                .collect();
+            raw.extend(filter_row(&row, &prev, bpp));
+            prev = row;
+        }
+    }
+    let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    zlib.write_all(&raw).ok()?;
+    let idat = zlib.finish().ok()?;
+
+    let mut out = b"\x89PNG\r\n\x1a\n".to_vec();
+    let mut idat = Some(idat);
+    for (kind, body) in png_chunks(data)? {
+        match kind {
+            b"IHDR" => {
+                let mut ihdr = body.to_vec();
+                *ihdr.get_mut(12)? = 1;
+                write_png_chunk(&mut out, kind, &ihdr);
+            }
+            // 原有的 IDAT 在第一个的位置替换为新数据
+            b"IDAT" => {
+                if let Some(idat) = idat.take() {
+                    write_png_chunk(&mut out, kind, &idat);
+                }
+            }
+            _ => write_png_chunk(&mut out, kind, body),
+        }
+    }
+    Some(out)
+}
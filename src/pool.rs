@@ -0,0 +1,113 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use tokio::sync::Semaphore;
+
+// 图片处理池：解码/编码等 CPU 密集的工作最多同时运行 workers 个
+// 请求中的处理在工作线程都忙时排队等待，排队数达到上限时直接拒绝 (Busy)，避免突发上传压垮 CPU
+// 后台任务 (缩略图队列、上传后处理) 只等待空闲的工作线程，不受排队上限限制
+pub struct ProcessingPool {
+    workers: Arc<Semaphore>,
+    size: usize,
+    max_queued: usize,
+    queued: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub enum PoolError {
+    // 排队数已达上限
+    Busy,
+    // 任务 panic
+    Failed,
+}
+
+impl std::fmt::Display for PoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolError::Busy => write!(f, "processing pool is busy"),
+            PoolError::Failed => write!(f, "processing task failed"),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
+// 排队计数的守卫，等待被取消 (请求断开) 时同样减一
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl ProcessingPool {
+    pub fn new(size: usize, max_queued: usize) -> Self {
+        let size = size.max(1);
+        Self {
+            workers: Arc::new(Semaphore::new(size)),
+            size,
+            max_queued,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    // 在池中执行 f；排队数达到上限时返回 Busy
+    pub async fn run<F, T>(&self, f: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.execute(f, true).await
+    }
+
+    // 后台任务使用：等待空闲的工作线程，不会被拒绝
+    pub async fn run_background<F, T>(&self, f: F) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.execute(f, false).await
+    }
+
+    async fn execute<F, T>(&self, f: F, bounded: bool) -> Result<T, PoolError>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = match self.workers.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                if self.queued.fetch_add(1, Ordering::Relaxed) >= self.max_queued && bounded {
+                    self.queued.fetch_sub(1, Ordering::Relaxed);
+                    return Err(PoolError::Busy);
+                }
+                let _queued = Queued(&self.queued);
+                self.workers
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| PoolError::Failed)?
+            }
+        };
+        // permit 随任务移动，请求断开后任务仍在运行时继续占用工作线程
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            f()
+        })
+        .await
+        .map_err(|_| PoolError::Failed)
+    }
+
+    // 正在运行和排队的任务数
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "workers": self.size,
+            "active": self.size - self.workers.available_permits(),
+            "queued": self.queued.load(Ordering::Relaxed),
+            "max_queued": self.max_queued,
+        })
+    }
+}
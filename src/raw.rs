@@ -0,0 +1,195 @@
+// 相机 RAW (CR2/NEF/ARW) 的识别和内嵌 JPEG 预览图提取
+// 这些格式都基于 TIFF：CR2 在 TIFF 头之后带有 "CR" 标记，NEF/ARW 通过 IFD0 的 Make 识别
+// 预览图位于 IFD0、IFD 链或 SubIFD 中，取其中最大的一张有损 JPEG
+use std::{collections::HashSet, io::Cursor};
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_MAKE: u16 = 0x010F;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+// 最多遍历的 IFD 数，避免构造的循环引用
+const MAX_IFDS: usize = 32;
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+// IFD 中的一项；value_pos 为 4 字节值字段的位置
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value_pos: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, pos: usize) -> Option<u16> {
+        let bytes = [*self.data.get(pos)?, *self.data.get(pos + 1)?];
+        Some(match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, pos: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn first_ifd(&self) -> Option<usize> {
+        self.u32(4).map(|offset| offset as usize)
+    }
+
+    // 读取 IFD 的所有项和下一个 IFD 的位置 (0 表示没有)
+    fn ifd(&self, pos: usize) -> Option<(Vec<Entry>, usize)> {
+        let count = self.u16(pos)? as usize;
+        let entries = (0..count)
+            .map(|i| {
+                let at = pos + 2 + i * 12;
+                Some(Entry {
+                    tag: self.u16(at)?,
+                    kind: self.u16(at + 2)?,
+                    count: self.u32(at + 4)?,
+                    value_pos: at + 8,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let next = self.u32(pos + 2 + count * 12)? as usize;
+        Some((entries, next))
+    }
+
+    // SHORT/LONG/IFD 类型的第 index 个值；总长度不超过 4 字节时直接存放在值字段中
+    fn value(&self, entry: &Entry, index: u32) -> Option<u32> {
+        let size = match entry.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return None,
+        };
+        if index >= entry.count {
+            return None;
+        }
+        let base = match entry.count as usize * size {
+            0..=4 => entry.value_pos,
+            _ => self.u32(entry.value_pos)? as usize,
+        };
+        let pos = base + index as usize * size;
+        match size {
+            2 => self.u16(pos).map(u32::from),
+            _ => self.u32(pos),
+        }
+    }
+
+    // ASCII 类型的值，去掉末尾的 NUL
+    fn ascii(&self, entry: &Entry) -> Option<&'a str> {
+        if entry.kind != 2 {
+            return None;
+        }
+        let len = entry.count as usize;
+        let pos = match len {
+            0..=4 => entry.value_pos,
+            _ => self.u32(entry.value_pos)? as usize,
+        };
+        let bytes = self.data.get(pos..pos + len)?;
+        std::str::from_utf8(bytes)
+            .ok()
+            .map(|s| s.trim_end_matches('\0').trim())
+    }
+}
+
+// 识别 RAW 格式，返回对应的 MIME 类型；不是支持的 RAW 时返回 None
+pub fn raw_mime(data: &[u8]) -> Option<&'static str> {
+    let tiff = Tiff::new(data)?;
+    if data.get(8..10) == Some(b"CR") {
+        return Some("image/x-canon-cr2");
+    }
+    let (entries, _) = tiff.ifd(tiff.first_ifd()?)?;
+    let make = entries
+        .iter()
+        .find(|e| e.tag == TAG_MAKE)
+        .and_then(|e| tiff.ascii(e))?
+        .to_ascii_uppercase();
+    if make.starts_with("NIKON") {
+        Some("image/x-nikon-nef")
+    } else if make.starts_with("SONY") {
+        Some("image/x-sony-arw")
+    } else {
+        None
+    }
+}
+
+// RAW 内嵌的最大一张 JPEG 预览图；不是 RAW 或没有可用的预览时返回 None
+pub fn raw_preview(data: &[u8]) -> Option<&[u8]> {
+    raw_mime(data)?;
+    let tiff = Tiff::new(data)?;
+
+    let mut queue = vec![tiff.first_ifd()?];
+    let mut visited = HashSet::new();
+    let mut best: Option<&[u8]> = None;
+    while let Some(pos) = queue.pop() {
+        if pos == 0 || visited.len() >= MAX_IFDS || !visited.insert(pos) {
+            continue;
+        }
+        let Some((entries, next)) = tiff.ifd(pos) else {
+            continue;
+        };
+        queue.push(next);
+        let find = |tag| entries.iter().find(|e| e.tag == tag);
+        if let Some(sub) = find(TAG_SUB_IFDS) {
+            queue.extend((0..sub.count).filter_map(|i| tiff.value(sub, i).map(|v| v as usize)));
+        }
+
+        // JPEGInterchangeFormat，或压缩方式为 JPEG (6) 的单条带数据
+        let candidate = match (find(TAG_JPEG_OFFSET), find(TAG_JPEG_LENGTH)) {
+            (Some(offset), Some(length)) => Some((offset, length)),
+            _ => find(TAG_COMPRESSION)
+                .and_then(|c| tiff.value(c, 0))
+                .filter(|&c| c == 6)
+                .and(find(TAG_STRIP_OFFSETS).zip(find(TAG_STRIP_BYTE_COUNTS)))
+                .filter(|(offsets, _)| offsets.count == 1),
+        };
+        let jpeg = candidate
+            .and_then(|(offset, length)| Some((tiff.value(offset, 0)?, tiff.value(length, 0)?)))
+            .and_then(|(offset, length)| {
+                data.get(offset as usize..(offset as usize).checked_add(length as usize)?)
+            })
+            .filter(|jpeg| is_lossy_jpeg(jpeg));
+        if let Some(jpeg) = jpeg
+            && best.is_none_or(|best| jpeg.len() > best.len())
+        {
+            best = Some(jpeg);
+        }
+    }
+    best
+}
+
+// CR2 的原始数据同样以 JPEG 形式存放，但使用的是无法直接显示的无损 JPEG
+fn is_lossy_jpeg(data: &[u8]) -> bool {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return false;
+    }
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(data));
+    decoder.read_info().is_ok()
+        && decoder
+            .info()
+            .is_some_and(|info| info.coding_process != jpeg_decoder::CodingProcess::Lossless)
+}
@@ -0,0 +1,160 @@
+// 定期摘要报告：每个 report_interval 周期汇总一次上传、删除、下载流量、下载次数最多的图片和存储增长，
+// 写入日志；配置了 report_webhook 时同时以 JSON POST 到该地址
+//
+// 计数来自内存中的运行时统计 (见 stats)，服务重启后的第一份报告只覆盖重启之后的时间
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, atomic::Ordering},
+    time::Duration,
+};
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppState;
+
+// 报告中列出的下载次数最多的图片数量
+const TOP_IMAGES: usize = 10;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(30))
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("failed to build http client")
+});
+
+// 报告周期，从服务启动时开始计算
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportInterval {
+    Daily,
+    Weekly,
+}
+
+impl ReportInterval {
+    pub fn period(self) -> Duration {
+        match self {
+            ReportInterval::Daily => Duration::from_secs(86400),
+            ReportInterval::Weekly => Duration::from_secs(7 * 86400),
+        }
+    }
+}
+
+// 某一时刻的累计计数和存储用量，两次快照之差即为一个周期的数据
+pub struct Snapshot {
+    at: chrono::DateTime<chrono::Utc>,
+    uploads: u64,
+    upload_bytes: u64,
+    deletes: u64,
+    download_bytes: u64,
+    images: usize,
+    blob_bytes: u64,
+}
+
+impl Snapshot {
+    pub async fn take(state: &AppState) -> Self {
+        let config = state.read_config("report").await;
+        let (_, blob_bytes) = config.blob_usage();
+        let stats = &state.stats;
+        Self {
+            at: chrono::Utc::now(),
+            uploads: stats.uploads.load(Ordering::Relaxed),
+            upload_bytes: stats.upload_bytes.load(Ordering::Relaxed),
+            deletes: stats.deletes.load(Ordering::Relaxed),
+            download_bytes: stats.download_bytes.load(Ordering::Relaxed),
+            images: config.images().len(),
+            blob_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TopImage {
+    pub name: String,
+    pub downloads: u64,
+}
+
+// 一个周期的摘要，同时也是 webhook 的请求体
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub from: chrono::DateTime<chrono::Utc>,
+    pub to: chrono::DateTime<chrono::Utc>,
+    pub uploads: u64,
+    pub upload_bytes: u64,
+    pub deletes: u64,
+    pub download_bytes: u64,
+    pub top_images: Vec<TopImage>,
+    // 周期结束时的图片数量和不重复 blob 的总大小，以及与周期开始时相比的变化
+    pub images: usize,
+    pub images_growth: i64,
+    pub blob_bytes: u64,
+    pub storage_growth: i64,
+}
+
+// 由周期首尾的快照和周期内各图片的下载次数生成摘要
+pub fn summarize(from: &Snapshot, to: &Snapshot, downloads: HashMap<String, u64>) -> Summary {
+    let mut top_images: Vec<_> = downloads
+        .into_iter()
+        .map(|(name, downloads)| TopImage { name, downloads })
+        .collect();
+    top_images.sort_by(|a, b| b.downloads.cmp(&a.downloads).then(a.name.cmp(&b.name)));
+    top_images.truncate(TOP_IMAGES);
+    Summary {
+        from: from.at,
+        to: to.at,
+        uploads: to.uploads - from.uploads,
+        upload_bytes: to.upload_bytes - from.upload_bytes,
+        deletes: to.deletes - from.deletes,
+        download_bytes: to.download_bytes - from.download_bytes,
+        top_images,
+        images: to.images,
+        images_growth: to.images as i64 - from.images as i64,
+        blob_bytes: to.blob_bytes,
+        storage_growth: to.blob_bytes as i64 - from.blob_bytes as i64,
+    }
+}
+
+// 将摘要写入日志
+pub fn log(summary: &Summary) {
+    let top: Vec<_> = summary
+        .top_images
+        .iter()
+        .map(|t| format!("{} ({})", t.name, t.downloads))
+        .collect();
+    info!(
+        "Summary {} - {}: {} uploads ({} bytes), {} deletes, {} bytes served, {} images ({:+}), {} bytes stored ({:+}), top: [{}]",
+        summary.from.format("%Y-%m-%d %H:%M"),
+        summary.to.format("%Y-%m-%d %H:%M"),
+        summary.uploads,
+        summary.upload_bytes,
+        summary.deletes,
+        summary.download_bytes,
+        summary.images,
+        summary.images_growth,
+        summary.blob_bytes,
+        summary.storage_growth,
+        top.join(", ")
+    );
+}
+
+// 以 JSON POST 摘要到 webhook
+pub async fn send(url: &str, summary: &Summary) -> anyhow::Result<()> {
+    let resp = CLIENT
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(summary)?)
+        .send()
+        .await?;
+    anyhow::ensure!(
+        resp.status().is_success(),
+        "webhook returned {}",
+        resp.status()
+    );
+    Ok(())
+}
@@ -0,0 +1,203 @@
+// 错误上报：panic 和返回 5xx 的请求以 Sentry 事件的格式发送到 sentry_dsn，
+// 兼容 Sentry 及 GlitchTip 等实现了 envelope 接口的服务
+//
+// DSN 形如 https://<public_key>@<host>/<project_id>，事件 POST 到 https://<host>/api/<project_id>/envelope/
+// 发送在后台进行，不阻塞请求；失败时只记录日志
+use std::{
+    net::SocketAddr,
+    sync::{
+        LazyLock, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use log::{info, warn};
+
+use crate::logging::AccessEntry;
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(30))
+        .user_agent(USER_AGENT)
+        .build()
+        .expect("failed to build http client")
+});
+
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+// 由 init 设置；未设置时不上报
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+// 正在发送的事件数，退出前由 flush 等待
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+struct Reporter {
+    dsn: String,
+    url: reqwest::Url,
+    auth: String,
+    // panic 可能发生在运行时之外的线程 (例如图片处理池)，发送任务统一交给服务的运行时
+    runtime: tokio::runtime::Handle,
+}
+
+impl Reporter {
+    fn from_dsn(dsn: &str) -> anyhow::Result<Self> {
+        let parsed = reqwest::Url::parse(dsn)?;
+        anyhow::ensure!(!parsed.username().is_empty(), "missing public key");
+        let path = parsed.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').unwrap_or(("", path));
+        anyhow::ensure!(!project.is_empty(), "missing project id");
+
+        let mut url = parsed.clone();
+        url.set_username("").ok();
+        url.set_password(None).ok();
+        url.set_path(&format!("{}/api/{}/envelope/", prefix, project));
+        let mut auth = format!(
+            "Sentry sentry_version=7, sentry_client={}, sentry_key={}",
+            USER_AGENT,
+            parsed.username()
+        );
+        if let Some(secret) = parsed.password() {
+            auth.push_str(&format!(", sentry_secret={}", secret));
+        }
+        Ok(Self {
+            dsn: dsn.to_string(),
+            url,
+            auth,
+            runtime: tokio::runtime::Handle::current(),
+        })
+    }
+
+    // 补全公共字段后在后台发送
+    fn capture(&self, mut event: serde_json::Value) {
+        let event_id = uuid::Uuid::new_v4().simple().to_string();
+        event["event_id"] = event_id.clone().into();
+        event["timestamp"] = chrono::Utc::now().to_rfc3339().into();
+        event["platform"] = "other".into();
+        event["release"] = USER_AGENT.replace('/', "@").into();
+        let header = serde_json::json!({ "event_id": event_id, "dsn": self.dsn });
+        let body = format!("{}\n{{\"type\":\"event\"}}\n{}\n", header, event);
+
+        let request = CLIENT
+            .post(self.url.clone())
+            .header("x-sentry-auth", &self.auth)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                "application/x-sentry-envelope",
+            )
+            .body(body);
+        PENDING.fetch_add(1, Ordering::Relaxed);
+        self.runtime.spawn(async move {
+            match request.send().await {
+                Ok(resp) if !resp.status().is_success() => {
+                    warn!("Error report rejected: {}", resp.status());
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to send error report: {}", e),
+            }
+            PENDING.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+// 开始上报；需要在 tokio 运行时中调用。DSN 无法解析时记录错误并不上报
+pub fn init(dsn: &str) {
+    let reporter = match Reporter::from_dsn(dsn) {
+        Ok(reporter) => reporter,
+        Err(e) => {
+            log::error!("Invalid sentry_dsn, error reporting is disabled: {}", e);
+            return;
+        }
+    };
+    info!("Reporting errors to {}", reporter.url);
+    if REPORTER.set(reporter).is_err() {
+        return;
+    }
+
+    // 保留默认的 panic 输出
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |panic| {
+        previous(panic);
+        capture_panic(panic);
+    }));
+}
+
+pub fn enabled() -> bool {
+    REPORTER.get().is_some()
+}
+
+fn capture_panic(panic: &std::panic::PanicHookInfo) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    let message = panic
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Box<dyn Any>".to_string());
+    let location = panic.location().map(|l| l.to_string());
+    let thread = std::thread::current()
+        .name()
+        .unwrap_or("<unnamed>")
+        .to_string();
+    let mut event = serde_json::json!({
+        "level": "fatal",
+        "exception": { "values": [{ "type": "panic", "value": message }] },
+        "tags": { "thread": thread },
+        "extra": {
+            "location": location,
+            "backtrace": std::backtrace::Backtrace::force_capture().to_string(),
+        },
+    });
+    // 处理请求时发生的 panic 附带请求 ID，便于对照日志
+    if let Some(id) = crate::logging::request_id() {
+        event["tags"]["request_id"] = id.into();
+    }
+    reporter.capture(event);
+}
+
+// 上报一个返回 5xx 的请求；message 为响应体 (handler 返回的错误说明)
+pub fn capture_request(entry: &AccessEntry, route: Option<&str>, message: &str) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    let route = route.unwrap_or("unmatched");
+    let mut headers = serde_json::Map::new();
+    if let Some(user_agent) = entry.user_agent {
+        headers.insert("User-Agent".into(), user_agent.into());
+    }
+    if let Some(referer) = entry.referer {
+        headers.insert("Referer".into(), referer.into());
+    }
+    let event = serde_json::json!({
+        "level": "error",
+        "message": {
+            "formatted": format!("{} {} {}: {}", entry.status, entry.method, route, message),
+        },
+        // 相同路由和状态码的错误归为一组
+        "fingerprint": [entry.method, route, entry.status.to_string()],
+        "request": {
+            "method": entry.method,
+            "url": entry.uri,
+            "headers": headers,
+        },
+        "user": { "ip_address": entry.addr.as_ref().map(SocketAddr::ip) },
+        "tags": {
+            "route": route,
+            "status": entry.status.to_string(),
+            "request_id": entry.request_id,
+        },
+        "extra": { "elapsed_ms": entry.elapsed.as_millis() as u64 },
+    });
+    reporter.capture(event);
+}
+
+// 等待后台发送中的事件完成，最多等待 timeout
+pub async fn flush(timeout: Duration) {
+    let deadline = tokio::time::Instant::now() + timeout;
+    while PENDING.load(Ordering::Relaxed) > 0 && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
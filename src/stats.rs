@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+// 请求耗时直方图的桶上限 (秒)
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// 运行时统计，只保存在内存中，重启后清零
+#[derive(Debug, Default)]
+pub struct Stats {
+    // 按 LRU 淘汰的格式副本数量及字节数
+    pub variant_evictions: AtomicU64,
+    pub variant_evicted_bytes: AtomicU64,
+    // 正在处理的请求数，关闭服务时等待其归零
+    pub in_flight: AtomicU64,
+    // 成功保存的上传文件数、字节数，以及其中内容已存在 (去重) 的文件数
+    pub uploads: AtomicU64,
+    pub upload_bytes: AtomicU64,
+    pub dedup_hits: AtomicU64,
+    // 下载响应的字节数 (按 Content-Length 统计)
+    pub download_bytes: AtomicU64,
+    // 删除的图片记录数
+    pub deletes: AtomicU64,
+    // 后台缩略图队列中等待处理的任务数
+    pub thumbnail_queue: AtomicU64,
+    // 服务启动时间
+    started: Started,
+    // 按 (方法, 路由) 统计的请求数和耗时
+    requests: Mutex<HashMap<(String, String), RouteStats>>,
+    // 上次摘要报告以来各图片 (按名称) 原图的下载次数，由 take_downloads 取出并清零
+    downloads: Mutex<HashMap<String, u64>>,
+    // 各调用方等待配置锁的时间
+    #[cfg(feature = "lock-metrics")]
+    lock_waits: Mutex<HashMap<&'static str, LockWait>>,
+}
+
+#[derive(Debug)]
+struct Started(Instant);
+
+impl Default for Started {
+    fn default() -> Self {
+        Self(Instant::now())
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteStats {
+    // 按状态码统计的请求数
+    statuses: HashMap<u16, u64>,
+    // 各桶 (不累计) 的请求数，最后一项为超过最大上限的请求
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    sum: Duration,
+    count: u64,
+}
+
+#[cfg(feature = "lock-metrics")]
+#[derive(Debug, Default, Clone, Copy)]
+struct LockWait {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Stats {
+    #[cfg(feature = "lock-metrics")]
+    pub fn record_lock_wait(&self, site: &'static str, wait: Duration) {
+        let mut waits = self.lock_waits.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = waits.entry(site).or_default();
+        entry.count += 1;
+        entry.total += wait;
+        entry.max = entry.max.max(wait);
+    }
+
+    // 记录一个已完成的请求；route 为匹配到的路由模板，未匹配时为 None
+    pub fn record_request(
+        &self,
+        method: &str,
+        route: Option<&str>,
+        status: u16,
+        elapsed: Duration,
+    ) {
+        let key = (method.to_string(), route.unwrap_or("unmatched").to_string());
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = requests.entry(key).or_default();
+        *entry.statuses.entry(status).or_default() += 1;
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&b| elapsed.as_secs_f64() <= b)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        entry.buckets[bucket] += 1;
+        entry.sum += elapsed;
+        entry.count += 1;
+    }
+
+    // 记录一次原图下载
+    pub fn record_download(&self, name: &str) {
+        let mut downloads = self.downloads.lock().unwrap_or_else(|e| e.into_inner());
+        match downloads.get_mut(name) {
+            Some(count) => *count += 1,
+            None => {
+                downloads.insert(name.to_string(), 1);
+            }
+        }
+    }
+
+    // 取出各图片的下载次数并清零
+    pub fn take_downloads(&self) -> HashMap<String, u64> {
+        std::mem::take(&mut *self.downloads.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    // 以 Prometheus 文本格式输出请求统计和计数器
+    pub fn write_prometheus(&self, out: &mut String) {
+        let counters = [
+            ("uploads_total", "Uploaded files stored", &self.uploads),
+            (
+                "upload_bytes_total",
+                "Bytes of uploaded files stored",
+                &self.upload_bytes,
+            ),
+            (
+                "dedup_hits_total",
+                "Uploads whose content was already stored",
+                &self.dedup_hits,
+            ),
+            (
+                "download_bytes_total",
+                "Bytes sent in download responses",
+                &self.download_bytes,
+            ),
+            ("deletes_total", "Image records deleted", &self.deletes),
+            (
+                "variant_evictions_total",
+                "Format variants evicted from the cache",
+                &self.variant_evictions,
+            ),
+            (
+                "variant_evicted_bytes_total",
+                "Bytes of evicted format variants",
+                &self.variant_evicted_bytes,
+            ),
+        ];
+        for (name, help, value) in counters {
+            write_metric(out, name, "counter", help, value.load(Ordering::Relaxed));
+        }
+        write_metric(
+            out,
+            "in_flight_requests",
+            "gauge",
+            "Requests currently being handled",
+            self.in_flight.load(Ordering::Relaxed),
+        );
+        write_metric(
+            out,
+            "thumbnail_queue_depth",
+            "gauge",
+            "Thumbnails waiting in the background queue",
+            self.thumbnail_queue.load(Ordering::Relaxed),
+        );
+
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        let mut keys: Vec<_> = requests.keys().collect();
+        keys.sort();
+        let _ = writeln!(out, "# HELP img_server_requests_total Requests handled");
+        let _ = writeln!(out, "# TYPE img_server_requests_total counter");
+        for key @ (method, route) in &keys {
+            let mut statuses: Vec<_> = requests[*key].statuses.iter().collect();
+            statuses.sort();
+            for (status, count) in statuses {
+                let _ = writeln!(
+                    out,
+                    "img_server_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                    method, route, status, count
+                );
+            }
+        }
+        let _ = writeln!(
+            out,
+            "# HELP img_server_request_duration_seconds Request latency"
+        );
+        let _ = writeln!(out, "# TYPE img_server_request_duration_seconds histogram");
+        for key @ (method, route) in &keys {
+            let stats = &requests[*key];
+            let labels = format!("method=\"{}\",route=\"{}\"", method, route);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "img_server_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "img_server_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, stats.count
+            );
+            let _ = writeln!(
+                out,
+                "img_server_request_duration_seconds_sum{{{}}} {}",
+                labels,
+                stats.sum.as_secs_f64()
+            );
+            let _ = writeln!(
+                out,
+                "img_server_request_duration_seconds_count{{{}}} {}",
+                labels, stats.count
+            );
+        }
+    }
+
+    // 标记一个请求开始处理，返回的守卫 drop 时计数减一
+    pub fn enter_request(&self) -> InFlightGuard<'_> {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(&self.in_flight)
+    }
+
+    // 服务已运行的时间
+    pub fn uptime(&self) -> Duration {
+        self.started.0.elapsed()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        #[allow(unused_mut)]
+        let mut json = serde_json::json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "uptime_secs": self.uptime().as_secs(),
+            "variant_evictions": self.variant_evictions.load(Ordering::Relaxed),
+            "variant_evicted_bytes": self.variant_evicted_bytes.load(Ordering::Relaxed),
+            "in_flight": self.in_flight.load(Ordering::Relaxed),
+            "uploads": self.uploads.load(Ordering::Relaxed),
+            "upload_bytes": self.upload_bytes.load(Ordering::Relaxed),
+            "dedup_hits": self.dedup_hits.load(Ordering::Relaxed),
+            "download_bytes": self.download_bytes.load(Ordering::Relaxed),
+            "deletes": self.deletes.load(Ordering::Relaxed),
+            "thumbnail_queue": self.thumbnail_queue.load(Ordering::Relaxed),
+        });
+        #[cfg(feature = "lock-metrics")]
+        {
+            let waits = self.lock_waits.lock().unwrap_or_else(|e| e.into_inner());
+            json["lock_wait"] = waits
+                .iter()
+                .map(|(site, w)| {
+                    let value = serde_json::json!({
+                        "count": w.count,
+                        "total_us": w.total.as_micros() as u64,
+                        "max_us": w.max.as_micros() as u64,
+                    });
+                    (site.to_string(), value)
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into();
+        }
+        json
+    }
+}
+
+// 输出一个不带标签的指标，名称加上 img_server_ 前缀
+pub fn write_metric(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: impl std::fmt::Display,
+) {
+    let _ = writeln!(out, "# HELP img_server_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE img_server_{} {}", name, kind);
+    let _ = writeln!(out, "img_server_{} {}", name, value);
+}
+
+pub struct InFlightGuard<'a>(&'a AtomicU64);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,633 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use axum::body::Bytes;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit,
+    aead::stream::{DecryptorBE32, EncryptorBE32},
+};
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+// 加密 blob 的文件格式：MAGIC | nonce 前缀 | 若干密文块
+// 每个明文块 CHUNK_SIZE 字节，加密后附带 TAG_LEN 字节认证标签，最后一块使用 STREAM 的
+// last 标记，防止截断
+// 未加密的 blob 直接保存明文；明文恰好以 MAGIC 开头时加上 nonce 全为 0 的文件头 (加密时不会使用该 nonce)，
+// 读取时据此与加密文件区分
+const MAGIC: &[u8; 8] = b"IMGENC01";
+const NONCE_LEN: usize = 7;
+const HEADER_LEN: usize = MAGIC.len() + NONCE_LEN;
+const PLAIN_NONCE: [u8; NONCE_LEN] = [0; NONCE_LEN];
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+// 下载时每次读取的字节数，以及写入 blob (上传、拉取上游) 的缓冲区大小
+// 启动时由 configure_buffers 按配置设置
+static READ_BUFFER: AtomicUsize = AtomicUsize::new(64 * 1024);
+static WRITE_BUFFER: AtomicUsize = AtomicUsize::new(256 * 1024);
+
+pub fn configure_buffers(read: usize, write: usize) {
+    READ_BUFFER.store(read.max(4096), Ordering::Relaxed);
+    WRITE_BUFFER.store(write.max(4096), Ordering::Relaxed);
+}
+
+pub fn read_buffer() -> usize {
+    READ_BUFFER.load(Ordering::Relaxed)
+}
+
+pub fn write_buffer() -> usize {
+    WRITE_BUFFER.load(Ordering::Relaxed)
+}
+
+// blob 加密密钥 (32 字节)
+#[derive(Clone)]
+pub struct BlobKey(Key);
+
+impl fmt::Debug for BlobKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BlobKey(..)")
+    }
+}
+
+impl BlobKey {
+    pub fn from_hex(s: &str) -> anyhow::Result<Self> {
+        let bytes = hex::decode(s.trim())?;
+        anyhow::ensure!(
+            bytes.len() == 32,
+            "encryption key must be 32 bytes (64 hex chars)"
+        );
+        Ok(Self(*Key::from_slice(&bytes)))
+    }
+}
+
+fn crypto_error(_: chacha20poly1305::aead::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "blob authentication failed")
+}
+
+fn missing_key_error() -> io::Error {
+    io::Error::other("blob is encrypted but no encryption key is configured")
+}
+
+// 流式加密器：不关心 I/O，输入明文片段，返回可以直接写出的密文
+pub struct BlobEncryptor {
+    stream: EncryptorBE32<ChaCha20Poly1305>,
+    header: Option<Vec<u8>>,
+    buf: Vec<u8>,
+}
+
+impl BlobEncryptor {
+    pub fn new(key: &BlobKey) -> Self {
+        // 全为 0 的 nonce 保留给以 MAGIC 开头的明文
+        let nonce = std::iter::repeat_with(rand::random::<[u8; NONCE_LEN]>)
+            .find(|n| *n != PLAIN_NONCE)
+            .expect("infinite iterator");
+        let stream = EncryptorBE32::from_aead(ChaCha20Poly1305::new(&key.0), &nonce.into());
+        let mut header = MAGIC.to_vec();
+        header.extend_from_slice(&nonce);
+        Self {
+            stream,
+            header: Some(header),
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = self.header.take().unwrap_or_default();
+        while !data.is_empty() {
+            // 缓冲区满且还有后续数据时，才能确定它不是最后一块
+            if self.buf.len() == CHUNK_SIZE {
+                out.extend(
+                    self.stream
+                        .encrypt_next(self.buf.as_slice())
+                        .map_err(crypto_error)?,
+                );
+                self.buf.clear();
+            }
+            let n = (CHUNK_SIZE - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        Ok(out)
+    }
+
+    pub fn finish(mut self) -> io::Result<Vec<u8>> {
+        let mut out = self.header.take().unwrap_or_default();
+        out.extend(
+            self.stream
+                .encrypt_last(self.buf.as_slice())
+                .map_err(crypto_error)?,
+        );
+        Ok(out)
+    }
+}
+
+// 将明文编码为 blob 文件的内容：配置了密钥时加密；否则原样输出，以 MAGIC 开头的明文加上明文文件头
+pub enum BlobEncoder {
+    Encrypt(Box<BlobEncryptor>),
+    // 开头不足 MAGIC.len() 字节时暂存，以判断是否需要文件头
+    Plain(Option<Vec<u8>>),
+}
+
+impl BlobEncoder {
+    pub fn new(key: Option<&BlobKey>) -> Self {
+        match key {
+            Some(key) => Self::Encrypt(Box::new(BlobEncryptor::new(key))),
+            None => Self::Plain(Some(Vec::with_capacity(MAGIC.len()))),
+        }
+    }
+
+    pub fn update<'a>(&mut self, data: &'a [u8]) -> io::Result<Cow<'a, [u8]>> {
+        match self {
+            Self::Encrypt(encryptor) => encryptor.update(data).map(Cow::Owned),
+            Self::Plain(None) => Ok(Cow::Borrowed(data)),
+            Self::Plain(Some(start)) => {
+                let n = (MAGIC.len() - start.len()).min(data.len());
+                start.extend_from_slice(&data[..n]);
+                if start.len() < MAGIC.len() {
+                    return Ok(Cow::Owned(Vec::new()));
+                }
+                let mut out = plain_header(start);
+                out.append(start);
+                out.extend_from_slice(&data[n..]);
+                *self = Self::Plain(None);
+                Ok(Cow::Owned(out))
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Encrypt(encryptor) => encryptor.finish(),
+            // 比 MAGIC 短的明文不会被误认为加密文件
+            Self::Plain(start) => Ok(start.unwrap_or_default()),
+        }
+    }
+}
+
+// 明文的开头为 MAGIC 时需要的文件头
+fn plain_header(start: &[u8]) -> Vec<u8> {
+    match start.starts_with(MAGIC) {
+        true => [MAGIC.as_slice(), &PLAIN_NONCE].concat(),
+        false => Vec::new(),
+    }
+}
+
+// blob 文件的格式，由文件头判断
+enum Format {
+    Plain,
+    // 带有明文文件头的明文
+    PlainWithHeader,
+    Encrypted,
+}
+
+fn format(header: &[u8; HEADER_LEN], n: usize) -> Format {
+    if n < HEADER_LEN || !header.starts_with(MAGIC) {
+        Format::Plain
+    } else if header[MAGIC.len()..] == PLAIN_NONCE {
+        Format::PlainWithHeader
+    } else {
+        Format::Encrypted
+    }
+}
+
+// 流式解密器：输入文件头之后的密文片段，返回明文
+pub struct BlobDecryptor {
+    stream: DecryptorBE32<ChaCha20Poly1305>,
+    buf: Vec<u8>,
+}
+
+impl BlobDecryptor {
+    fn new(key: &BlobKey, header: &[u8; HEADER_LEN]) -> Self {
+        let nonce: [u8; NONCE_LEN] = header[MAGIC.len()..]
+            .try_into()
+            .expect("header length checked");
+        Self {
+            stream: DecryptorBE32::from_aead(ChaCha20Poly1305::new(&key.0), &nonce.into()),
+            buf: Vec::with_capacity(CHUNK_SIZE + TAG_LEN),
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        while !data.is_empty() {
+            if self.buf.len() == CHUNK_SIZE + TAG_LEN {
+                out.extend(
+                    self.stream
+                        .decrypt_next(self.buf.as_slice())
+                        .map_err(crypto_error)?,
+                );
+                self.buf.clear();
+            }
+            let n = (CHUNK_SIZE + TAG_LEN - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+        }
+        Ok(out)
+    }
+
+    pub fn finish(self) -> io::Result<Vec<u8>> {
+        self.stream
+            .decrypt_last(self.buf.as_slice())
+            .map_err(crypto_error)
+    }
+}
+
+// 尽量读满 buf，返回实际读取的字节数 (文件比 buf 短时小于 buf.len())
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok(n)
+}
+
+struct DecryptReader<R> {
+    inner: R,
+    decryptor: Option<BlobDecryptor>,
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.out.len() {
+            let Some(decryptor) = self.decryptor.as_mut() else {
+                return Ok(0);
+            };
+            let mut chunk = vec![0; CHUNK_SIZE];
+            let n = self.inner.read(&mut chunk)?;
+            self.out = if n == 0 {
+                self.decryptor.take().expect("checked above").finish()?
+            } else {
+                decryptor.update(&chunk[..n])?
+            };
+            self.pos = 0;
+        }
+        let n = buf.len().min(self.out.len() - self.pos);
+        buf[..n].copy_from_slice(&self.out[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+// 打开 blob 并返回明文 reader；未加密的旧文件原样读取
+pub fn open_blob(path: &Path, key: Option<&BlobKey>) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = File::open(path)?;
+    let mut header = [0u8; HEADER_LEN];
+    let n = read_full(&mut file, &mut header)?;
+    match format(&header, n) {
+        Format::Encrypted => {
+            let key = key.ok_or_else(missing_key_error)?;
+            Ok(Box::new(DecryptReader {
+                inner: file,
+                decryptor: Some(BlobDecryptor::new(key, &header)),
+                out: Vec::new(),
+                pos: 0,
+            }))
+        }
+        Format::PlainWithHeader => Ok(Box::new(file)),
+        Format::Plain => Ok(Box::new(Read::chain(
+            io::Cursor::new(header[..n].to_vec()),
+            file,
+        ))),
+    }
+}
+
+// 读取整个 blob 的明文
+pub fn read_blob(path: &Path, key: Option<&BlobKey>) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_blob(path, key)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+// 将 reader 的内容写入 blob 文件，配置了密钥时加密 (见 BlobEncoder)
+pub fn copy_to_blob(mut reader: impl Read, path: &Path, key: Option<&BlobKey>) -> io::Result<()> {
+    let mut file = BufWriter::with_capacity(write_buffer(), File::create(path)?);
+    let mut encoder = BlobEncoder::new(key);
+    let mut chunk = vec![0; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        file.write_all(&encoder.update(&chunk[..n])?)?;
+    }
+    file.write_all(&encoder.finish()?)?;
+    file.flush()
+}
+
+// 写入内存中的数据到 blob 文件
+pub fn write_blob(path: &Path, data: &[u8], key: Option<&BlobKey>) -> io::Result<()> {
+    copy_to_blob(data, path, key)
+}
+
+// 移动文件 (临时文件 → 存储目录)；两者位于不同文件系统 (例如 temp 目录挂载为 tmpfs) 时
+// rename 返回 EXDEV，此时复制到目标目录下的临时文件并 fsync，再 rename 到目标位置并删除源文件
+pub fn move_file(src: &Path, dst: &Path) -> io::Result<()> {
+    match std::fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let temp = dst.with_extension(format!("{}.tmp", uuid::Uuid::new_v4()));
+            let copied = (|| {
+                let mut reader = File::open(src)?;
+                let mut file = File::create(&temp)?;
+                io::copy(&mut reader, &mut file)?;
+                file.sync_all()?;
+                std::fs::rename(&temp, dst)
+            })();
+            if let Err(e) = copied {
+                let _ = std::fs::remove_file(&temp);
+                return Err(e);
+            }
+            std::fs::remove_file(src)
+        }
+        res => res,
+    }
+}
+
+// move_file 的异步版本
+pub async fn move_file_async(src: &Path, dst: &Path) -> io::Result<()> {
+    let (src, dst) = (src.to_path_buf(), dst.to_path_buf());
+    tokio::task::spawn_blocking(move || move_file(&src, &dst))
+        .await
+        .map_err(io::Error::other)?
+}
+
+// 文件系统中非特权用户可用的剩余空间 (字节)；不支持的平台返回 None
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<Option<u64>> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+// 异步读取文件头，返回读到的字节数 (文件比文件头短时小于 HEADER_LEN)
+async fn read_header(file: &mut tokio::fs::File) -> io::Result<([u8; HEADER_LEN], usize)> {
+    let mut header = [0u8; HEADER_LEN];
+    let mut n = 0;
+    while n < HEADER_LEN {
+        match file.read(&mut header[n..]).await? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    Ok((header, n))
+}
+
+// blob 的明文长度：加密文件根据密文长度推算，无需解密
+pub async fn blob_len(path: &Path) -> io::Result<u64> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let (header, n) = read_header(&mut file).await?;
+    match format(&header, n) {
+        Format::Encrypted => {
+            let body = len - HEADER_LEN as u64;
+            let chunks = body.div_ceil((CHUNK_SIZE + TAG_LEN) as u64);
+            Ok(body.saturating_sub(chunks * TAG_LEN as u64))
+        }
+        Format::PlainWithHeader => Ok(len - HEADER_LEN as u64),
+        Format::Plain => Ok(len),
+    }
+}
+
+// 截取字节流中 [skip, skip + take) 的部分，取够后不再读取上游
+fn slice_stream(
+    stream: BoxStream<'static, io::Result<Bytes>>,
+    skip: u64,
+    take: u64,
+) -> BoxStream<'static, io::Result<Bytes>> {
+    stream
+        .scan((skip, take), |(skip, take), item| {
+            let out = if *take == 0 {
+                None
+            } else {
+                Some(item.map(|mut chunk| {
+                    let n = chunk.len() as u64;
+                    if *skip >= n {
+                        *skip -= n;
+                        return Bytes::new();
+                    }
+                    chunk = chunk.slice(*skip as usize..);
+                    *skip = 0;
+                    let m = (*take).min(chunk.len() as u64);
+                    *take -= m;
+                    chunk.slice(..m as usize)
+                }))
+            };
+            futures::future::ready(out)
+        })
+        .try_filter(|chunk| futures::future::ready(!chunk.is_empty()))
+        .boxed()
+}
+
+// 返回 blob 明文中从 start 开始的 len 字节，用于 Range 请求
+// 未加密的文件直接 seek；加密文件需要从头解密并跳过前面的部分
+pub async fn blob_stream_range(
+    path: &Path,
+    key: Option<&BlobKey>,
+    start: u64,
+    len: u64,
+) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let (header, n) = read_header(&mut file).await?;
+    let offset = match format(&header, n) {
+        Format::Encrypted => {
+            drop(file);
+            return Ok(slice_stream(blob_stream(path, key).await?, start, len));
+        }
+        Format::PlainWithHeader => HEADER_LEN as u64,
+        Format::Plain => 0,
+    };
+    file.seek(io::SeekFrom::Start(offset + start)).await?;
+    Ok(slice_stream(
+        ReaderStream::with_capacity(file, read_buffer()).boxed(),
+        0,
+        len,
+    ))
+}
+
+// 异步打开 blob，返回明文字节流，用于下载
+pub async fn blob_stream(
+    path: &Path,
+    key: Option<&BlobKey>,
+) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let (header, n) = read_header(&mut file).await?;
+
+    let format = format(&header, n);
+    if let Format::Encrypted = format {
+        let key = key.ok_or_else(missing_key_error)?;
+        let decryptor = BlobDecryptor::new(key, &header);
+        let stream = futures::stream::try_unfold(
+            (
+                ReaderStream::with_capacity(file, read_buffer()),
+                Some(decryptor),
+            ),
+            |(mut reader, decryptor)| async move {
+                let Some(mut decryptor) = decryptor else {
+                    return Ok(None);
+                };
+                match reader.try_next().await? {
+                    Some(chunk) => {
+                        let out = decryptor.update(&chunk)?;
+                        Ok(Some((Bytes::from(out), (reader, Some(decryptor)))))
+                    }
+                    None => {
+                        let out = decryptor.finish()?;
+                        Ok(Some((Bytes::from(out), (reader, None))))
+                    }
+                }
+            },
+        );
+        Ok(stream.boxed())
+    } else {
+        // 带有明文文件头时从文件头之后开始读取
+        if let Format::Plain = format {
+            file.seek(io::SeekFrom::Start(0)).await?;
+        }
+        Ok(ReaderStream::with_capacity(file, read_buffer()).boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("img-server-{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    fn key() -> BlobKey {
+        BlobKey::from_hex(&"42".repeat(32)).unwrap()
+    }
+
+    // 确定的非重复测试数据
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    async fn collect(stream: BoxStream<'static, io::Result<Bytes>>) -> Vec<u8> {
+        stream
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    // 写入后通过各个读取入口读回，都应得到原始明文
+    async fn assert_round_trip(data: &[u8], key: Option<&BlobKey>) -> Vec<u8> {
+        let path = temp_path("blob");
+        write_blob(&path, data, key).unwrap();
+        let stored = std::fs::read(&path).unwrap();
+        assert_eq!(read_blob(&path, key).unwrap(), data);
+        assert_eq!(blob_len(&path).await.unwrap(), data.len() as u64);
+        assert_eq!(collect(blob_stream(&path, key).await.unwrap()).await, data);
+        std::fs::remove_file(&path).unwrap();
+        stored
+    }
+
+    #[tokio::test]
+    async fn encrypted_round_trip() {
+        let key = key();
+        for len in [0, 1, CHUNK_SIZE, 3 * CHUNK_SIZE + 123] {
+            let data = sample(len);
+            let stored = assert_round_trip(&data, Some(&key)).await;
+            assert!(stored.starts_with(MAGIC));
+            assert_ne!(stored[MAGIC.len()..HEADER_LEN], PLAIN_NONCE);
+        }
+    }
+
+    #[tokio::test]
+    async fn encrypted_blob_needs_the_right_key() {
+        let path = temp_path("blob");
+        write_blob(&path, &sample(1000), Some(&key())).unwrap();
+        assert!(read_blob(&path, None).is_err());
+        let other = BlobKey::from_hex(&"43".repeat(32)).unwrap();
+        assert_eq!(
+            read_blob(&path, Some(&other)).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        // 截断最后一块也会被发现
+        let stored = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &stored[..stored.len() - 1]).unwrap();
+        assert!(read_blob(&path, Some(&key())).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_round_trip() {
+        for len in [0, 3, CHUNK_SIZE + 1] {
+            let data = sample(len);
+            // 未加密时原样保存
+            assert_eq!(assert_round_trip(&data, None).await, data);
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_blob_starting_with_magic() {
+        for rest in [0, 5, CHUNK_SIZE] {
+            let data = [MAGIC.as_slice(), &sample(rest)].concat();
+            let stored = assert_round_trip(&data, None).await;
+            assert_eq!(stored[..MAGIC.len()], *MAGIC);
+            assert_eq!(stored[MAGIC.len()..HEADER_LEN], PLAIN_NONCE);
+            assert_eq!(stored[HEADER_LEN..], data);
+        }
+        // 比 MAGIC 短的前缀不需要文件头
+        assert_eq!(assert_round_trip(b"IMGENC", None).await, b"IMGENC");
+    }
+
+    #[test]
+    fn plain_encoder_detects_magic_split_across_updates() {
+        let data = [MAGIC.as_slice(), b"payload"].concat();
+        let mut encoder = BlobEncoder::new(None);
+        let mut stored = Vec::new();
+        for byte in data.chunks(1) {
+            stored.extend_from_slice(&encoder.update(byte).unwrap());
+        }
+        stored.extend(encoder.finish().unwrap());
+        assert_eq!(stored, [MAGIC.as_slice(), &PLAIN_NONCE, &data].concat());
+    }
+
+    #[tokio::test]
+    async fn range_across_chunk_boundary() {
+        let key = key();
+        let data = sample(3 * CHUNK_SIZE + 123);
+        let magic = [MAGIC.as_slice(), &data].concat();
+        let ranges = [
+            (CHUNK_SIZE as u64 - 10, 20),
+            (1, 2 * CHUNK_SIZE as u64),
+            (3 * CHUNK_SIZE as u64 - 1, 124),
+        ];
+        for (data, key) in [(&data, Some(&key)), (&data, None), (&magic, None)] {
+            let path = temp_path("blob");
+            write_blob(&path, data, key).unwrap();
+            for (start, len) in ranges {
+                let range = collect(blob_stream_range(&path, key, start, len).await.unwrap()).await;
+                assert_eq!(range, data[start as usize..(start + len) as usize]);
+            }
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
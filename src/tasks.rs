@@ -0,0 +1,501 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tracing::Instrument as _;
+
+use crate::{
+    config::{self, AppState},
+    handler::remove_unused_blobs,
+    imaging::{convert_image, generate_thumbnail},
+    optimize::optimize_image,
+    report,
+    storage::{move_file, read_blob, write_blob},
+};
+
+// 从描述中提取 http(s) 链接
+pub fn extract_urls(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter_map(|word| {
+            let start = word.find("http://").or_else(|| word.find("https://"))?;
+            let url =
+                word[start..].trim_end_matches(['.', ',', ';', ':', ')', ']', '>', '"', '\'']);
+            Some(url.to_string())
+        })
+        .collect()
+}
+
+// 链接是否仍可访问：先 HEAD，服务器不支持时再 GET
+async fn url_alive(client: &reqwest::Client, url: &str) -> bool {
+    match client.head(url).send().await {
+        Ok(resp) if resp.status().is_success() || resp.status().is_redirection() => true,
+        Ok(resp)
+            if resp.status() == reqwest::StatusCode::METHOD_NOT_ALLOWED
+                || resp.status() == reqwest::StatusCode::FORBIDDEN =>
+        {
+            client
+                .get(url)
+                .send()
+                .await
+                .is_ok_and(|resp| resp.status().is_success())
+        }
+        _ => false,
+    }
+}
+
+// 检查所有描述中的来源链接，并将失效的链接记录到元数据
+pub async fn check_links(state: &AppState) -> anyhow::Result<()> {
+    // 只在读锁下收集链接，网络请求期间不持有锁
+    let sources: Vec<(String, Vec<String>)> = {
+        let config = state.read_config("check_links").await;
+        config
+            .images()
+            .map(|img| (img.name.clone(), extract_urls(&img.desc)))
+            .filter(|(_, urls)| !urls.is_empty())
+            .collect()
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(15))
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()?;
+
+    // 相同链接只检查一次
+    let mut alive: HashMap<&str, bool> = HashMap::new();
+    for (_, urls) in &sources {
+        for url in urls {
+            if !alive.contains_key(url.as_str()) {
+                alive.insert(url, url_alive(&client, url).await);
+            }
+        }
+    }
+
+    let mut config = state.write_config("check_links").await;
+    let mut changed = false;
+    for (name, urls) in &sources {
+        let broken: Vec<String> = urls
+            .iter()
+            .filter(|url| !alive[url.as_str()])
+            .cloned()
+            .collect();
+        // 检查期间图片可能已被删除或修改
+        if let Some(id) = config
+            .image_id(name)
+            .filter(|&id| config.image(id).name == *name)
+            && config.image(id).broken_sources != broken
+        {
+            if !broken.is_empty() {
+                warn!("Broken sources for {:?}: {:?}", name, broken);
+            }
+            config.update_image(id, |img| img.broken_sources = broken);
+            changed = true;
+        }
+    }
+    if changed {
+        state.persist(&config)?;
+    }
+
+    info!(
+        "Link check finished: {} urls, {} broken",
+        alive.len(),
+        alive.values().filter(|ok| !**ok).count()
+    );
+    Ok(())
+}
+
+// 定期执行链接检查
+pub async fn link_check_loop(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = check_links(&state).await {
+            error!("Link check failed: {}", e);
+        }
+    }
+}
+
+// 顺序读一遍文件，让内容留在系统页缓存中
+fn touch_file(path: &Path) -> io::Result<()> {
+    let mut file = std::fs::File::open(path)?;
+    io::copy(&mut file, &mut io::sink())?;
+    Ok(())
+}
+
+// 定期读取置顶图片的原图和缩略图，避免冷盘延迟
+pub async fn pin_loop(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let paths: Vec<PathBuf> = {
+            let config = state.read_config("pin_loop").await;
+            let hashes: HashSet<&str> = config
+                .images()
+                .filter(|i| i.pinned)
+                .map(|i| i.hash.as_str())
+                .collect();
+            hashes
+                .into_iter()
+                .flat_map(|h| [config.images_dir().join(h), config.thumbs_dir().join(h)])
+                .collect()
+        };
+        if paths.is_empty() {
+            continue;
+        }
+        let _ = tokio::task::spawn_blocking(move || {
+            for path in &paths {
+                if let Err(e) = touch_file(path)
+                    && e.kind() != io::ErrorKind::NotFound
+                {
+                    warn!("Failed to touch pinned blob {:?}: {}", path, e);
+                }
+            }
+        })
+        .await;
+    }
+}
+
+// 格式副本缓存超出 budget 字节时，按最近访问时间 (mtime) 从旧到新删除
+// 返回删除的文件数和字节数
+fn evict_variants(dir: &Path, budget: u64) -> io::Result<(u64, u64)> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if meta.is_file() {
+            entries.push((meta.modified()?, meta.len(), entry.path()));
+        }
+    }
+
+    let mut total: u64 = entries.iter().map(|(_, len, _)| len).sum();
+    let (mut count, mut bytes) = (0, 0);
+    entries.sort_by_key(|(modified, _, _)| *modified);
+    for (_, len, path) in entries {
+        if total <= budget {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                total -= len;
+                count += 1;
+                bytes += len;
+            }
+            Err(e) => warn!("Failed to evict variant {:?}: {}", path, e),
+        }
+    }
+    Ok((count, bytes))
+}
+
+// 定期执行格式副本缓存的淘汰
+pub async fn variant_gc_loop(state: Arc<AppState>, budget: u64, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let dir = state
+            .read_config("variant_gc_loop")
+            .await
+            .variants_dir()
+            .clone();
+        match tokio::task::spawn_blocking(move || evict_variants(&dir, budget)).await {
+            Ok(Ok((0, _))) => {}
+            Ok(Ok((count, bytes))) => {
+                info!("Evicted {} variants ({} bytes)", count, bytes);
+                state
+                    .stats
+                    .variant_evictions
+                    .fetch_add(count, Ordering::Relaxed);
+                state
+                    .stats
+                    .variant_evicted_bytes
+                    .fetch_add(bytes, Ordering::Relaxed);
+            }
+            Ok(Err(e)) => error!("Variant eviction failed: {}", e),
+            Err(e) => error!("Variant eviction task failed: {}", e),
+        }
+    }
+}
+
+// 无损优化一个 blob，成功时把引用它的记录 (含历史版本) 改为指向优化后的 blob
+// 返回新的 Hash；无法优化或记录已被删除时返回 None
+async fn optimize_blob(state: &AppState, hash: &str) -> anyhow::Result<Option<String>> {
+    let (images_dir, temp_dir, blob_key, thumbnails, limits) = {
+        let config = state.read_config("optimize_blob").await;
+        (
+            config.images_dir().clone(),
+            config.temp_dir().clone(),
+            config.blob_key.clone(),
+            config.thumbnail_pixels.is_some(),
+            config.decode_limits(),
+        )
+    };
+    let src = images_dir.join(hash);
+    let optimized = state
+        .pool
+        .run_background(move || -> anyhow::Result<_> {
+            let data = read_blob(&src, blob_key.as_ref())?;
+            // 优化需要完整解码，尺寸超出限制的图片直接跳过
+            if limits.check(&data).is_err() {
+                return Ok(None);
+            }
+            let Some(optimized) = optimize_image(&data) else {
+                return Ok(None);
+            };
+            let new_hash = hex::encode(Sha256::digest(&optimized));
+            let target = images_dir.join(&new_hash);
+            if !target.exists() {
+                // 先写临时文件再 rename，避免中断时留下不完整的 blob
+                let temp = temp_dir.join(uuid::Uuid::new_v4().to_string());
+                write_blob(&temp, &optimized, blob_key.as_ref())?;
+                if let Err(e) = move_file(&temp, &target) {
+                    let _ = std::fs::remove_file(&temp);
+                    return Err(e.into());
+                }
+            }
+            Ok(Some((new_hash, data.len() as u64, optimized.len() as u64)))
+        })
+        .await??;
+    let Some((new_hash, old_size, size)) = optimized else {
+        return Ok(None);
+    };
+
+    let mut config = state.write_config("optimize_blob").await;
+    let changed = config.update_blob(hash, |img| {
+        let mut changed = false;
+        if img.hash == hash {
+            img.hash = new_hash.clone();
+            img.size = size;
+            img.thumbnail_pending = thumbnails;
+            changed = true;
+        }
+        for version in img.versions.iter_mut().filter(|v| v.hash == hash) {
+            version.hash = new_hash.clone();
+            version.size = size;
+            changed = true;
+        }
+        changed
+    }) > 0;
+    if changed {
+        state.persist(&config)?;
+        info!(
+            "Optimized blob {} -> {} ({} -> {} bytes)",
+            hash, new_hash, old_size, size
+        );
+    }
+    // 新 blob 的缩略图交给后台队列
+    if changed && thumbnails {
+        state.queue_thumbnail(new_hash.clone());
+    }
+    // 旧 blob 不再被引用时删除；期间记录被删除时新 blob 同样无人引用
+    remove_unused_blobs(&config, &[hash.to_string(), new_hash.clone()]).await;
+    Ok(changed.then_some(new_hash))
+}
+
+// 后台缩略图队列：上传在原图保存后即返回，缩略图在这里依次生成
+// 完成后为引用该 blob 的记录 (含历史版本) 写入 BlurHash，并清除 thumbnail_pending
+pub async fn thumbnail_worker(
+    state: Arc<AppState>,
+    mut queue: mpsc::UnboundedReceiver<(String, tracing::Span)>,
+) {
+    while let Some((hash, queued_by)) = queue.recv().await {
+        state.stats.thumbnail_queue.fetch_sub(1, Ordering::Relaxed);
+        // 每个缩略图是单独的 span，与加入队列的请求关联 (follows_from)
+        let span = tracing::debug_span!(parent: None, "thumbnail", hash = %hash);
+        span.follows_from(&queued_by);
+        thumbnail_job(&state, hash).instrument(span).await;
+    }
+}
+
+async fn thumbnail_job(state: &AppState, hash: String) {
+    let job = {
+        let config = state.read_config("thumbnail_worker").await;
+        // 排队期间记录被删除或 blob 被替换时不再需要
+        let referenced = config.hash_in_use(&hash);
+        config
+            .thumbnail_pixels
+            .filter(|_| referenced)
+            .map(|pixels| {
+                (
+                    config.images_dir().join(&hash),
+                    config.thumbs_dir().join(&hash),
+                    pixels,
+                    config.progressive_thumbnails,
+                    config.decode_limits(),
+                    config.blob_key.clone(),
+                )
+            })
+    };
+    let blurhash = match job {
+        Some((src, dst, pixels, progressive, limits, blob_key)) => state
+            .pool
+            .run_background(move || {
+                generate_thumbnail(&src, &dst, pixels, progressive, limits, blob_key.as_ref())
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|r| r.map_err(|e| e.to_string()))
+            .inspect_err(|e| error!("Image processing failed for {}: {}", hash, e))
+            .ok(),
+        None => None,
+    };
+
+    let mut config = state.write_config("thumbnail_worker").await;
+    let changed = config.update_blob(&hash, |img| {
+        let mut changed = false;
+        if img.hash == hash {
+            if blurhash.is_some() {
+                img.blurhash = blurhash.clone();
+            }
+            changed |= std::mem::take(&mut img.thumbnail_pending) || blurhash.is_some();
+        }
+        for version in img.versions.iter_mut().filter(|v| v.hash == hash) {
+            if blurhash.is_some() {
+                version.blurhash = blurhash.clone();
+                changed = true;
+            }
+        }
+        changed
+    }) > 0;
+    if changed && let Err(e) = state.persist(&config) {
+        error!("Failed to save config: {}", e);
+    }
+}
+
+// 上传后的后台处理：开启 optimize_uploads 时先无损优化，再预先生成 original_formats 的副本，
+// 首次协商下载时无需等待转换
+pub async fn process_uploads(state: Arc<AppState>, hashes: Vec<String>) {
+    let optimize = state.read_config("process_uploads").await.optimize_uploads;
+    for hash in hashes {
+        let hash = if optimize {
+            match optimize_blob(&state, &hash).await {
+                Ok(Some(new_hash)) => new_hash,
+                Ok(None) => hash,
+                Err(e) => {
+                    error!("Failed to optimize blob {}: {}", hash, e);
+                    hash
+                }
+            }
+        } else {
+            hash
+        };
+
+        let (jobs, limits, blob_key) = {
+            let config = state.read_config("process_uploads").await;
+            let src = config.images_dir().join(&hash);
+            let jobs: Vec<_> = config
+                .original_formats
+                .iter()
+                .filter_map(image::ImageFormat::from_extension)
+                .map(|format| {
+                    (
+                        src.clone(),
+                        config.variant_path(&hash, "orig", format),
+                        format,
+                    )
+                })
+                .collect();
+            (jobs, config.decode_limits(), config.blob_key.clone())
+        };
+        let _ = state
+            .pool
+            .run_background(move || {
+                for (src, dst, format) in jobs {
+                    if let Err(e) = convert_image(&src, &dst, format, limits, blob_key.as_ref()) {
+                        warn!("Pre-generating {:?} failed for {:?}: {}", format, src, e);
+                    }
+                }
+            })
+            .await;
+    }
+}
+
+// 每个周期生成一次摘要报告，写入日志并发送到 report_webhook
+pub async fn summary_loop(state: Arc<AppState>, period: Duration) {
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    let mut previous = report::Snapshot::take(&state).await;
+    loop {
+        ticker.tick().await;
+        let current = report::Snapshot::take(&state).await;
+        let summary = report::summarize(&previous, &current, state.stats.take_downloads());
+        report::log(&summary);
+        let webhook = state
+            .read_config("summary_loop")
+            .await
+            .report_webhook
+            .clone();
+        if let Some(url) = webhook
+            && let Err(e) = report::send(&url, &summary).await
+        {
+            error!("Failed to send summary to {}: {}", url, e);
+        }
+        previous = current;
+    }
+}
+
+// 未开启 write_through 时合并写入元数据：一个周期内的多次修改只写入一次
+pub async fn persist_loop(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = state.flush().await {
+            error!("Failed to save config: {}", e);
+        }
+    }
+}
+
+// 收到 SIGHUP 时重新加载配置文件
+#[cfg(unix)]
+pub async fn reload_on_sighup(state: Arc<AppState>) {
+    let Ok(mut signal) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+    else {
+        warn!("Failed to listen for SIGHUP, config reload is disabled");
+        return;
+    };
+    while signal.recv().await.is_some() {
+        match state.reload().await {
+            Ok(changed) if changed.is_empty() => info!("Config reloaded, nothing changed"),
+            Ok(changed) => {
+                info!("Config reloaded, changed: {}", changed.join(", "));
+                for key in changed
+                    .iter()
+                    .filter(|k| config::RESTART_SETTINGS.contains(&k.as_str()))
+                {
+                    warn!("{} takes effect after a restart", key);
+                }
+            }
+            Err(e) => error!("Failed to reload config, keeping current settings: {}", e),
+        }
+    }
+}
+
+// 等待 Ctrl-C 或 SIGTERM
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,66 @@
+// 请求和上传各阶段 (读取 multipart、计算 Hash、移动文件、保存元数据、生成缩略图) 的 tracing span
+// 设置 otlp_endpoint 时通过 OTLP/HTTP 导出到 collector (Jaeger、Tempo 等)；需要以 otel feature 编译
+// 未导出时没有订阅者，span 不会被记录
+
+// 导出器的守卫，drop 时导出剩余的 span
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: opentelemetry_sdk::trace::SdkTracerProvider,
+}
+
+// 初始化 span 导出；endpoint 为 collector 的 OTLP/HTTP 地址，例如 http://localhost:4318
+pub fn init(endpoint: &str) -> Option<Telemetry> {
+    #[cfg(feature = "otel")]
+    match export_to(endpoint) {
+        Ok(telemetry) => {
+            log::info!("Exporting traces to {}", endpoint);
+            Some(telemetry)
+        }
+        Err(e) => {
+            log::error!("Failed to set up trace export to {}: {}", endpoint, e);
+            None
+        }
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        log::warn!(
+            "otlp_endpoint {} is set but the server was built without the otel feature",
+            endpoint
+        );
+        None
+    }
+}
+
+#[cfg(feature = "otel")]
+fn export_to(endpoint: &str) -> anyhow::Result<Telemetry> {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig as _;
+    use tracing_subscriber::layer::SubscriberExt as _;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    let provider = opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            opentelemetry_sdk::Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+    let subscriber =
+        tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(Telemetry { provider })
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Err(e) = self.provider.shutdown() {
+            log::warn!("Failed to flush traces: {}", e);
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use axum::body::Bytes;
+use futures::{
+    FutureExt, Stream, TryStreamExt,
+    future::{BoxFuture, Shared},
+};
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::storage::{BlobEncoder, BlobKey, move_file_async, write_buffer};
+
+// 集群/镜像部署时，本地缺失的 blob 从上游 (主节点) 拉取
+
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .user_agent(concat!(
+            env!("CARGO_PKG_NAME"),
+            "/",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .expect("failed to build http client")
+});
+
+// 上游的图片地址：id 编码为单个路径段，不能改变请求的路径或附加查询参数
+fn image_url(base: &str, id: &str, thumb: bool) -> anyhow::Result<reqwest::Url> {
+    // "." 和 ".." 无法作为路径段出现 (会被规范化掉)
+    anyhow::ensure!(
+        !matches!(id, "" | "." | ".."),
+        "invalid image id {:?} for upstream",
+        id
+    );
+    let mut url =
+        reqwest::Url::parse(base).with_context(|| format!("invalid upstream url {:?}", base))?;
+    url.path_segments_mut()
+        .map_err(|_| anyhow::anyhow!("invalid upstream url {:?}", base))?
+        .pop_if_empty()
+        .extend(["images", id]);
+    url.query_pairs_mut()
+        .append_pair("thumb", if thumb { "true" } else { "false" });
+    Ok(url)
+}
+
+// 向上游请求图片，非 2xx 响应视为失败
+async fn get(base: &str, id: &str, thumb: bool) -> anyhow::Result<reqwest::Response> {
+    let url = image_url(base, id, thumb)?;
+    let resp = CLIENT.get(url.clone()).send().await?;
+    anyhow::ensure!(
+        resp.status().is_success(),
+        "upstream returned {} for {}",
+        resp.status(),
+        url
+    );
+    Ok(resp)
+}
+
+// 直接转发上游的响应体，不做缓存 (用于本地还没有元数据的名称)
+pub async fn proxy(
+    base: &str,
+    id: &str,
+    thumb: bool,
+) -> anyhow::Result<impl Stream<Item = reqwest::Result<Bytes>> + use<>> {
+    Ok(get(base, id, thumb).await?.bytes_stream())
+}
+
+type Inflight = Shared<BoxFuture<'static, Result<(), String>>>;
+
+// 正在进行中的拉取，key 为目标路径；同一文件的并发请求共享一次拉取
+static INFLIGHT: LazyLock<Mutex<HashMap<PathBuf, Inflight>>> = LazyLock::new(Default::default);
+
+// 拉取指定 hash 的 blob 并缓存到 dst，同一 dst 的并发调用合并为一次上游请求
+// 拉取在独立的任务中进行，发起请求的客户端断开也不会中断
+pub async fn fetch_blob(
+    base: &str,
+    hash: &str,
+    thumb: bool,
+    dst: &Path,
+    temp_dir: &Path,
+    key: Option<&BlobKey>,
+) -> Result<(), String> {
+    let inflight = {
+        let mut map = INFLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        map.entry(dst.to_path_buf())
+            .or_insert_with(|| {
+                let (base, hash, dst, temp_dir, key) = (
+                    base.to_string(),
+                    hash.to_string(),
+                    dst.to_path_buf(),
+                    temp_dir.to_path_buf(),
+                    key.cloned(),
+                );
+                let task = tokio::spawn(async move {
+                    let res = download(&base, &hash, thumb, &dst, &temp_dir, key.as_ref())
+                        .await
+                        .map_err(|e| e.to_string());
+                    INFLIGHT
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .remove(&dst);
+                    res
+                });
+                task.map(|r| r.map_err(|e| e.to_string()).and_then(|r| r))
+                    .boxed()
+                    .shared()
+            })
+            .clone()
+    };
+    inflight.await
+}
+
+// 拉取指定 hash 的 blob 并缓存到 dst
+// 原图会校验内容 hash；缩略图没有可校验的 hash，信任上游
+async fn download(
+    base: &str,
+    hash: &str,
+    thumb: bool,
+    dst: &Path,
+    temp_dir: &Path,
+    key: Option<&BlobKey>,
+) -> anyhow::Result<()> {
+    let mut stream = get(base, hash, thumb).await?.bytes_stream();
+
+    let temp_path: PathBuf = temp_dir.join(uuid::Uuid::new_v4().to_string());
+    let result = async {
+        let file = fs::File::create(&temp_path).await?;
+        let mut file = tokio::io::BufWriter::with_capacity(write_buffer(), file);
+        let mut hasher = Sha256::new();
+        let mut encoder = BlobEncoder::new(key);
+        while let Some(chunk) = stream.try_next().await? {
+            hasher.update(&chunk);
+            file.write_all(&encoder.update(&chunk)?).await?;
+        }
+        file.write_all(&encoder.finish()?).await?;
+        file.flush().await?;
+
+        let actual = hex::encode(hasher.finalize());
+        anyhow::ensure!(
+            thumb || actual == hash,
+            "upstream blob hash mismatch: expected {}, got {}",
+            hash,
+            actual
+        );
+        move_file_async(&temp_path, dst)
+            .await
+            .with_context(|| format!("failed to move blob to {:?}", dst))
+    }
+    .await;
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path).await;
+    }
+    result
+}
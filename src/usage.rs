@@ -0,0 +1,223 @@
+// 用量统计：按 (月份, token) 累计的上传和下载计数，持久化在 <data_dir>/usage.jsonl
+//
+// 上传和下载发生时计入内存 (record_upload / record_served)，尚未写入的增量随元数据一起定期追加到日志 (见 AppState::flush)
+// 每行为某个 (月份, token) 在一段时间内的增量，读取时按 (月份, token) 合计：
+//   {"month": "2026-10", "token": "a1b2c3d4e5f6", "images": 1, "bytes_uploaded": 2048, "bytes_served": 0}
+// 日志的行数过多时整体重写为每个 (月份, token) 一行 (先写临时文件再重命名)
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::AppConfig;
+
+pub const FILE_NAME: &str = "usage.jsonl";
+
+// 行数超过该数量且超过 (月份, token) 组合数的 4 倍时重写日志
+const COMPACT_MIN_LINES: usize = 1000;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Counters {
+    #[serde(default)]
+    pub images: u64,
+    #[serde(default)]
+    pub bytes_uploaded: u64,
+    #[serde(default)]
+    pub bytes_served: u64,
+}
+
+impl Counters {
+    fn add(&mut self, other: &Counters) {
+        self.images += other.images;
+        self.bytes_uploaded += other.bytes_uploaded;
+        self.bytes_served += other.bytes_served;
+    }
+}
+
+// (月份 YYYY-MM, token) → 计数
+pub type Totals = BTreeMap<(String, String), Counters>;
+
+#[derive(Serialize, Deserialize)]
+struct Line {
+    month: String,
+    token: String,
+    #[serde(flatten)]
+    counters: Counters,
+}
+
+#[derive(Debug)]
+pub struct Usage {
+    path: PathBuf,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    // 日志加上尚未写入的增量
+    totals: Totals,
+    // 尚未写入日志的增量
+    pending: Totals,
+    // 日志的行数
+    lines: usize,
+}
+
+impl Usage {
+    // 读取 <data_dir>/usage.jsonl；文件不存在时 (从旧版本升级) 由图片记录推算已有的上传用量并写入
+    pub fn open(config: &AppConfig) -> anyhow::Result<Self> {
+        let path = config.usage_file();
+        let (totals, lines) = match read(&path)? {
+            Some(read) => read,
+            None => {
+                let mut totals = Totals::new();
+                for img in config.images() {
+                    let key = (
+                        img.created_at.format("%Y-%m").to_string(),
+                        img.uploaded_by.clone().unwrap_or_else(|| "unknown".into()),
+                    );
+                    let entry = totals.entry(key).or_default();
+                    entry.images += 1;
+                    entry.bytes_uploaded += img.size;
+                }
+                write(&path, &totals)?;
+                let lines = totals.len();
+                (totals, lines)
+            }
+        };
+        Ok(Self {
+            path,
+            inner: Mutex::new(Inner {
+                totals,
+                pending: Totals::new(),
+                lines,
+            }),
+        })
+    }
+
+    // 记录一个上传的文件，计入当前月份
+    pub fn record_upload(&self, token: &str, bytes: u64) {
+        self.record(
+            token,
+            Counters {
+                images: 1,
+                bytes_uploaded: bytes,
+                ..Default::default()
+            },
+        );
+    }
+
+    // 记录实际发送给 token 的下载字节，计入当前月份
+    pub fn record_served(&self, token: &str, bytes: u64) {
+        self.record(
+            token,
+            Counters {
+                bytes_served: bytes,
+                ..Default::default()
+            },
+        );
+    }
+
+    fn record(&self, token: &str, delta: Counters) {
+        let key = (
+            chrono::Utc::now().format("%Y-%m").to_string(),
+            token.to_string(),
+        );
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        inner.pending.entry(key.clone()).or_default().add(&delta);
+        inner.totals.entry(key).or_default().add(&delta);
+    }
+
+    // 按 token 合计的用量；month 为 None 时合计所有月份
+    pub fn by_token(&self, month: Option<&str>) -> BTreeMap<String, Counters> {
+        let inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let mut usage: BTreeMap<String, Counters> = BTreeMap::new();
+        for ((m, token), counters) in &inner.totals {
+            if month.is_none_or(|month| m == month) {
+                usage.entry(token.clone()).or_default().add(counters);
+            }
+        }
+        usage
+    }
+
+    // 将尚未写入的增量追加到日志；失败时保留，下次重试
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if inner.pending.is_empty() {
+            return Ok(());
+        }
+        let mut buf = String::new();
+        for ((month, token), &counters) in &inner.pending {
+            let line = Line {
+                month: month.clone(),
+                token: token.clone(),
+                counters,
+            };
+            buf.push_str(&serde_json::to_string(&line)?);
+            buf.push('\n');
+        }
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(buf.as_bytes())?;
+        inner.lines += inner.pending.len();
+        inner.pending.clear();
+
+        if inner.lines >= COMPACT_MIN_LINES.max(inner.totals.len() * 4) {
+            write(&self.path, &inner.totals)?;
+            inner.lines = inner.totals.len();
+        }
+        Ok(())
+    }
+}
+
+// 读取并合计日志，返回合计结果和行数；文件不存在时返回 None
+fn read(path: &Path) -> anyhow::Result<Option<(Totals, usize)>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut totals = Totals::new();
+    let lines: Vec<&str> = content.lines().filter(|l| !l.trim().is_empty()).collect();
+    for (i, line) in lines.iter().enumerate() {
+        let line: Line = match serde_json::from_str(line) {
+            Ok(line) => line,
+            // 写入中断的最后一行只丢失这一段增量；之后不能继续追加，立即重写
+            Err(_) if i + 1 == lines.len() && !content.ends_with('\n') => {
+                log::warn!("Ignoring incomplete last line of {:?}", path);
+                write(path, &totals)?;
+                let lines = totals.len();
+                return Ok(Some((totals, lines)));
+            }
+            Err(e) => anyhow::bail!("{:?} line {}: {}", path, i + 1, e),
+        };
+        totals
+            .entry((line.month, line.token))
+            .or_default()
+            .add(&line.counters);
+    }
+    Ok(Some((totals, lines.len())))
+}
+
+// 重写为每个 (月份, token) 一行
+fn write(path: &Path, totals: &Totals) -> anyhow::Result<()> {
+    let temp = path.with_extension("jsonl.tmp");
+    let mut file = std::io::BufWriter::new(fs::File::create(&temp)?);
+    for ((month, token), &counters) in totals {
+        let line = Line {
+            month: month.clone(),
+            token: token.clone(),
+            counters,
+        };
+        serde_json::to_writer(&mut file, &line)?;
+        file.write_all(b"\n")?;
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(&temp, path)?;
+    Ok(())
+}
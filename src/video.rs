@@ -0,0 +1,54 @@
+// 短视频 (MP4/WebM) 的识别和封面帧提取
+// 封面帧通过外部的 ffmpeg 提取：未安装 ffmpeg 时视频仍可上传和下载，只是没有缩略图
+use std::{
+    io::{Error, ErrorKind},
+    process::{Command, Stdio},
+};
+
+// MP4 (ISO BMFF) 常见的主品牌；HEIF/AVIF 使用同样的容器，但品牌不同
+const MP4_BRANDS: &[&[u8]] = &[
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"dash",
+];
+
+// 识别视频格式，返回对应的 MIME 类型；不是支持的视频时返回 None
+pub fn video_mime(data: &[u8]) -> Option<&'static str> {
+    if data.get(4..8) == Some(b"ftyp") {
+        let brand = data.get(8..12)?;
+        return MP4_BRANDS.contains(&brand).then_some("video/mp4");
+    }
+    // WebM：EBML 头，DocType 为 "webm" (Matroska 的子集)
+    if data.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+        let head = &data[..data.len().min(64)];
+        return head
+            .windows(4)
+            .any(|w| w == b"webm")
+            .then_some("video/webm");
+    }
+    None
+}
+
+// 调用 ffmpeg 提取第一帧，返回 PNG 数据
+// MP4 的索引 (moov) 可能位于文件末尾，无法从管道读取，因此先写入临时文件
+pub fn poster_frame(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let temp = std::env::temp_dir().join(format!("img-server-video-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&temp, data)?;
+    let output = Command::new("ffmpeg")
+        .args(["-v", "error", "-i"])
+        .arg(&temp)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png"])
+        .args(["-pix_fmt", "rgb24", "-"])
+        .stdin(Stdio::null())
+        .output();
+    let _ = std::fs::remove_file(&temp);
+    let output = output.map_err(|e| match e.kind() {
+        ErrorKind::NotFound => Error::new(ErrorKind::NotFound, "ffmpeg not found"),
+        _ => e,
+    })?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(Error::other(format!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}